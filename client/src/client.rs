@@ -0,0 +1,426 @@
+use crate::entrance;
+use crate::rpc::{CallError, Incoming, PendingCalls};
+use anyhow::Result;
+use bytes::Bytes;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use prost::Message;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use verse_common::prelude::*;
+use verse_proto::rpc::rpc_service::{RPC_ID_KEEP_ALIVE, RPC_ID_NEGOTIATE, RPC_ID_SWARM};
+use verse_proto::rpc::*;
+use verse_proto::service::RpcMethod;
+use verse_proto::swarm::swarm_node_service::{ExchangeRoutingInfo, Subscribe, Transfer};
+use verse_proto::swarm::*;
+use verse_session_id::{SessionId, SessionIdPair};
+use webrtc::api::APIBuilder;
+use webrtc::data_channel::data_channel_message::DataChannelMessage;
+use webrtc::data_channel::RTCDataChannel;
+use webrtc::ice_transport::ice_candidate::{RTCIceCandidate, RTCIceCandidateInit};
+use webrtc::ice_transport::ice_server::RTCIceServer;
+use webrtc::peer_connection::configuration::RTCConfiguration;
+use webrtc::peer_connection::peer_connection_state::RTCPeerConnectionState;
+use webrtc::peer_connection::RTCPeerConnection;
+
+// requests pushed from the hub waiting for recv(), newer ones are dropped when full
+const INCOMING_BUFFER_SIZE: usize = 256;
+
+#[derive(Clone, Debug)]
+pub struct ClientConfig {
+    /// ex: https://hub.example.com
+    pub entrance_url: String,
+    /// The world to join.
+    pub world_url: String,
+    /// STUN/TURN urls, not needed for a hub reachable directly.
+    pub ice_servers: Vec<String>,
+    /// Timeout of connecting and of each call.
+    pub timeout: Duration,
+    pub keep_alive_interval: Duration,
+}
+impl ClientConfig {
+    pub fn new(entrance_url: impl Into<String>, world_url: impl Into<String>) -> Self {
+        ClientConfig {
+            entrance_url: entrance_url.into(),
+            world_url: world_url.into(),
+            ice_servers: Vec::new(),
+            timeout: Duration::from_secs(10),
+            keep_alive_interval: Duration::from_secs(30),
+        }
+    }
+}
+
+/// A peer of hubserv, joined to a world over WebRTC like the browser does.
+pub struct Client {
+    pair: Arc<SessionIdPair>,
+    config: ClientConfig,
+    pc: Arc<RTCPeerConnection>,
+    conn: Arc<Connection>,
+    incoming: tokio::sync::Mutex<mpsc::Receiver<Incoming>>,
+}
+
+/// State shared with the callbacks of the data channel.
+struct Connection {
+    dc: Arc<RTCDataChannel>,
+    pending: Mutex<PendingCalls>,
+    assembler: Mutex<FragmentAssembler>,
+    incoming: mpsc::Sender<Incoming>,
+    codec: Mutex<RpcCodec>,
+    fragment_size: Mutex<Option<usize>>,
+    message_id: AtomicU32,
+}
+
+impl Client {
+    /// Enters `config.world_url` and waits for the data channel to open.
+    pub async fn connect(config: ClientConfig, pair: SessionIdPair) -> Result<Self> {
+        let pair = Arc::new(pair);
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(
+            api.new_peer_connection(RTCConfiguration {
+                ice_servers: vec![RTCIceServer {
+                    urls: config.ice_servers.clone(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            })
+            .await?,
+        );
+        let res =
+            tokio::time::timeout(config.timeout, Self::setup(config.clone(), pair, &pc)).await;
+        let res = match res {
+            Ok(res) => res,
+            Err(_) => Err(CallError::Timeout.into()),
+        };
+        if res.is_err() {
+            pc.close()
+                .await
+                .map_err(anyhow::Error::from)
+                .if_err_info(logmsg!("can't close pc"));
+        }
+        res
+    }
+
+    async fn setup(
+        config: ClientConfig,
+        pair: Arc<SessionIdPair>,
+        pc: &Arc<RTCPeerConnection>,
+    ) -> Result<Self> {
+        let dc = pc.create_data_channel("data", None).await?;
+        let (incoming_tx, incoming_rx) = mpsc::channel(INCOMING_BUFFER_SIZE);
+        let conn = Arc::new(Connection {
+            dc: dc.clone(),
+            pending: Mutex::new(PendingCalls::default()),
+            assembler: Mutex::new(FragmentAssembler::new(Default::default())),
+            incoming: incoming_tx,
+            codec: Mutex::new(RpcCodec::Zlib),
+            fragment_size: Mutex::new(None),
+            message_id: AtomicU32::new(0),
+        });
+
+        let (open_tx, open_rx) = oneshot::channel();
+        let open_tx = Mutex::new(Some(open_tx));
+        dc.on_open(Box::new(move || {
+            if let Some(tx) = open_tx.lock().take() {
+                let _ = tx.send(());
+            }
+            Box::pin(async {})
+        }));
+        {
+            let conn = Arc::downgrade(&conn);
+            dc.on_message(Box::new(move |m: DataChannelMessage| {
+                if let Some(conn) = conn.upgrade() {
+                    conn.on_message(&m.data)
+                        .if_err_info(logmsg!("bad packet from hub"));
+                }
+                Box::pin(async {})
+            }));
+        }
+        {
+            let conn = Arc::downgrade(&conn);
+            dc.on_close(Box::new(move || {
+                if let Some(conn) = conn.upgrade() {
+                    conn.pending.lock().clear();
+                }
+                Box::pin(async {})
+            }));
+        }
+        {
+            let conn = Arc::downgrade(&conn);
+            pc.on_peer_connection_state_change(Box::new(move |s: RTCPeerConnectionState| {
+                if s == RTCPeerConnectionState::Failed
+                    || s == RTCPeerConnectionState::Disconnected
+                    || s == RTCPeerConnectionState::Closed
+                {
+                    if let Some(conn) = conn.upgrade() {
+                        conn.pending.lock().clear();
+                    }
+                }
+                Box::pin(async {})
+            }));
+        }
+
+        // the hub accepts candidates after /enter
+        let (candidate_tx, candidate_rx) = mpsc::unbounded_channel();
+        pc.on_ice_candidate(Box::new(move |c: Option<RTCIceCandidate>| {
+            let _ = candidate_tx.send(c.and_then(|c| c.to_json().ok()));
+            Box::pin(async {})
+        }));
+
+        let offer = pc.create_offer(None).await?;
+        pc.set_local_description(offer.clone()).await?;
+        let answer = entrance::enter(&config.entrance_url, &pair, &config.world_url, offer).await?;
+        pc.set_remote_description(answer).await?;
+        tokio::spawn(send_candidates(
+            config.clone(),
+            Arc::downgrade(&pair),
+            candidate_rx,
+        ));
+
+        open_rx.await.map_err(|_| CallError::Closed)?;
+
+        let client = Client {
+            pair,
+            pc: pc.clone(),
+            conn,
+            incoming: tokio::sync::Mutex::new(incoming_rx),
+            config,
+        };
+        client.negotiate().await?;
+        tokio::spawn(keep_alive(
+            Arc::downgrade(&client.conn),
+            client.config.keep_alive_interval,
+        ));
+        Ok(client)
+    }
+
+    async fn negotiate(&self) -> Result<()> {
+        let res = self
+            .call(
+                RPC_ID_NEGOTIATE,
+                RpcCapabilities {
+                    fragmentation: true,
+                    max_fragment_size: DEFAULT_MAX_FRAGMENT_SIZE as u32,
+                    codecs: Codec::ALL
+                        .iter()
+                        .filter(|v| v.is_available())
+                        .map(|v| RpcCodec::from_codec(*v) as i32)
+                        .collect(),
+                }
+                .encode_to_vec(),
+            )
+            .await?;
+        let res = RpcCapabilities::decode(res.as_slice())?;
+        *self.conn.fragment_size.lock() = res.get_fragment_size();
+        if let Some(codec) = res.select_codec() {
+            *self.conn.codec.lock() = codec;
+        }
+        Ok(())
+    }
+
+    pub fn session_id(&self) -> SessionId {
+        self.pair.get_id()
+    }
+
+    /// Calls the rpc of the hub and returns the param of the response.
+    pub async fn call(&self, rpc_id: u32, param: Vec<u8>) -> Result<Vec<u8>> {
+        self.request(rpc_id, param).await
+    }
+
+    /// Calls the rpc carried in a SwarmPacket.
+    pub async fn call_swarm(&self, rpc_id: u32, param: Vec<u8>) -> Result<Vec<u8>> {
+        let param = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest { rpc_id, param })),
+        }
+        .encode_to_vec();
+        self.request(RPC_ID_SWARM, param).await
+    }
+
+    /// Calls a method of SwarmNodeService.
+    pub async fn call_method<M: RpcMethod>(&self, req: M::Request) -> Result<M::Response> {
+        let res = self.call_swarm(M::RPC_ID, req.encode_to_vec()).await?;
+        Ok(M::Response::decode(res.as_slice())?)
+    }
+
+    async fn request(&self, rpc_id: u32, param: Vec<u8>) -> Result<Vec<u8>> {
+        let (request_id, rx) = self.conn.pending.lock().push();
+        if let Err(e) = self.conn.send_request(rpc_id, request_id, param).await {
+            self.conn.pending.lock().remove(request_id);
+            return Err(e);
+        }
+        match tokio::time::timeout(self.config.timeout, rx).await {
+            Ok(Ok(res)) => Ok(res?),
+            Ok(Err(_)) => Err(CallError::Closed.into()),
+            Err(_) => {
+                self.conn.pending.lock().remove(request_id);
+                Err(CallError::Timeout.into())
+            }
+        }
+    }
+
+    /// Returns the RoutingInfo of the hub.
+    pub async fn exchange_routing_info(&self, ri: RoutingInfo) -> Result<RoutingInfo> {
+        self.call_method::<ExchangeRoutingInfo>(ri).await
+    }
+
+    /// Sends a signed `payload` to the peer of `to`.
+    pub async fn transfer(
+        &self,
+        to: &SessionId,
+        payload: Vec<u8>,
+        ttl: u32,
+    ) -> Result<TransferResponse> {
+        let to_session_id = to.to_vec();
+        let ss = self.pair.sign(vec![&to_session_id, &payload])?;
+        self.call_method::<Transfer>(TransferRequest {
+            to_session_id,
+            signature: Some(SignatureSet {
+                from_session_id: self.session_id().to_vec(),
+                signature: ss.signature.to_vec(),
+                salt: ss.salt.to_vec(),
+            }),
+            payload,
+            ttl,
+        })
+        .await
+    }
+
+    /// Starts or stops receiving the MembershipEvents of the world.
+    pub async fn subscribe(&self, subscribe: bool) -> Result<SubscribeResponse> {
+        self.call_method::<Subscribe>(SubscribeRequest { subscribe })
+            .await
+    }
+
+    /// The next request pushed from the hub. None after the connection is closed.
+    pub async fn recv(&self) -> Option<Incoming> {
+        self.incoming.lock().await.recv().await
+    }
+
+    pub async fn close(&self) -> Result<()> {
+        self.conn.pending.lock().clear();
+        self.conn.dc.close().await?;
+        self.pc.close().await?;
+        Ok(())
+    }
+}
+
+impl Connection {
+    async fn send_request(&self, rpc_id: u32, request_id: u32, param: Vec<u8>) -> Result<()> {
+        let mut packet = RpcPacket::default();
+        packet.set_request(RpcRequest {
+            rpc_id,
+            param,
+            request_id,
+        });
+        let codec = *self.codec.lock();
+        let data = packet.encode_packet_as(codec);
+        let fragment_size = *self.fragment_size.lock();
+        let fragments = fragment_size.and_then(|size| {
+            split_packet(&data, self.message_id.fetch_add(1, Ordering::Relaxed), size)
+        });
+        for v in fragments.unwrap_or_else(|| vec![data]) {
+            self.dc.send(&Bytes::from(v)).await?;
+        }
+        Ok(())
+    }
+
+    fn on_message(&self, data: &[u8]) -> Result<()> {
+        let mut packet = RpcPacket::decode_packet(data)?;
+        if let Some(rpc_packet::Data::Fragment(fragment)) = packet.data {
+            let Some(data) = self.assembler.lock().push(fragment, get_now_msec())? else {
+                return Ok(());
+            };
+            packet = RpcPacket::decode_packet(&data)?;
+        }
+        match packet.data {
+            // requests pushed from the hub
+            Some(rpc_packet::Data::Response(res))
+                if res.request_id == 0 && res.rpc_id == RPC_ID_SWARM =>
+            {
+                self.on_swarm_packet(SwarmPacket::decode(res.param.as_slice())?);
+            }
+            Some(rpc_packet::Data::Response(res)) if res.request_id != 0 => {
+                let param = if res.rpc_id == RPC_ID_SWARM {
+                    match SwarmPacket::decode(res.param.as_slice())?.data {
+                        Some(swarm_packet::Data::Response(res)) => res.param,
+                        _ => return Err(anyhow::anyhow!("not a swarm response")),
+                    }
+                } else {
+                    res.param
+                };
+                if !self.pending.lock().resolve(res.request_id, Ok(param)) {
+                    debug!("response to no call: {}", res.rpc_id);
+                }
+            }
+            Some(rpc_packet::Data::Error(e)) => {
+                let resolved = self.pending.lock().resolve(e.request_id, Err((&e).into()));
+                if !resolved {
+                    debug!("rpc error: {:?}", e);
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn on_swarm_packet(&self, packet: SwarmPacket) {
+        let Some(swarm_packet::Data::Request(req)) = packet.data else {
+            return;
+        };
+        let rpc_id = req.rpc_id;
+        match Incoming::try_from(req) {
+            Ok(v) => {
+                if self.incoming.try_send(v).is_err() {
+                    debug!("incoming request dropped: {}", rpc_id);
+                }
+            }
+            Err(e) => debug!("bad request from hub: {} {}", rpc_id, e),
+        }
+    }
+}
+
+async fn send_candidates(
+    config: ClientConfig,
+    pair: Weak<SessionIdPair>,
+    mut rx: mpsc::UnboundedReceiver<Option<RTCIceCandidateInit>>,
+) {
+    // None after gathering is complete
+    while let Some(Some(candidate)) = rx.recv().await {
+        let Some(pair) = pair.upgrade() else {
+            return;
+        };
+        entrance::candidate(&config.entrance_url, &pair, &config.world_url, candidate)
+            .await
+            .if_err_info(logmsg!("can't send candidate"));
+    }
+}
+
+async fn keep_alive(conn: Weak<Connection>, interval: Duration) {
+    loop {
+        tokio::time::sleep(interval).await;
+        let Some(conn) = conn.upgrade() else {
+            return;
+        };
+        if let Err(e) = conn.send_request(RPC_ID_KEEP_ALIVE, 0, Vec::new()).await {
+            debug!("keep alive stopped: {}", e);
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connect_error() {
+        let pair = verse_session_id::new_session_id_pair().unwrap();
+        let config = ClientConfig {
+            timeout: Duration::from_secs(3),
+            ..ClientConfig::new("http://127.0.0.1:1", "https://example.com/")
+        };
+        assert!(Client::connect(config, pair).await.is_err());
+    }
+}
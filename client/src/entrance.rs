@@ -0,0 +1,123 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use verse_common::http::post_string;
+use verse_common::prelude::url_join;
+use verse_session_id::{SessionIdPair, SignatureSet};
+use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
+use webrtc::peer_connection::sdp::session_description::RTCSessionDescription;
+
+/// Body of /enter and /candidate, `payload` is the signed JSON.
+#[derive(Serialize, Deserialize)]
+pub struct SignedRequest {
+    #[serde(rename = "sessionId")]
+    pub session_id: String,
+    pub sign: SignatureSet,
+    pub payload: String,
+}
+impl SignedRequest {
+    pub fn new<T: Serialize>(pair: &SessionIdPair, payload: &T) -> Result<Self> {
+        let payload = serde_json::to_string(payload)?;
+        let sign = pair.sign(vec![payload.as_bytes()])?;
+        Ok(SignedRequest {
+            session_id: pair.get_id().to_string(),
+            sign,
+            payload,
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EnterRequestPayload {
+    pub url: String,
+    pub sdp: RTCSessionDescription,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CandidateRequestPayload {
+    pub url: String,
+    pub sdp: RTCIceCandidateInit,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct EnterResponse {
+    pub sdp: RTCSessionDescription,
+}
+
+/// `entrance_url`: ex. https://hub.example.com
+pub async fn enter(
+    entrance_url: &str,
+    pair: &SessionIdPair,
+    world_url: &str,
+    offer: RTCSessionDescription,
+) -> Result<RTCSessionDescription> {
+    let req = SignedRequest::new(
+        pair,
+        &EnterRequestPayload {
+            url: world_url.to_string(),
+            sdp: offer,
+        },
+    )?;
+    let res = post_json(entrance_url, "/enter", &req).await?;
+    let res: EnterResponse = serde_json::from_str(&res)?;
+    Ok(res.sdp)
+}
+
+pub async fn candidate(
+    entrance_url: &str,
+    pair: &SessionIdPair,
+    world_url: &str,
+    candidate: RTCIceCandidateInit,
+) -> Result<()> {
+    let req = SignedRequest::new(
+        pair,
+        &CandidateRequestPayload {
+            url: world_url.to_string(),
+            sdp: candidate,
+        },
+    )?;
+    post_json(entrance_url, "/candidate", &req).await?;
+    Ok(())
+}
+
+async fn post_json(entrance_url: &str, path: &str, req: &SignedRequest) -> Result<String> {
+    post_string(
+        url_join(entrance_url, path),
+        serde_json::to_string(req)?,
+        "application/json".to_string(),
+    )
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use verse_session_id::{new_session_id_pair, SessionId};
+
+    #[test]
+    fn test_signed_request() {
+        let pair = new_session_id_pair().unwrap();
+        let req = SignedRequest::new(
+            &pair,
+            &EnterRequestPayload {
+                url: "https://example.com/".to_string(),
+                sdp: Default::default(),
+            },
+        )
+        .unwrap();
+        let json = serde_json::to_string(&req).unwrap();
+
+        let req: SignedRequest = serde_json::from_str(&json).unwrap();
+        let session_id: SessionId = req.session_id.parse().unwrap();
+        assert_eq!(session_id, pair.get_id());
+        session_id
+            .verify(vec![req.payload.as_bytes()], &req.sign)
+            .unwrap();
+        let payload: serde_json::Value = serde_json::from_str(&req.payload).unwrap();
+        assert_eq!(payload["url"], "https://example.com/");
+
+        let other = new_session_id_pair().unwrap().get_id();
+        assert!(other
+            .verify(vec![req.payload.as_bytes()], &req.sign)
+            .is_err());
+    }
+}
@@ -0,0 +1,8 @@
+//! Client of hubserv speaking the same protocol as the browser:
+//! signed /enter and /candidate, then RpcPacket over a WebRTC data channel.
+mod client;
+pub mod entrance;
+mod rpc;
+
+pub use client::{Client, ClientConfig};
+pub use rpc::{CallError, Incoming};
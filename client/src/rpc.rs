@@ -0,0 +1,158 @@
+use prost::Message;
+use std::collections::HashMap;
+use thiserror::Error;
+use tokio::sync::oneshot;
+use verse_proto::rpc::{RpcError, RpcErrorCode};
+use verse_proto::swarm::swarm_hub_service::{
+    RPC_ID_BECOME_GATEWAY, RPC_ID_MEMBERSHIP_EVENTS, RPC_ID_MERGE_SUGGESTION,
+};
+use verse_proto::swarm::swarm_node_service::{RPC_ID_MULTICAST, RPC_ID_TRANSFER};
+use verse_proto::swarm::*;
+
+#[derive(Error, Debug)]
+pub enum CallError {
+    #[error("rpc error: {code:?} {message}")]
+    Rpc { code: RpcErrorCode, message: String },
+    #[error("timeout")]
+    Timeout,
+    #[error("connection closed")]
+    Closed,
+}
+impl From<&RpcError> for CallError {
+    fn from(e: &RpcError) -> Self {
+        CallError::Rpc {
+            code: e.code(),
+            message: e.message.clone(),
+        }
+    }
+}
+
+type Reply = Result<Vec<u8>, CallError>;
+
+/// Calls waiting for the response, by the request id sent with them.
+#[derive(Default)]
+pub(crate) struct PendingCalls {
+    last_id: u32,
+    calls: HashMap<u32, oneshot::Sender<Reply>>,
+}
+impl PendingCalls {
+    /// Returns the request id to send, never 0.
+    pub(crate) fn push(&mut self) -> (u32, oneshot::Receiver<Reply>) {
+        loop {
+            self.last_id = self.last_id.wrapping_add(1);
+            if self.last_id != 0 && !self.calls.contains_key(&self.last_id) {
+                break;
+            }
+        }
+        let (tx, rx) = oneshot::channel();
+        self.calls.insert(self.last_id, tx);
+        (self.last_id, rx)
+    }
+    /// False if no call is waiting for it.
+    pub(crate) fn resolve(&mut self, request_id: u32, reply: Reply) -> bool {
+        self.calls
+            .remove(&request_id)
+            .is_some_and(|tx| tx.send(reply).is_ok())
+    }
+    /// Forgets the call that failed to be sent or timed out.
+    pub(crate) fn remove(&mut self, request_id: u32) {
+        self.calls.remove(&request_id);
+    }
+    /// Fails all the calls with CallError::Closed.
+    pub(crate) fn clear(&mut self) {
+        self.calls.clear();
+    }
+}
+
+/// Requests pushed from the hub.
+#[derive(Debug)]
+pub enum Incoming {
+    Transfer(TransferRequest),
+    Multicast(MulticastRequest),
+    MembershipEvents(MembershipEvents),
+    BecomeGateway(BecomeGatewayRequest),
+    MergeSuggestion(MergeSuggestion),
+    /// Ids unknown to this client.
+    Other(SwarmRequest),
+}
+impl TryFrom<SwarmRequest> for Incoming {
+    type Error = prost::DecodeError;
+    fn try_from(req: SwarmRequest) -> Result<Self, Self::Error> {
+        let param = req.param.as_slice();
+        Ok(match req.rpc_id {
+            RPC_ID_TRANSFER => Incoming::Transfer(TransferRequest::decode(param)?),
+            RPC_ID_MULTICAST => Incoming::Multicast(MulticastRequest::decode(param)?),
+            RPC_ID_MEMBERSHIP_EVENTS => {
+                Incoming::MembershipEvents(MembershipEvents::decode(param)?)
+            }
+            RPC_ID_BECOME_GATEWAY => Incoming::BecomeGateway(BecomeGatewayRequest::decode(param)?),
+            RPC_ID_MERGE_SUGGESTION => Incoming::MergeSuggestion(MergeSuggestion::decode(param)?),
+            _ => Incoming::Other(req),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_pending_calls() {
+        let mut p = PendingCalls::default();
+        let (a_id, a) = p.push();
+        let (b_id, b) = p.push();
+        assert_ne!(a_id, b_id);
+
+        // answered out of order
+        assert!(p.resolve(b_id, Ok(vec![2])));
+        assert!(!p.resolve(b_id, Ok(vec![3])));
+        assert!(!p.resolve(100, Ok(vec![])));
+        assert!(p.resolve(a_id, Err(CallError::Timeout)));
+        assert_eq!(b.await.unwrap().unwrap(), vec![2]);
+        assert!(matches!(a.await.unwrap(), Err(CallError::Timeout)));
+
+        // the late response of a call that timed out does not answer the next one
+        let (timed_out, _) = p.push();
+        p.remove(timed_out);
+        let (c_id, c) = p.push();
+        assert!(!p.resolve(timed_out, Ok(vec![4])));
+        assert!(p.resolve(c_id, Ok(vec![5])));
+        assert_eq!(c.await.unwrap().unwrap(), vec![5]);
+        assert!(p.calls.is_empty());
+
+        // 0 is skipped
+        p.last_id = u32::MAX;
+        let (id, _) = p.push();
+        assert_eq!(id, 1);
+
+        let (_, d) = p.push();
+        p.clear();
+        assert!(d.await.is_err());
+    }
+
+    #[test]
+    fn test_incoming() {
+        let req = SwarmRequest {
+            rpc_id: RPC_ID_TRANSFER,
+            param: TransferRequest {
+                ttl: 3,
+                ..Default::default()
+            }
+            .encode_to_vec(),
+        };
+        match Incoming::try_from(req).unwrap() {
+            Incoming::Transfer(v) => assert_eq!(v.ttl, 3),
+            v => panic!("{:?}", v),
+        }
+        let req = SwarmRequest {
+            rpc_id: 100,
+            param: vec![1],
+        };
+        assert!(matches!(Incoming::try_from(req), Ok(Incoming::Other(_))));
+        let req = SwarmRequest {
+            rpc_id: RPC_ID_MERGE_SUGGESTION,
+            param: vec![0xff],
+        };
+        assert!(Incoming::try_from(req).is_err());
+    }
+}
@@ -25,6 +25,10 @@ impl Client {
     pub fn set_node_list(&self, data: NodeListData) {
         *self.node_list.lock() = Some(Arc::new(data.nodes));
     }
+    /// Assigns nothing until a list is set again.
+    pub fn clear_node_list(&self) {
+        *self.node_list.lock() = None;
+    }
     pub fn get_node_list(&self) -> Option<Arc<Vec<NodeListNode>>> {
         self.node_list.lock().as_ref().cloned()
     }
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::IpAddr;
+
+mod cloudflare;
+pub use cloudflare::{CfAuthInfo, CfDnsProvider};
+mod rfc2136;
+pub use rfc2136::{ResponseError, Rfc2136DnsProvider, TsigKey};
+
+/// A record for IPv4, AAAA for IPv6.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct DnsRecord {
+    /// Opaque to callers, only passed back to the provider.
+    pub id: String,
+    /// FQDN without the trailing dot.
+    pub name: String,
+    pub ip: IpAddr,
+}
+
+/// Address records of a zone.
+#[async_trait]
+pub trait DnsProvider: Send + Sync {
+    /// None if the zone of `domain` is not managed by this provider.
+    async fn get_zone_id(&self, domain: &str) -> Result<Option<String>>;
+    /// A and AAAA records of the zone.
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>>;
+    /// `proxy`: serve through the provider's proxy, ignored by providers without one.
+    async fn create_record(&self, zone_id: &str, name: &str, ip: IpAddr, proxy: bool)
+        -> Result<()>;
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+        ip: IpAddr,
+        proxy: bool,
+    ) -> Result<()>;
+    async fn delete_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()>;
+}
+
+/// Points `name` to `ip`, the zone is the parent domain of `name`.
+/// Records of the other address family are left as they are.
+pub async fn set_record(
+    provider: &dyn DnsProvider,
+    name: &str,
+    ip: IpAddr,
+    proxy: bool,
+) -> Result<()> {
+    let (_, domain) = name.split_once('.').unwrap_or(("", name));
+    let Some(zone_id) = provider.get_zone_id(domain).await? else {
+        anyhow::bail!("unsupported host name: {}", domain);
+    };
+    let records = provider.list_records(&zone_id).await?;
+    match records
+        .iter()
+        .find(|v| v.name == name && v.ip.is_ipv4() == ip.is_ipv4())
+    {
+        Some(v) if v.ip == ip => Ok(()),
+        Some(v) => provider.update_record(&zone_id, v, ip, proxy).await,
+        None => provider.create_record(&zone_id, name, ip, proxy).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fake::MemoryDnsProvider;
+
+    #[tokio::test]
+    async fn test_set_record() {
+        let dns = MemoryDnsProvider::new(&[("example.com", "z1")]);
+        let ip4: IpAddr = "1.2.3.4".parse().unwrap();
+        let ip6: IpAddr = "2001:db8::1".parse().unwrap();
+        set_record(&dns, "hub.example.com", ip4, true)
+            .await
+            .unwrap();
+        set_record(&dns, "hub.example.com", ip6, true)
+            .await
+            .unwrap();
+        set_record(&dns, "hub.example.com", "1.2.3.5".parse().unwrap(), true)
+            .await
+            .unwrap();
+        assert_eq!(
+            dns.get_names(),
+            vec![
+                ("hub.example.com".to_string(), "1.2.3.5".to_string()),
+                ("hub.example.com".to_string(), "2001:db8::1".to_string()),
+            ]
+        );
+        assert!(set_record(&dns, "hub.example.net", ip4, true)
+            .await
+            .is_err());
+    }
+}
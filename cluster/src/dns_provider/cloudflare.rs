@@ -0,0 +1,175 @@
+use super::{DnsProvider, DnsRecord};
+use anyhow::Result;
+use async_trait::async_trait;
+use cloudflare::endpoints::{dns, zone};
+use cloudflare::framework::{
+    async_api::{ApiClient, Client},
+    auth::Credentials,
+    Environment, HttpApiClientConfig,
+};
+use std::net::IpAddr;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CfAuthInfo {
+    /// API token scoped to Zone:Read and DNS:Edit of the zones.
+    ApiToken(String),
+    /// Global API key of the account.
+    ApiKey { email: String, api_key: String },
+}
+
+/// Cloudflare, a client is created for each call.
+pub struct CfDnsProvider {
+    auth: CfAuthInfo,
+}
+impl CfDnsProvider {
+    pub fn new(auth: CfAuthInfo) -> Self {
+        CfDnsProvider { auth }
+    }
+}
+#[async_trait]
+impl DnsProvider for CfDnsProvider {
+    async fn get_zone_id(&self, domain: &str) -> Result<Option<String>> {
+        get_zone_id(&create_client(&self.auth)?, domain).await
+    }
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>> {
+        list_records(&create_client(&self.auth)?, zone_id).await
+    }
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        name: &str,
+        ip: IpAddr,
+        proxy: bool,
+    ) -> Result<()> {
+        create_client(&self.auth)?
+            .request(&dns::CreateDnsRecord {
+                zone_identifier: zone_id,
+                params: dns::CreateDnsRecordParams {
+                    proxied: Some(proxy),
+                    name,
+                    content: to_content(ip),
+                    ttl: None,
+                    priority: None,
+                },
+            })
+            .await?;
+        Ok(())
+    }
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+        ip: IpAddr,
+        proxy: bool,
+    ) -> Result<()> {
+        create_client(&self.auth)?
+            .request(&dns::UpdateDnsRecord {
+                zone_identifier: zone_id,
+                identifier: &record.id,
+                params: dns::UpdateDnsRecordParams {
+                    proxied: Some(proxy),
+                    name: &record.name,
+                    content: to_content(ip),
+                    ttl: None,
+                },
+            })
+            .await?;
+        Ok(())
+    }
+    async fn delete_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()> {
+        create_client(&self.auth)?
+            .request(&dns::DeleteDnsRecord {
+                zone_identifier: zone_id,
+                identifier: &record.id,
+            })
+            .await?;
+        Ok(())
+    }
+}
+
+pub fn create_client(cf_auth: &CfAuthInfo) -> Result<Client> {
+    let credentials = match cf_auth {
+        CfAuthInfo::ApiToken(token) => Credentials::UserAuthToken {
+            token: token.clone(),
+        },
+        CfAuthInfo::ApiKey { email, api_key } => Credentials::UserAuthKey {
+            email: email.clone(),
+            key: api_key.clone(),
+        },
+    };
+    Client::new(
+        credentials,
+        HttpApiClientConfig::default(),
+        Environment::Production,
+    )
+}
+
+fn to_content(ip: IpAddr) -> dns::DnsContent {
+    match ip {
+        IpAddr::V4(content) => dns::DnsContent::A { content },
+        IpAddr::V6(content) => dns::DnsContent::AAAA { content },
+    }
+}
+
+pub async fn list_records(client: &Client, zone_id: &str) -> Result<Vec<DnsRecord>> {
+    let res: Vec<dns::DnsRecord> = client
+        .request(&dns::ListDnsRecords {
+            zone_identifier: zone_id,
+            params: dns::ListDnsRecordsParams {
+                per_page: Some(50000),
+                ..Default::default()
+            },
+        })
+        .await?
+        .result;
+    Ok(res
+        .into_iter()
+        .filter_map(|v| {
+            let ip = match v.content {
+                dns::DnsContent::A { content } => IpAddr::V4(content),
+                dns::DnsContent::AAAA { content } => IpAddr::V6(content),
+                _ => return None,
+            };
+            Some(DnsRecord {
+                id: v.id,
+                name: v.name,
+                ip,
+            })
+        })
+        .collect())
+}
+pub async fn get_zone_id(client: &Client, domain: &str) -> Result<Option<String>> {
+    let res = client
+        .request(&zone::ListZones {
+            params: zone::ListZonesParams {
+                name: Some(domain.to_owned()),
+                ..Default::default()
+            },
+        })
+        .await?;
+    if res.result.len() != 1 {
+        return Ok(None);
+    }
+    Ok(Some(res.result[0].id.to_owned()))
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    #[tokio::test]
+    #[ignore = "needs CLOUDFLARE_API_TOKEN"]
+    async fn test_list_records() {
+        let cf_client = create_client(&CfAuthInfo::ApiToken(
+            env::var("CLOUDFLARE_API_TOKEN").unwrap(),
+        ))
+        .unwrap();
+        let cf_zone_id = get_zone_id(&cf_client, "verseengine.cloud")
+            .await
+            .unwrap()
+            .unwrap();
+
+        let res = list_records(&cf_client, &cf_zone_id).await.unwrap();
+        assert!(!res.is_empty());
+        println!("{:?}", res);
+    }
+}
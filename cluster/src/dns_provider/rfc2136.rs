@@ -0,0 +1,619 @@
+use super::{DnsProvider, DnsRecord};
+use anyhow::{anyhow, bail, Result};
+use async_trait::async_trait;
+use std::net::{IpAddr, SocketAddr};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+use tokio::time::timeout;
+use trust_dns_proto::op::{Message, MessageType, OpCode, Query, ResponseCode};
+use trust_dns_proto::rr::dnssec::rdata::tsig::{
+    make_tsig_record, signed_bitmessage_to_buf, TsigAlgorithm, TSIG,
+};
+use trust_dns_proto::rr::dnssec::rdata::DNSSECRData;
+use trust_dns_proto::rr::{DNSClass, Name, RData, Record, RecordType};
+use trust_dns_proto::serialize::binary::BinEncoder;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Allowed clock skew of TSIG.
+const FUDGE: u16 = 300;
+const TTL: u32 = 300;
+
+#[derive(Error, Debug)]
+#[error("{server}: {code}")]
+pub struct ResponseError {
+    pub server: SocketAddr,
+    pub code: ResponseCode,
+}
+
+/// Shared secret of TSIG (RFC 8945), ex. a key of `tsig-keygen` for BIND.
+#[derive(Clone)]
+pub struct TsigKey {
+    name: Name,
+    algorithm: TsigAlgorithm,
+    secret: Vec<u8>,
+}
+impl TsigKey {
+    /// `algorithm`: hmac-sha256, hmac-sha384 or hmac-sha512
+    pub fn new(name: &str, algorithm: &str, secret: Vec<u8>) -> Result<Self> {
+        let algorithm = TsigAlgorithm::from_name(Name::from_ascii(algorithm)?);
+        if !algorithm.supported() {
+            bail!("unsupported tsig algorithm: {:?}", algorithm);
+        }
+        Ok(TsigKey {
+            name: Name::from_ascii(name)?,
+            algorithm,
+            secret,
+        })
+    }
+
+    /// Appends the TSIG record, returns its MAC.
+    /// `previous_mac`: MAC of the request when signing a response.
+    fn sign(&self, msg: &mut Message, previous_mac: Option<&[u8]>) -> Result<Vec<u8>> {
+        self.sign_at(msg, previous_mac, now_secs())
+    }
+    fn sign_at(&self, msg: &mut Message, previous_mac: Option<&[u8]>, now: u64) -> Result<Vec<u8>> {
+        let pre_tsig = TSIG::new(
+            self.algorithm.clone(),
+            now,
+            FUDGE,
+            Vec::new(),
+            msg.id(),
+            0,
+            Vec::new(),
+        );
+        // message_tbs compresses names after previous_mac, so the wire format is used
+        let mut tbs = Vec::new();
+        if let Some(previous_mac) = previous_mac {
+            tbs.extend((previous_mac.len() as u16).to_be_bytes());
+            tbs.extend(previous_mac);
+        }
+        tbs.extend(msg.to_vec()?);
+        let mut variables = Vec::new();
+        pre_tsig.emit_tsig_for_mac(&mut BinEncoder::new(&mut variables), &self.name)?;
+        tbs.extend(variables);
+        let mac = self.algorithm.mac_data(&self.secret, &tbs)?;
+        msg.add_tsig(make_tsig_record(
+            self.name.clone(),
+            pre_tsig.set_mac(mac.clone()),
+        ));
+        Ok(mac)
+    }
+    /// Verifies the TSIG of `bytes` chained to `previous_mac`, returns its MAC.
+    /// `first`: false for the following messages of a zone transfer.
+    fn verify(&self, bytes: &[u8], previous_mac: Option<&[u8]>, first: bool) -> Result<Vec<u8>> {
+        self.verify_at(bytes, previous_mac, first, now_secs())
+    }
+    fn verify_at(
+        &self,
+        bytes: &[u8],
+        previous_mac: Option<&[u8]>,
+        first: bool,
+        now: u64,
+    ) -> Result<Vec<u8>> {
+        let (tbs, record) = signed_bitmessage_to_buf(previous_mac, bytes, first)?;
+        let Some(RData::DNSSEC(DNSSECRData::TSIG(tsig))) = record.data() else {
+            bail!("not signed");
+        };
+        if record.name() != &self.name || tsig.algorithm() != &self.algorithm {
+            bail!("signed by an unknown key: {}", record.name());
+        }
+        self.algorithm
+            .verify_mac(&self.secret, &tbs, tsig.mac())
+            .map_err(|_| anyhow!("bad signature"))?;
+        if now.abs_diff(tsig.time()) > tsig.fudge() as u64 {
+            bail!("bad time of signature");
+        }
+        Ok(tsig.mac().to_vec())
+    }
+}
+
+/// Dynamic updates (RFC 2136) of BIND, Knot or PowerDNS, signed with TSIG if a key is given.
+/// Zones are listed by zone transfers (AXFR), which needs to be allowed for the key.
+/// Zone ids are the zone names, record ids are "<name> <ip>".
+pub struct Rfc2136DnsProvider {
+    server: SocketAddr,
+    key: Option<TsigKey>,
+}
+impl Rfc2136DnsProvider {
+    pub fn new(server: SocketAddr, key: Option<TsigKey>) -> Self {
+        Rfc2136DnsProvider { server, key }
+    }
+
+    fn new_message(&self, op_code: OpCode, query: Query) -> Message {
+        let mut msg = Message::new();
+        msg.set_id(new_id())
+            .set_message_type(MessageType::Query)
+            .set_op_code(op_code)
+            .add_query(query);
+        msg
+    }
+    fn sign(&self, msg: &mut Message) -> Result<Option<Vec<u8>>> {
+        self.key.as_ref().map(|key| key.sign(msg, None)).transpose()
+    }
+    /// Fails unless the response is signed with the key and NOERROR.
+    /// The signature is checked first, so a forged NXDOMAIN or REFUSED is not a ResponseError.
+    fn check_response(
+        &self,
+        bytes: &[u8],
+        previous_mac: Option<&[u8]>,
+        first: bool,
+    ) -> Result<(Message, Option<Vec<u8>>)> {
+        let res = Message::from_vec(bytes)?;
+        let mac = match &self.key {
+            Some(key) => Some(key.verify(bytes, previous_mac, first)?),
+            None => None,
+        };
+        if res.response_code() != ResponseCode::NoError {
+            return Err(ResponseError {
+                server: self.server,
+                code: res.response_code(),
+            }
+            .into());
+        }
+        Ok((res, mac))
+    }
+
+    async fn exchange(&self, mut msg: Message) -> Result<Message> {
+        let mac = self.sign(&mut msg)?;
+        let bind_addr = if self.server.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(bind_addr).await?;
+        socket.connect(self.server).await?;
+        socket.send(&msg.to_vec()?).await?;
+        let mut buf = vec![0u8; 4096];
+        loop {
+            let n = timeout(TIMEOUT, socket.recv(&mut buf)).await??;
+            // ignore stray packets
+            if n < 2 || buf[..2] != msg.id().to_be_bytes() {
+                continue;
+            }
+            return Ok(self.check_response(&buf[..n], mac.as_deref(), true)?.0);
+        }
+    }
+
+    /// Answer records of the transfer, SOA records at both ends are included.
+    async fn transfer(&self, zone: &Name) -> Result<Vec<Record>> {
+        let mut msg = self.new_message(OpCode::Query, Query::query(zone.clone(), RecordType::AXFR));
+        let mut mac = self.sign(&mut msg)?;
+        let mut stream = timeout(TIMEOUT, TcpStream::connect(self.server)).await??;
+        let bytes = msg.to_vec()?;
+        stream.write_u16(bytes.len() as u16).await?;
+        stream.write_all(&bytes).await?;
+
+        let mut records = Vec::new();
+        let mut soa_count = 0;
+        let mut first = true;
+        while soa_count < 2 {
+            let len = timeout(TIMEOUT, stream.read_u16()).await?? as usize;
+            let mut buf = vec![0u8; len];
+            timeout(TIMEOUT, stream.read_exact(&mut buf)).await??;
+            let (res, res_mac) = self.check_response(&buf, mac.as_deref(), first)?;
+            mac = res_mac;
+            first = false;
+            for v in res.answers() {
+                if v.rr_type() == RecordType::SOA {
+                    soa_count += 1;
+                }
+                records.push(v.clone());
+            }
+            if res.answers().is_empty() {
+                bail!("{}: empty transfer of {}", self.server, zone);
+            }
+        }
+        Ok(records)
+    }
+
+    async fn update(&self, zone_id: &str, updates: Vec<Record>) -> Result<()> {
+        let mut query = Query::query(Name::from_ascii(zone_id)?, RecordType::SOA);
+        query.set_query_class(DNSClass::IN);
+        let mut msg = self.new_message(OpCode::Update, query);
+        // the update section is the authority section
+        msg.add_name_servers(updates);
+        self.exchange(msg).await?;
+        Ok(())
+    }
+}
+#[async_trait]
+impl DnsProvider for Rfc2136DnsProvider {
+    async fn get_zone_id(&self, domain: &str) -> Result<Option<String>> {
+        let name = Name::from_ascii(domain)?;
+        let msg = self.new_message(OpCode::Query, Query::query(name.clone(), RecordType::SOA));
+        let res = match self.exchange(msg).await {
+            Ok(v) => v,
+            Err(e) => match e.downcast_ref::<ResponseError>().map(|v| v.code) {
+                Some(ResponseCode::NXDomain | ResponseCode::Refused) => return Ok(None),
+                _ => return Err(e),
+            },
+        };
+        let found = res
+            .answers()
+            .iter()
+            .any(|v| v.rr_type() == RecordType::SOA && v.name() == &name);
+        Ok(found.then(|| domain.trim_end_matches('.').to_string()))
+    }
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>> {
+        let records = self.transfer(&Name::from_ascii(zone_id)?).await?;
+        Ok(records
+            .iter()
+            .filter_map(|v| {
+                let ip = match v.data()? {
+                    RData::A(ip) => IpAddr::V4(*ip),
+                    RData::AAAA(ip) => IpAddr::V6(*ip),
+                    _ => return None,
+                };
+                let name = v.name().to_string().trim_end_matches('.').to_string();
+                Some(DnsRecord {
+                    id: format!("{} {}", name, ip),
+                    name,
+                    ip,
+                })
+            })
+            .collect())
+    }
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        name: &str,
+        ip: IpAddr,
+        _proxy: bool,
+    ) -> Result<()> {
+        self.update(zone_id, vec![add_record(name, ip)?]).await
+    }
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+        ip: IpAddr,
+        _proxy: bool,
+    ) -> Result<()> {
+        self.update(
+            zone_id,
+            vec![
+                delete_record(&record.name, record.ip)?,
+                add_record(&record.name, ip)?,
+            ],
+        )
+        .await
+    }
+    async fn delete_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()> {
+        self.update(zone_id, vec![delete_record(&record.name, record.ip)?])
+            .await
+    }
+}
+
+fn to_rdata(ip: IpAddr) -> RData {
+    match ip {
+        IpAddr::V4(v) => RData::A(v),
+        IpAddr::V6(v) => RData::AAAA(v),
+    }
+}
+fn add_record(name: &str, ip: IpAddr) -> Result<Record> {
+    Ok(Record::from_rdata(
+        Name::from_ascii(name)?,
+        TTL,
+        to_rdata(ip),
+    ))
+}
+/// Deletes an RR from an RRset (RFC 2136 2.5.4): class NONE and TTL 0.
+fn delete_record(name: &str, ip: IpAddr) -> Result<Record> {
+    let mut record = Record::from_rdata(Name::from_ascii(name)?, 0, to_rdata(ip));
+    record.set_dns_class(DNSClass::NONE);
+    Ok(record)
+}
+
+fn new_id() -> u16 {
+    let mut buf = [0u8; 2];
+    getrandom::getrandom(&mut buf).expect("getrandom");
+    u16::from_be_bytes(buf)
+}
+fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::net::TcpListener;
+    use trust_dns_proto::rr::rdata::SOA;
+
+    const ZONE: &str = "example.test";
+
+    fn new_key(secret: &[u8]) -> TsigKey {
+        TsigKey::new("hub-key", "hmac-sha256", secret.to_vec()).unwrap()
+    }
+
+    /// Primary of ZONE accepting updates and transfers signed with `key`.
+    struct TestServer {
+        key: TsigKey,
+        zone: Name,
+        records: Mutex<Vec<Record>>,
+    }
+    impl TestServer {
+        async fn start(key: TsigKey) -> (SocketAddr, Arc<Self>) {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let socket = UdpSocket::bind(addr).await.unwrap();
+            let server = Arc::new(TestServer {
+                key,
+                zone: Name::from_ascii(ZONE).unwrap(),
+                records: Mutex::new(vec![add_record(
+                    "www.example.test",
+                    "1.2.3.9".parse().unwrap(),
+                )
+                .unwrap()]),
+            });
+            tokio::spawn({
+                let server = server.clone();
+                async move {
+                    let mut buf = [0u8; 4096];
+                    while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                        let res = server.handle(&buf[..n]);
+                        socket.send_to(&res[0], from).await.unwrap();
+                    }
+                }
+            });
+            tokio::spawn({
+                let server = server.clone();
+                async move {
+                    while let Ok((mut stream, _)) = listener.accept().await {
+                        let len = stream.read_u16().await.unwrap() as usize;
+                        let mut buf = vec![0u8; len];
+                        stream.read_exact(&mut buf).await.unwrap();
+                        for v in server.handle(&buf) {
+                            stream.write_u16(v.len() as u16).await.unwrap();
+                            stream.write_all(&v).await.unwrap();
+                        }
+                    }
+                }
+            });
+            (addr, server)
+        }
+
+        fn get_names(&self) -> Vec<String> {
+            let mut res: Vec<String> = self
+                .records
+                .lock()
+                .iter()
+                .map(|v| {
+                    format!(
+                        "{} {}",
+                        v.name().to_string().trim_end_matches('.'),
+                        to_ip(v)
+                    )
+                })
+                .collect();
+            res.sort();
+            res
+        }
+
+        fn handle(&self, bytes: &[u8]) -> Vec<Vec<u8>> {
+            let req = Message::from_vec(bytes).unwrap();
+            let query = req.queries()[0].clone();
+            let mut res = Message::new();
+            res.set_id(req.id())
+                .set_message_type(MessageType::Response)
+                .set_op_code(req.op_code())
+                .add_query(query.clone());
+            let Ok(mac) = self.key.verify(bytes, None, true) else {
+                res.set_response_code(ResponseCode::NotAuth);
+                return vec![res.to_vec().unwrap()];
+            };
+            if query.name() != &self.zone {
+                res.set_response_code(ResponseCode::NXDomain);
+            } else if req.op_code() == OpCode::Update {
+                let mut records = self.records.lock();
+                for v in req.name_servers() {
+                    match v.dns_class() {
+                        DNSClass::IN if !records.contains(v) => records.push(v.clone()),
+                        DNSClass::NONE => {
+                            records.retain(|r| !(r.name() == v.name() && r.data() == v.data()))
+                        }
+                        _ => {}
+                    }
+                }
+            } else if query.query_type() == RecordType::AXFR {
+                // the last SOA in a second message
+                res.add_answer(self.soa());
+                res.add_answers(self.records.lock().clone());
+                let mac = self.key.sign(&mut res, Some(&mac)).unwrap();
+                let mut last = Message::new();
+                last.set_id(req.id())
+                    .set_message_type(MessageType::Response)
+                    .add_answer(self.soa());
+                self.sign_following(&mut last, &mac);
+                return vec![res.to_vec().unwrap(), last.to_vec().unwrap()];
+            } else {
+                res.add_answer(self.soa());
+            }
+            self.key.sign(&mut res, Some(&mac)).unwrap();
+            vec![res.to_vec().unwrap()]
+        }
+
+        fn soa(&self) -> Record {
+            let ns = Name::from_ascii("ns.example.test").unwrap();
+            Record::from_rdata(
+                self.zone.clone(),
+                TTL,
+                RData::SOA(SOA::new(ns.clone(), ns, 1, 3600, 600, 86400, 60)),
+            )
+        }
+        /// Following messages of a transfer are signed with the timers only.
+        fn sign_following(&self, msg: &mut Message, previous_mac: &[u8]) {
+            let time = now_secs();
+            let mut tbs = (previous_mac.len() as u16).to_be_bytes().to_vec();
+            tbs.extend(previous_mac);
+            tbs.extend(msg.to_vec().unwrap());
+            tbs.extend(&time.to_be_bytes()[2..]);
+            tbs.extend(FUDGE.to_be_bytes());
+            let mac = self.key.algorithm.mac_data(&self.key.secret, &tbs).unwrap();
+            let tsig = TSIG::new(
+                self.key.algorithm.clone(),
+                time,
+                FUDGE,
+                mac,
+                msg.id(),
+                0,
+                Vec::new(),
+            );
+            msg.add_tsig(make_tsig_record(self.key.name.clone(), tsig));
+        }
+    }
+
+    fn to_ip(record: &Record) -> IpAddr {
+        match record.data().unwrap() {
+            RData::A(v) => IpAddr::V4(*v),
+            RData::AAAA(v) => IpAddr::V6(*v),
+            v => panic!("{:?}", v),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rfc2136_dns_provider() {
+        let key = new_key(b"0123456789abcdef0123456789abcdef");
+        let (addr, server) = TestServer::start(key.clone()).await;
+        let dns = Rfc2136DnsProvider::new(addr, Some(key));
+
+        assert_eq!(dns.get_zone_id(ZONE).await.unwrap().as_deref(), Some(ZONE));
+        assert_eq!(dns.get_zone_id("example.net").await.unwrap(), None);
+
+        dns.create_record(ZONE, "hub.example.test", "1.2.3.4".parse().unwrap(), true)
+            .await
+            .unwrap();
+        dns.create_record(
+            ZONE,
+            "hub.example.test",
+            "2001:db8::1".parse().unwrap(),
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            server.get_names(),
+            vec![
+                "hub.example.test 1.2.3.4",
+                "hub.example.test 2001:db8::1",
+                "www.example.test 1.2.3.9",
+            ]
+        );
+
+        let records = dns.list_records(ZONE).await.unwrap();
+        assert_eq!(records.len(), 3);
+        let v4 = records
+            .iter()
+            .find(|v| v.name == "hub.example.test" && v.ip.is_ipv4())
+            .unwrap();
+        dns.update_record(ZONE, v4, "1.2.3.5".parse().unwrap(), true)
+            .await
+            .unwrap();
+        let v6 = records.iter().find(|v| v.ip.is_ipv6()).unwrap();
+        dns.delete_record(ZONE, v6).await.unwrap();
+        let mut names: Vec<String> = dns
+            .list_records(ZONE)
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| v.id)
+            .collect();
+        names.sort();
+        assert_eq!(
+            names,
+            vec!["hub.example.test 1.2.3.5", "www.example.test 1.2.3.9"]
+        );
+
+        // other keys and unsigned requests are rejected
+        for dns in [
+            Rfc2136DnsProvider::new(addr, Some(new_key(b"other"))),
+            Rfc2136DnsProvider::new(addr, None),
+        ] {
+            let err = dns
+                .create_record(ZONE, "evil.example.test", "1.2.3.6".parse().unwrap(), true)
+                .await
+                .unwrap_err();
+            // the unsigned NOTAUTH can not be trusted with a key
+            let code = err.downcast_ref::<ResponseError>().map(|v| v.code);
+            if dns.key.is_some() {
+                assert_eq!(code, None);
+            } else {
+                assert_eq!(code, Some(ResponseCode::NotAuth));
+            }
+            assert!(dns.list_records(ZONE).await.is_err());
+            assert!(dns.get_zone_id("example.net").await.is_err());
+        }
+        assert_eq!(server.get_names().len(), 2);
+    }
+
+    /// Query of `example.test SOA` and its NXDOMAIN response signed with `hub-key` at
+    /// 1700000000 (and +1). Assembled by hand after RFC 8945 4.3.3, the MACs are HMAC-SHA256
+    /// of the digest components computed apart from this code.
+    const SIGNED_QUERY: &str = "123400000001000000000001076578616d706c650474657374000006000107\
+        6875622d6b65790000fa00ff00000000003d0b686d61632d7368613235360000006553f100012c00202cbd\
+        68ba6d329f1cc26e048328f96f5e9024aa885be46eaacd043271ad34975a123400000000";
+    const SIGNED_RESPONSE: &str = "123484030001000000000001076578616d706c6504746573740000060001\
+        076875622d6b65790000fa00ff00000000003d0b686d61632d7368613235360000006553f101012c0020988f\
+        c0a81124a2f2481695edf441ef105b29aac7187062078de842b1785248d7123400000000";
+    const TIME: u64 = 1700000000;
+
+    #[test]
+    fn test_tsig_vector() {
+        let key = new_key(b"0123456789abcdef0123456789abcdef");
+        let query = hex::decode(SIGNED_QUERY).unwrap();
+        let response = hex::decode(SIGNED_RESPONSE).unwrap();
+
+        let mut msg = Message::new();
+        msg.set_id(0x1234)
+            .set_message_type(MessageType::Query)
+            .set_op_code(OpCode::Query)
+            .add_query(Query::query(
+                Name::from_ascii(ZONE).unwrap(),
+                RecordType::SOA,
+            ));
+        let mac = key.sign_at(&mut msg, None, TIME).unwrap();
+        assert_eq!(msg.to_vec().unwrap(), query);
+        assert_eq!(key.verify_at(&query, None, true, TIME).unwrap(), mac);
+
+        let res_mac = key.verify_at(&response, Some(&mac), true, TIME).unwrap();
+        assert_eq!(
+            &res_mac[..],
+            &response[response.len() - 38..response.len() - 6]
+        );
+        // chained to another request, tampered, out of time or another key
+        assert!(key
+            .verify_at(&response, Some(&[0u8; 32]), true, TIME)
+            .is_err());
+        let mut tampered = response.clone();
+        tampered[3] = 0x05;
+        assert!(key.verify_at(&tampered, Some(&mac), true, TIME).is_err());
+        assert!(key
+            .verify_at(&response, Some(&mac), true, TIME + 1000)
+            .is_err());
+        assert!(new_key(b"other")
+            .verify_at(&response, Some(&mac), true, TIME)
+            .is_err());
+
+        // an unsigned NXDOMAIN is not taken as the answer
+        let dns = Rfc2136DnsProvider::new("127.0.0.1:53".parse().unwrap(), Some(key));
+        let mut forged = Message::from_vec(&response).unwrap();
+        forged.take_additionals();
+        forged.take_signature();
+        let err = dns
+            .check_response(&forged.to_vec().unwrap(), Some(&mac), true)
+            .unwrap_err();
+        assert!(err.downcast_ref::<ResponseError>().is_none());
+    }
+
+    #[test]
+    fn test_tsig_key() {
+        assert!(TsigKey::new("k", "hmac-sha512", vec![1]).is_ok());
+        assert!(TsigKey::new("k", "hmac-md5", vec![1]).is_err());
+    }
+}
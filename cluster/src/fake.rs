@@ -0,0 +1,228 @@
+//! In-memory DnsProvider, ObjectStore and NodeSource, to run the cluster manager without network access.
+use crate::dns_provider::{DnsProvider, DnsRecord};
+use crate::node_source::{Node, NodeSource};
+use crate::storage::{ObjectStore, StoredObject};
+use anyhow::{anyhow, Result};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::net::IpAddr;
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeDnsRecord {
+    pub zone_id: String,
+    pub record: DnsRecord,
+    pub proxied: bool,
+}
+
+/// Zones and A/AAAA records kept in memory.
+#[derive(Default)]
+pub struct MemoryDnsProvider {
+    /// zone name -> zone id
+    zones: HashMap<String, String>,
+    records: Mutex<Vec<FakeDnsRecord>>,
+    next_id: Mutex<u64>,
+}
+impl MemoryDnsProvider {
+    /// `zones`: (zone name, zone id)
+    pub fn new(zones: &[(&str, &str)]) -> Self {
+        MemoryDnsProvider {
+            zones: zones
+                .iter()
+                .map(|(name, id)| (name.to_string(), id.to_string()))
+                .collect(),
+            ..Default::default()
+        }
+    }
+    /// Adds a record directly, returns its id.
+    pub fn insert(&self, zone_id: &str, name: &str, ip: &str) -> String {
+        let id = self.new_id();
+        self.records.lock().push(FakeDnsRecord {
+            zone_id: zone_id.into(),
+            record: DnsRecord {
+                id: id.clone(),
+                name: name.into(),
+                ip: ip.parse().unwrap(),
+            },
+            proxied: false,
+        });
+        id
+    }
+    pub fn get_records(&self) -> Vec<FakeDnsRecord> {
+        self.records.lock().clone()
+    }
+    /// (name, ip) of the records, sorted.
+    pub fn get_names(&self) -> Vec<(String, String)> {
+        let mut res: Vec<(String, String)> = self
+            .records
+            .lock()
+            .iter()
+            .map(|v| (v.record.name.clone(), v.record.ip.to_string()))
+            .collect();
+        res.sort();
+        res
+    }
+    fn new_id(&self) -> String {
+        let mut next_id = self.next_id.lock();
+        *next_id += 1;
+        format!("r{}", next_id)
+    }
+    fn check_zone(&self, zone_id: &str) -> Result<()> {
+        if !self.zones.values().any(|id| id == zone_id) {
+            return Err(anyhow!("zone not found: {}", zone_id));
+        }
+        Ok(())
+    }
+    fn find(&self, zone_id: &str, id: &str) -> Result<usize> {
+        self.records
+            .lock()
+            .iter()
+            .position(|v| v.zone_id == zone_id && v.record.id == id)
+            .ok_or_else(|| anyhow!("record not found: {}", id))
+    }
+}
+#[async_trait]
+impl DnsProvider for MemoryDnsProvider {
+    async fn get_zone_id(&self, domain: &str) -> Result<Option<String>> {
+        Ok(self.zones.get(domain).cloned())
+    }
+    async fn list_records(&self, zone_id: &str) -> Result<Vec<DnsRecord>> {
+        self.check_zone(zone_id)?;
+        Ok(self
+            .records
+            .lock()
+            .iter()
+            .filter(|v| v.zone_id == zone_id)
+            .map(|v| v.record.clone())
+            .collect())
+    }
+    async fn create_record(
+        &self,
+        zone_id: &str,
+        name: &str,
+        ip: IpAddr,
+        proxy: bool,
+    ) -> Result<()> {
+        self.check_zone(zone_id)?;
+        let id = self.new_id();
+        self.records.lock().push(FakeDnsRecord {
+            zone_id: zone_id.into(),
+            record: DnsRecord {
+                id,
+                name: name.into(),
+                ip,
+            },
+            proxied: proxy,
+        });
+        Ok(())
+    }
+    async fn update_record(
+        &self,
+        zone_id: &str,
+        record: &DnsRecord,
+        ip: IpAddr,
+        proxy: bool,
+    ) -> Result<()> {
+        let i = self.find(zone_id, &record.id)?;
+        let mut records = self.records.lock();
+        records[i].record.ip = ip;
+        records[i].proxied = proxy;
+        Ok(())
+    }
+    async fn delete_record(&self, zone_id: &str, record: &DnsRecord) -> Result<()> {
+        let i = self.find(zone_id, &record.id)?;
+        self.records.lock().remove(i);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FakeObject {
+    pub body: Vec<u8>,
+    pub content_type: String,
+    pub etag: String,
+}
+
+/// Objects kept in memory by (bucket, key).
+#[derive(Default)]
+pub struct MemoryObjectStore {
+    objects: Mutex<HashMap<(String, String), FakeObject>>,
+    put_count: Mutex<usize>,
+}
+impl MemoryObjectStore {
+    pub fn get_object(&self, bucket: &str, key: &str) -> Option<FakeObject> {
+        self.objects
+            .lock()
+            .get(&(bucket.to_string(), key.to_string()))
+            .cloned()
+    }
+    pub fn get_put_count(&self) -> usize {
+        *self.put_count.lock()
+    }
+}
+#[async_trait]
+impl ObjectStore for MemoryObjectStore {
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &str,
+    ) -> Result<()> {
+        let mut put_count = self.put_count.lock();
+        *put_count += 1;
+        self.objects.lock().insert(
+            (bucket.to_string(), key.to_string()),
+            FakeObject {
+                body,
+                content_type: content_type.into(),
+                etag: format!("\"{}\"", put_count),
+            },
+        );
+        Ok(())
+    }
+    async fn fetch_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        if_none_match: Option<&str>,
+    ) -> Result<Option<StoredObject>> {
+        let obj = self
+            .get_object(bucket, key)
+            .ok_or_else(|| anyhow!("object not found: {}/{}", bucket, key))?;
+        if if_none_match == Some(obj.etag.as_str()) {
+            return Ok(None);
+        }
+        Ok(Some(StoredObject {
+            body: obj.body,
+            etag: Some(obj.etag),
+        }))
+    }
+}
+
+/// Nodes set from code.
+#[derive(Default)]
+pub struct MemoryNodeSource {
+    nodes: Mutex<Vec<Node>>,
+}
+impl MemoryNodeSource {
+    pub fn new(ips: &[&str]) -> Self {
+        let res = MemoryNodeSource::default();
+        res.set_nodes(ips);
+        res
+    }
+    pub fn set_nodes(&self, ips: &[&str]) {
+        *self.nodes.lock() = ips
+            .iter()
+            .map(|v| Node {
+                public_ip: v.to_string(),
+            })
+            .collect();
+    }
+}
+#[async_trait]
+impl NodeSource for MemoryNodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        Ok(self.nodes.lock().clone())
+    }
+}
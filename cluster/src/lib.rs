@@ -5,6 +5,10 @@ pub mod data;
 pub use client::Client;
 pub use client::Worker;
 mod aws;
-mod cf;
+pub mod dns_provider;
+#[cfg(any(test, feature = "fake"))]
+pub mod fake;
 pub mod manager;
+pub mod node_list_store;
 pub mod node_source;
+pub mod storage;
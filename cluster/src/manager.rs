@@ -1,138 +1,136 @@
-use crate::aws::load_aws_config;
-use crate::cf;
-pub use crate::cf::CfAuthInfo;
 use crate::data;
+pub use crate::dns_provider::{CfAuthInfo, CfDnsProvider, DnsProvider, DnsRecord};
 use crate::func::*;
-use crate::node_source;
-use anyhow::{anyhow, bail, Result};
-
-pub struct S3Path {
-    pub bucket: String,
-    pub key: String,
-}
+use crate::node_list_store::NodeListStore;
+use crate::node_source::{self, NodeSource};
+pub use crate::storage::S3Path;
+use anyhow::{bail, Result};
+use parking_lot::Mutex;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 pub struct Manager {
     cluster_host: String,
-    role: String,
-    stage: String,
-    aws_region: String,
 
-    cluster_json_s3_path: S3Path,
+    node_source: Arc<dyn NodeSource>,
+    dns: Arc<dyn DnsProvider>,
+    node_list_store: Arc<dyn NodeListStore>,
 
-    cf_auth: CfAuthInfo,
+    /// Nodes of the last successful update.
+    last_nodes: Mutex<Option<Vec<node_source::Node>>>,
 }
 
 impl Manager {
     pub fn new(
         cluster_host: &str,
-        role: &str,
-        stage: &str,
-        aws_region: &str,
-        cluster_json_s3_path: S3Path,
-        cf_auth: CfAuthInfo,
+        node_source: Arc<dyn NodeSource>,
+        dns: Arc<dyn DnsProvider>,
+        node_list_store: Arc<dyn NodeListStore>,
     ) -> Self {
         Manager {
             cluster_host: cluster_host.into(),
-            role: role.into(),
-            stage: stage.into(),
-            aws_region: aws_region.into(),
-            cluster_json_s3_path,
-            cf_auth,
+            node_source,
+            dns,
+            node_list_store,
+            last_nodes: Mutex::new(None),
         }
     }
 
     pub async fn update(&self) -> Result<()> {
         let nodes = self.get_nodes().await?;
-        // node listのjsonファイルをs3にアップロードする
-        self.update_cluster_json(&nodes).await?;
+        self.apply(nodes).await
+    }
+    /// Same as update, but does nothing while the nodes are the same as the last update.
+    /// Returns true if updated.
+    pub async fn update_if_changed(&self) -> Result<bool> {
+        let nodes = self.get_nodes().await?;
+        if self.last_nodes.lock().as_ref() == Some(&nodes) {
+            return Ok(false);
+        }
+        self.apply(nodes).await?;
+        Ok(true)
+    }
+    async fn apply(&self, nodes: Vec<node_source::Node>) -> Result<()> {
+        // node listを保存する
+        self.node_list_store
+            .put(&self.create_node_list(&nodes))
+            .await?;
         self.update_dns(&nodes).await?;
+        *self.last_nodes.lock() = Some(nodes);
         Ok(())
     }
 
     async fn update_dns(&self, nodes: &[node_source::Node]) -> Result<()> {
         let (prefix, base_domain) = split_host(&self.cluster_host);
 
-        let cf_client = cf::create_client(&self.cf_auth)?;
-        let Some(cf_zone_id) = cf::get_zone_id(&cf_client, &base_domain).await? else {
+        let Some(zone_id) = self.dns.get_zone_id(&base_domain).await? else {
             bail!(format!("{} not found", base_domain));
         };
 
-        let records = cf::get_arecords(&cf_client, &cf_zone_id).await?;
+        let records = self.dns.list_records(&zone_id).await?;
         let RecordGroups {
             cluster_records,
             single_records,
         } = split_records(&records, &prefix, &base_domain, &self.cluster_host);
 
-        let current_ips: Vec<&str> = nodes.iter().map(|v| v.public_ip.as_str()).collect();
+        let current_ips = nodes
+            .iter()
+            .map(|v| v.public_ip.parse::<IpAddr>())
+            .collect::<Result<Vec<_>, _>>()?;
 
         let DiffResult { adds, dels } = diff_records(&cluster_records, &current_ips);
         for v in dels {
-            cf::delete_arecord(&cf_client, &cf_zone_id, &v.id).await?;
+            self.dns.delete_record(&zone_id, v).await?;
         }
         for v in adds {
-            cf::add_arecord(&cf_client, &cf_zone_id, &prefix, v, true).await?;
+            self.dns
+                .create_record(&zone_id, &self.cluster_host, v, true)
+                .await?;
         }
 
         let DiffResult { adds, dels } = diff_records(&single_records, &current_ips);
         for v in dels {
-            cf::delete_arecord(&cf_client, &cf_zone_id, &v.id).await?;
+            self.dns.delete_record(&zone_id, v).await?;
         }
         for v in adds {
-            let prefix = get_node_prefix(v, &self.cluster_host);
-            cf::add_arecord(&cf_client, &cf_zone_id, &prefix, v, true).await?;
+            let name = get_node_host(&v.to_string(), &self.cluster_host);
+            self.dns.create_record(&zone_id, &name, v, true).await?;
         }
         Ok(())
     }
-    async fn update_cluster_json(&self, nodes: &[node_source::Node]) -> Result<()> {
-        let js = self.create_cluster_json(nodes)?;
-        self.upload_cluster_json(&js).await?;
-        Ok(())
-    }
-    async fn upload_cluster_json(&self, js: &str) -> Result<()> {
-        use aws_sdk_s3 as s3;
-
-        let config = load_aws_config(&Some(self.aws_region.to_owned())).await;
-        let client = s3::Client::new(&config);
-        use s3::types::ByteStream;
-        let body = ByteStream::from(js.as_bytes().to_vec());
-        client
-            .put_object()
-            .bucket(self.cluster_json_s3_path.bucket.to_owned())
-            .key(self.cluster_json_s3_path.key.to_owned())
-            .content_type("application/json".to_owned())
-            .body(body)
-            .send()
-            .await?;
-        Ok(())
-    }
-    fn create_cluster_json(&self, nodes: &[node_source::Node]) -> Result<String> {
-        let data = data::NodeListData {
+    fn create_node_list(&self, nodes: &[node_source::Node]) -> data::NodeListData {
+        data::NodeListData {
             nodes: nodes
                 .iter()
                 .map(|v| data::NodeListNode {
                     host: get_node_host(&v.public_ip, &self.cluster_host),
                 })
                 .collect(),
-        };
-        serde_json::to_string(&data).map_err(|e| anyhow!(e))
+        }
     }
+    /// Nodes sorted and de-duplicated by host, so the node list (and the world assignment by
+    /// index into it) does not depend on the order the source returns them in.
     async fn get_nodes(&self) -> Result<Vec<node_source::Node>> {
-        node_source::load_nodes_from_ec2(&self.role, &self.stage, &self.aws_region).await
+        let mut nodes = self.node_source.get_nodes().await?;
+        let host = |v: &node_source::Node| get_node_host(&v.public_ip, &self.cluster_host);
+        nodes.sort_by_cached_key(host);
+        nodes.dedup_by(|a, b| host(a) == host(b));
+        Ok(nodes)
     }
 }
 
 struct RecordGroups<'a> {
-    cluster_records: Vec<&'a cf::CfARecord>,
-    single_records: Vec<&'a cf::CfARecord>,
+    cluster_records: Vec<&'a DnsRecord>,
+    single_records: Vec<&'a DnsRecord>,
 }
 fn split_records<'a>(
-    records: &'a [cf::CfARecord],
+    records: &'a [DnsRecord],
     prefix: &str,
     base_domain: &str,
     cluster_host: &str,
 ) -> RecordGroups<'a> {
-    let mut cluster_records = Vec::<&cf::CfARecord>::new();
-    let mut single_records = Vec::<&cf::CfARecord>::new();
+    let mut cluster_records = Vec::<&DnsRecord>::new();
+    let mut single_records = Vec::<&DnsRecord>::new();
 
     for record in records {
         if record.name == cluster_host {
@@ -168,24 +166,27 @@ fn split_records<'a>(
 }
 
 struct DiffResult<'a> {
-    adds: Vec<&'a str>,
-    dels: Vec<&'a cf::CfARecord>,
+    adds: Vec<IpAddr>,
+    dels: Vec<&'a DnsRecord>,
 }
 
-fn diff_records<'a>(records: &'a [&cf::CfARecord], current_ips: &'a [&str]) -> DiffResult<'a> {
+/// A records are always managed, AAAA records only if some node has an IPv6 address,
+/// so hand-made AAAA records of IPv4 nodes are kept.
+fn diff_records<'a>(records: &[&'a DnsRecord], current_ips: &[IpAddr]) -> DiffResult<'a> {
     use std::collections::HashSet;
-    let prev_ip_set: HashSet<&str> = HashSet::from_iter(records.iter().map(|v| v.ip.as_str()));
-    let current_ip_set: HashSet<&str> = HashSet::from_iter(current_ips.iter().cloned());
-    let mut adds = Vec::<&str>::new();
-    let mut dels = Vec::<&cf::CfARecord>::new();
+    let prev_ip_set: HashSet<IpAddr> = HashSet::from_iter(records.iter().map(|v| v.ip));
+    let current_ip_set: HashSet<IpAddr> = HashSet::from_iter(current_ips.iter().cloned());
+    let manages_ipv6 = current_ips.iter().any(|v| v.is_ipv6());
+    let mut adds = Vec::<IpAddr>::new();
+    let mut dels = Vec::<&DnsRecord>::new();
 
     for v in current_ips {
         if !prev_ip_set.contains(v) {
-            adds.push(v);
+            adds.push(*v);
         }
     }
     for v in records {
-        if !current_ip_set.contains(v.ip.as_str()) {
+        if (v.ip.is_ipv4() || manages_ipv6) && !current_ip_set.contains(&v.ip) {
             dels.push(v);
         }
     }
@@ -196,50 +197,151 @@ fn diff_records<'a>(records: &'a [&cf::CfARecord], current_ips: &'a [&str]) -> D
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::node_list_store::ObjectNodeListStore;
     use std::env;
     #[tokio::test]
+    #[ignore = "needs CLOUDFLARE_API_TOKEN and AWS credentials"]
     async fn test_manager() {
-        let cf_email = env::var("CLOUDFLARE_EMAIL").unwrap();
-        let cf_api_key = env::var("CLOUDFLARE_API_KEY").unwrap();
+        let cf_api_token = env::var("CLOUDFLARE_API_TOKEN").unwrap();
 
         let mgr = Manager::new(
             "entrance.verseengine.cloud",
-            "dev",
-            "CellServer",
-            "ap-northeast-1",
+            Arc::new(crate::node_source::Ec2NodeSource::new(
+                "dev",
+                "CellServer",
+                "ap-northeast-1",
+            )),
+            Arc::new(CfDnsProvider::new(CfAuthInfo::ApiToken(cf_api_token))),
+            Arc::new(ObjectNodeListStore::s3(
+                "ap-northeast-1",
+                S3Path {
+                    bucket: "mdev-test-data".into(),
+                    key: "cluster/cluster-dev.json".into(),
+                },
+            )),
+        );
+        mgr.update().await.unwrap();
+    }
+    #[tokio::test]
+    async fn test_manager_update() {
+        use crate::fake::*;
+        let cluster_host = "entrance.verseengine.cloud";
+        let nodes = Arc::new(MemoryNodeSource::new(&["1.2.3.4", "1.2.3.5"]));
+        let dns = Arc::new(MemoryDnsProvider::new(&[("verseengine.cloud", "z1")]));
+        dns.insert("z1", cluster_host, "1.2.3.4");
+        // a node that is gone
+        dns.insert("z1", cluster_host, "1.2.3.9");
+        dns.insert("z1", &get_node_host("1.2.3.9", cluster_host), "1.2.3.9");
+        dns.insert("z1", "www.verseengine.cloud", "1.2.3.9");
+        // hand-made AAAA records are kept while the nodes are IPv4 only
+        dns.insert("z1", cluster_host, "2001:db8::1");
+        let aaaa_host = get_node_host("1.2.3.4", cluster_host);
+        dns.insert("z1", &aaaa_host, "2001:db8::4");
+        let store = Arc::new(MemoryObjectStore::default());
+        let node_list_store = Arc::new(ObjectNodeListStore::new(
+            store.clone(),
             S3Path {
-                bucket: "mdev-test-data".into(),
-                key: "cluster/cluster-dev.json".into(),
-            },
-            CfAuthInfo {
-                email: cf_email,
-                api_key: cf_api_key,
+                bucket: "b".into(),
+                key: "cluster.json".into(),
             },
+        ));
+        let mgr = Manager::new(
+            cluster_host,
+            nodes.clone(),
+            dns.clone(),
+            node_list_store.clone(),
         );
+
+        let expected = |ips: &[&str]| {
+            let mut res = vec![
+                ("www.verseengine.cloud".to_string(), "1.2.3.9".to_string()),
+                (cluster_host.to_string(), "2001:db8::1".to_string()),
+                (aaaa_host.clone(), "2001:db8::4".to_string()),
+            ];
+            for ip in ips {
+                res.push((cluster_host.to_string(), ip.to_string()));
+                res.push((get_node_host(ip, cluster_host), ip.to_string()));
+            }
+            res.sort();
+            res
+        };
+        let node_hosts = |ips: &[&str]| {
+            let mut res = ips
+                .iter()
+                .map(|v| get_node_host(v, cluster_host))
+                .collect::<Vec<_>>();
+            res.sort();
+            res
+        };
+        let hosts = |store: &MemoryObjectStore| {
+            let obj = store.get_object("b", "cluster.json").unwrap();
+            assert_eq!(obj.content_type, "application/json");
+            let data: data::NodeListData = serde_json::from_slice(&obj.body).unwrap();
+            data.nodes.into_iter().map(|v| v.host).collect::<Vec<_>>()
+        };
+
         mgr.update().await.unwrap();
+        assert_eq!(dns.get_names(), expected(&["1.2.3.4", "1.2.3.5"]));
+        assert!(dns
+            .get_records()
+            .iter()
+            .filter(|v| v.record.ip.to_string() == "1.2.3.5")
+            .all(|v| v.proxied));
+        assert_eq!(hosts(&store), node_hosts(&["1.2.3.4", "1.2.3.5"]));
+
+        nodes.set_nodes(&["1.2.3.5", "1.2.3.6"]);
+        mgr.update().await.unwrap();
+        assert_eq!(dns.get_names(), expected(&["1.2.3.5", "1.2.3.6"]));
+        assert_eq!(hosts(&store), node_hosts(&["1.2.3.5", "1.2.3.6"]));
+        assert_eq!(store.get_put_count(), 2);
+
+        // no changes
+        let records = dns.get_records();
+        mgr.update().await.unwrap();
+        assert_eq!(dns.get_records(), records);
+        assert_eq!(store.get_put_count(), 3);
+        assert!(!mgr.update_if_changed().await.unwrap());
+        assert_eq!(store.get_put_count(), 3);
+        nodes.set_nodes(&["1.2.3.6"]);
+        assert!(mgr.update_if_changed().await.unwrap());
+        assert_eq!(dns.get_names(), expected(&["1.2.3.6"]));
+        assert_eq!(store.get_put_count(), 4);
+
+        // the order and duplicates from the source do not matter
+        nodes.set_nodes(&["1.2.3.4", "1.2.3.5", "1.2.3.6"]);
+        assert!(mgr.update_if_changed().await.unwrap());
+        let sorted = node_hosts(&["1.2.3.4", "1.2.3.5", "1.2.3.6"]);
+        assert_eq!(hosts(&store), sorted);
+        nodes.set_nodes(&["1.2.3.6", "1.2.3.5", "1.2.3.4", "1.2.3.5"]);
+        assert!(!mgr.update_if_changed().await.unwrap());
+        assert_eq!(store.get_put_count(), 5);
+        assert_eq!(hosts(&store), sorted);
+
+        let mgr = Manager::new("entrance.example.com", nodes, dns, node_list_store);
+        assert!(mgr.update().await.is_err());
     }
     #[test]
     fn test_split_records() {
         let records = vec![
-            cf::CfARecord {
+            DnsRecord {
                 id: "1".into(),
                 name: "entrance.verseengine.cloud".into(),
-                ip: "1.2.3.4".into(),
+                ip: "1.2.3.4".parse().unwrap(),
             },
-            cf::CfARecord {
+            DnsRecord {
                 id: "2".into(),
                 name: "entrance-1111111111111111.verseengine.cloud".into(),
-                ip: "1.2.3.4".into(),
+                ip: "1.2.3.4".parse().unwrap(),
             },
-            cf::CfARecord {
+            DnsRecord {
                 id: "3".into(),
                 name: "entrance-1111111111111112.verseengine.cloud".into(),
-                ip: "1.2.3.4".into(),
+                ip: "1.2.3.4".parse().unwrap(),
             },
-            cf::CfARecord {
+            DnsRecord {
                 id: "4".into(),
                 name: "entrance-1.verseengine.cloud".into(),
-                ip: "1.2.3.4".into(),
+                ip: "1.2.3.4".parse().unwrap(),
             },
         ];
         let RecordGroups {
@@ -261,48 +363,69 @@ mod tests {
     #[test]
     fn test_diff_records() {
         let records = vec![];
-        let current_ips = vec!["1.1.1.0"];
+        let ip = |v: &str| v.parse::<IpAddr>().unwrap();
+        let current_ips = vec![ip("1.1.1.0")];
         let DiffResult { adds, dels } = diff_records(&records, &current_ips);
         assert!(dels.is_empty());
         assert_eq!(adds.len(), 1);
-        assert_eq!(adds[0], "1.1.1.0");
+        assert_eq!(adds[0], ip("1.1.1.0"));
 
         let records = vec![
-            cf::CfARecord {
+            DnsRecord {
                 id: "1".into(),
                 name: "entrance.verseengine.cloud".into(),
-                ip: "1.1.1.0".into(),
+                ip: "1.1.1.0".parse().unwrap(),
             },
-            cf::CfARecord {
+            DnsRecord {
                 id: "2".into(),
                 name: "entrance.verseengine.cloud".into(),
-                ip: "1.1.1.1".into(),
+                ip: "1.1.1.1".parse().unwrap(),
             },
         ];
-        let record_refs: Vec<&cf::CfARecord> = records.iter().collect();
+        let record_refs: Vec<&DnsRecord> = records.iter().collect();
         let current_ips = vec![];
         let DiffResult { adds, dels } = diff_records(&record_refs, &current_ips);
         assert!(adds.is_empty());
         assert_eq!(dels.len(), 2);
 
         let records = vec![
-            cf::CfARecord {
+            DnsRecord {
                 id: "1".into(),
                 name: "entrance.verseengine.cloud".into(),
-                ip: "1.1.1.0".into(),
+                ip: "1.1.1.0".parse().unwrap(),
             },
-            cf::CfARecord {
+            DnsRecord {
                 id: "2".into(),
                 name: "entrance.verseengine.cloud".into(),
-                ip: "1.1.1.1".into(),
+                ip: "1.1.1.1".parse().unwrap(),
             },
         ];
-        let current_ips = vec!["1.1.1.1", "1.1.1.2"];
-        let record_refs: Vec<&cf::CfARecord> = records.iter().collect();
+        let current_ips = vec![ip("1.1.1.1"), ip("1.1.1.2")];
+        let record_refs: Vec<&DnsRecord> = records.iter().collect();
         let DiffResult { adds, dels } = diff_records(&record_refs, &current_ips);
         assert_eq!(adds.len(), 1);
         assert_eq!(dels.len(), 1);
         assert_eq!(&dels[0].id, "1");
-        assert_eq!(adds[0], "1.1.1.2");
+        assert_eq!(adds[0], ip("1.1.1.2"));
+
+        // AAAA records are managed only with IPv6 nodes
+        let records = [
+            DnsRecord {
+                id: "1".into(),
+                name: "entrance.verseengine.cloud".into(),
+                ip: "1.1.1.0".parse().unwrap(),
+            },
+            DnsRecord {
+                id: "2".into(),
+                name: "entrance.verseengine.cloud".into(),
+                ip: "2001:db8::1".parse().unwrap(),
+            },
+        ];
+        let record_refs: Vec<&DnsRecord> = records.iter().collect();
+        let DiffResult { dels, .. } = diff_records(&record_refs, &[ip("1.1.1.1")]);
+        assert_eq!(dels.iter().map(|v| &v.id).collect::<Vec<_>>(), ["1"]);
+        let DiffResult { adds, dels } = diff_records(&record_refs, &[ip("2001:db8::2")]);
+        assert_eq!(dels.len(), 2);
+        assert_eq!(adds, [ip("2001:db8::2")]);
     }
 }
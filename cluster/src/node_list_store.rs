@@ -0,0 +1,178 @@
+use crate::data::NodeListData;
+use anyhow::Result;
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+mod file;
+pub use file::FileNodeListStore;
+mod http;
+pub use http::HttpNodeListStore;
+mod object;
+pub use object::ObjectNodeListStore;
+
+/// Validators of a fetched list, sent back to fetch it only when it changed.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct NodeListVersion {
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum Fetched {
+    Modified(NodeListData, NodeListVersion),
+    NotModified,
+}
+
+/// Where the cluster manager puts the node list and the nodes fetch it from.
+#[async_trait]
+pub trait NodeListStore: Send + Sync {
+    async fn put(&self, data: &NodeListData) -> Result<()>;
+    /// NotModified if the list is still `version`.
+    async fn fetch(&self, version: Option<&NodeListVersion>) -> Result<Fetched>;
+}
+
+struct CacheState {
+    data: Option<NodeListData>,
+    version: Option<NodeListVersion>,
+    /// Last successful fetch, including NotModified.
+    fetched_at: Option<Instant>,
+}
+
+/// The last fetched list of a store.
+/// While fetches fail, the list is used for `max_stale` (forever if None), then dropped.
+pub struct NodeListCache {
+    store: Arc<dyn NodeListStore>,
+    max_stale: Option<Duration>,
+    state: Mutex<CacheState>,
+}
+impl NodeListCache {
+    pub fn new(store: Arc<dyn NodeListStore>, max_stale: Option<Duration>) -> Self {
+        NodeListCache {
+            store,
+            max_stale,
+            state: Mutex::new(CacheState {
+                data: None,
+                version: None,
+                fetched_at: None,
+            }),
+        }
+    }
+
+    /// Fetches the list if it changed. Returns true if it changed.
+    pub async fn refresh(&self) -> Result<bool> {
+        let version = self.state.lock().version.clone();
+        let fetched = self.store.fetch(version.as_ref()).await?;
+        let mut state = self.state.lock();
+        state.fetched_at = Some(Instant::now());
+        match fetched {
+            Fetched::Modified(data, version) => {
+                let changed = state.data.as_ref() != Some(&data);
+                state.data = Some(data);
+                state.version = Some(version);
+                Ok(changed)
+            }
+            Fetched::NotModified => Ok(false),
+        }
+    }
+
+    /// None before the first fetch, or if the list is older than `max_stale`.
+    pub fn get(&self) -> Option<NodeListData> {
+        let state = self.state.lock();
+        let fetched_at = state.fetched_at?;
+        if let Some(max_stale) = self.max_stale {
+            if fetched_at.elapsed() > max_stale {
+                return None;
+            }
+        }
+        state.data.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::NodeListNode;
+    use crate::fake::MemoryObjectStore;
+    use crate::storage::S3Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    /// Fails while `fail` is set.
+    struct FlakyStore {
+        inner: ObjectNodeListStore,
+        fail: AtomicBool,
+    }
+    #[async_trait]
+    impl NodeListStore for FlakyStore {
+        async fn put(&self, data: &NodeListData) -> Result<()> {
+            self.inner.put(data).await
+        }
+        async fn fetch(&self, version: Option<&NodeListVersion>) -> Result<Fetched> {
+            if self.fail.load(Ordering::Relaxed) {
+                anyhow::bail!("unavailable");
+            }
+            self.inner.fetch(version).await
+        }
+    }
+
+    fn new_data(hosts: &[&str]) -> NodeListData {
+        NodeListData {
+            nodes: hosts
+                .iter()
+                .map(|v| NodeListNode {
+                    host: v.to_string(),
+                })
+                .collect(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_node_list_cache() {
+        let objects = Arc::new(MemoryObjectStore::default());
+        let store = Arc::new(FlakyStore {
+            inner: ObjectNodeListStore::new(
+                objects,
+                S3Path {
+                    bucket: "b".into(),
+                    key: "cluster.json".into(),
+                },
+            ),
+            fail: AtomicBool::new(false),
+        });
+        let cache = NodeListCache::new(store.clone(), Some(Duration::from_millis(200)));
+        assert!(cache.refresh().await.is_err());
+        assert_eq!(cache.get(), None);
+
+        store.put(&new_data(&["a"])).await.unwrap();
+        assert!(cache.refresh().await.unwrap());
+        assert_eq!(cache.get(), Some(new_data(&["a"])));
+        assert!(!cache.refresh().await.unwrap());
+
+        // same content with a new version
+        store.put(&new_data(&["a"])).await.unwrap();
+        assert!(!cache.refresh().await.unwrap());
+        store.put(&new_data(&["a", "b"])).await.unwrap();
+        assert!(cache.refresh().await.unwrap());
+
+        // stale
+        store.fail.store(true, Ordering::Relaxed);
+        assert!(cache.refresh().await.is_err());
+        assert_eq!(cache.get(), Some(new_data(&["a", "b"])));
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(cache.refresh().await.is_err());
+        assert_eq!(cache.get(), None);
+
+        store.fail.store(false, Ordering::Relaxed);
+        assert!(!cache.refresh().await.unwrap());
+        assert_eq!(cache.get(), Some(new_data(&["a", "b"])));
+
+        // kept forever without max_stale
+        let cache = NodeListCache::new(store.clone(), None);
+        cache.refresh().await.unwrap();
+        store.fail.store(true, Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(cache.refresh().await.is_err());
+        assert_eq!(cache.get(), Some(new_data(&["a", "b"])));
+    }
+}
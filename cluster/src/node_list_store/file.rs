@@ -0,0 +1,91 @@
+use super::{Fetched, NodeListStore, NodeListVersion};
+use crate::data::NodeListData;
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+/// JSON file on a local or shared file system, compared by its modification time.
+pub struct FileNodeListStore {
+    path: PathBuf,
+}
+impl FileNodeListStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileNodeListStore { path: path.into() }
+    }
+    async fn get_modified(&self) -> Result<String> {
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|v| v.modified())
+            .with_context(|| format!("{}", self.path.display()))?;
+        Ok(modified.duration_since(UNIX_EPOCH)?.as_nanos().to_string())
+    }
+}
+#[async_trait]
+impl NodeListStore for FileNodeListStore {
+    async fn put(&self, data: &NodeListData) -> Result<()> {
+        // readers never see a partially written file
+        let mut tmp = self.path.clone().into_os_string();
+        tmp.push(".tmp");
+        tokio::fs::write(&tmp, serde_json::to_vec(data)?)
+            .await
+            .with_context(|| format!("{:?}", tmp))?;
+        tokio::fs::rename(&tmp, &self.path)
+            .await
+            .with_context(|| format!("{}", self.path.display()))?;
+        Ok(())
+    }
+    async fn fetch(&self, version: Option<&NodeListVersion>) -> Result<Fetched> {
+        let modified = self.get_modified().await?;
+        if version.and_then(|v| v.last_modified.as_ref()) == Some(&modified) {
+            return Ok(Fetched::NotModified);
+        }
+        let s = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("{}", self.path.display()))?;
+        Ok(Fetched::Modified(
+            serde_json::from_str(&s)?,
+            NodeListVersion {
+                etag: None,
+                last_modified: Some(modified),
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::NodeListNode;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_file_node_list_store() {
+        let path = std::env::temp_dir().join(format!("cluster-{}.json", std::process::id()));
+        let store = FileNodeListStore::new(&path);
+        assert!(store.fetch(None).await.is_err());
+
+        let data = NodeListData {
+            nodes: vec![NodeListNode { host: "a".into() }],
+        };
+        store.put(&data).await.unwrap();
+        let Fetched::Modified(fetched, version) = store.fetch(None).await.unwrap() else {
+            panic!("not fetched");
+        };
+        assert_eq!(fetched, data);
+        assert!(matches!(
+            store.fetch(Some(&version)).await.unwrap(),
+            Fetched::NotModified
+        ));
+
+        // some file systems have a coarse mtime
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let data = NodeListData { nodes: vec![] };
+        store.put(&data).await.unwrap();
+        let Fetched::Modified(fetched, _) = store.fetch(Some(&version)).await.unwrap() else {
+            panic!("not fetched");
+        };
+        assert_eq!(fetched, data);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
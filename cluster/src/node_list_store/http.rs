@@ -0,0 +1,163 @@
+use super::{Fetched, NodeListStore, NodeListVersion};
+use crate::data::NodeListData;
+use anyhow::Result;
+use async_trait::async_trait;
+use reqwest::header::{ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+
+/// PUT and conditional GET of a URL, ex. a WebDAV server or a presigned URL.
+pub struct HttpNodeListStore {
+    url: String,
+    client: reqwest::Client,
+}
+impl HttpNodeListStore {
+    pub fn new(url: &str) -> Self {
+        HttpNodeListStore {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+#[async_trait]
+impl NodeListStore for HttpNodeListStore {
+    async fn put(&self, data: &NodeListData) -> Result<()> {
+        self.client
+            .put(&self.url)
+            .json(data)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+    async fn fetch(&self, version: Option<&NodeListVersion>) -> Result<Fetched> {
+        let mut req = self.client.get(&self.url);
+        if let Some(version) = version {
+            if let Some(etag) = &version.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &version.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let res = req.send().await?;
+        if res.status() == StatusCode::NOT_MODIFIED {
+            return Ok(Fetched::NotModified);
+        }
+        let res = res.error_for_status()?;
+        let header = |name| {
+            res.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(|v| v.to_owned())
+        };
+        let version = NodeListVersion {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        };
+        Ok(Fetched::Modified(res.json().await?, version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::NodeListNode;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    /// Keeps the PUT body, the ETag is the number of PUTs.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/cluster.json", listener.local_addr().unwrap());
+        let object: Arc<Mutex<Option<(String, u32)>>> = Arc::default();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let mut stream = BufReader::new(stream);
+                let mut request_line = String::new();
+                stream.read_line(&mut request_line).await.unwrap();
+                let mut headers = Vec::new();
+                loop {
+                    let mut line = String::new();
+                    stream.read_line(&mut line).await.unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else {
+                        break;
+                    };
+                    headers.push((name.to_lowercase(), value.to_owned()));
+                }
+                let header = |name: &str| {
+                    headers
+                        .iter()
+                        .find(|(k, _)| k == name)
+                        .map(|(_, v)| v.clone())
+                };
+                let (status, extra, body) = if request_line.starts_with("PUT ") {
+                    let len: usize = header("content-length").unwrap().parse().unwrap();
+                    let mut body = vec![0u8; len];
+                    stream.read_exact(&mut body).await.unwrap();
+                    let mut object = object.lock();
+                    let n = object.as_ref().map(|v| v.1).unwrap_or(0) + 1;
+                    *object = Some((String::from_utf8(body).unwrap(), n));
+                    ("204 No Content", String::new(), String::new())
+                } else {
+                    match object.lock().clone() {
+                        None => ("404 Not Found", String::new(), String::new()),
+                        Some((_, n)) if header("if-none-match") == Some(format!("\"{}\"", n)) => {
+                            ("304 Not Modified", String::new(), String::new())
+                        }
+                        Some((body, n)) => (
+                            "200 OK",
+                            format!(
+                                "ETag: \"{}\"\r\nLast-Modified: Sun, 18 Oct 2026 00:00:0{} GMT\r\n",
+                                n, n
+                            ),
+                            body,
+                        ),
+                    }
+                };
+                let res = format!(
+                    "HTTP/1.1 {}\r\n{}Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    extra,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(res.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_http_node_list_store() {
+        let store = HttpNodeListStore::new(&serve().await);
+        assert!(store.fetch(None).await.is_err());
+
+        let data = NodeListData {
+            nodes: vec![NodeListNode { host: "a".into() }],
+        };
+        store.put(&data).await.unwrap();
+        let Fetched::Modified(fetched, version) = store.fetch(None).await.unwrap() else {
+            panic!("not fetched");
+        };
+        assert_eq!(fetched, data);
+        assert_eq!(
+            version,
+            NodeListVersion {
+                etag: Some("\"1\"".into()),
+                last_modified: Some("Sun, 18 Oct 2026 00:00:01 GMT".into()),
+            }
+        );
+        assert!(matches!(
+            store.fetch(Some(&version)).await.unwrap(),
+            Fetched::NotModified
+        ));
+
+        store.put(&data).await.unwrap();
+        let Fetched::Modified(_, version) = store.fetch(Some(&version)).await.unwrap() else {
+            panic!("not fetched");
+        };
+        assert_eq!(version.etag, Some("\"2\"".into()));
+    }
+}
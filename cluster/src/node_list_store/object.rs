@@ -0,0 +1,91 @@
+use super::{Fetched, NodeListStore, NodeListVersion};
+use crate::data::NodeListData;
+use crate::storage::{ObjectStore, S3ObjectStore, S3Path};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::sync::Arc;
+
+/// An object of an ObjectStore, compared by its ETag.
+pub struct ObjectNodeListStore {
+    store: Arc<dyn ObjectStore>,
+    path: S3Path,
+}
+impl ObjectNodeListStore {
+    pub fn new(store: Arc<dyn ObjectStore>, path: S3Path) -> Self {
+        ObjectNodeListStore { store, path }
+    }
+    pub fn s3(region: &str, path: S3Path) -> Self {
+        Self::new(Arc::new(S3ObjectStore::new(region)), path)
+    }
+}
+#[async_trait]
+impl NodeListStore for ObjectNodeListStore {
+    async fn put(&self, data: &NodeListData) -> Result<()> {
+        self.store
+            .put_object(
+                &self.path.bucket,
+                &self.path.key,
+                serde_json::to_vec(data)?,
+                "application/json",
+            )
+            .await
+    }
+    async fn fetch(&self, version: Option<&NodeListVersion>) -> Result<Fetched> {
+        let if_none_match = version.and_then(|v| v.etag.as_deref());
+        let Some(obj) = self
+            .store
+            .fetch_object(&self.path.bucket, &self.path.key, if_none_match)
+            .await?
+        else {
+            return Ok(Fetched::NotModified);
+        };
+        Ok(Fetched::Modified(
+            serde_json::from_slice(&obj.body)?,
+            NodeListVersion {
+                etag: obj.etag,
+                last_modified: None,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::NodeListNode;
+    use crate::fake::MemoryObjectStore;
+
+    #[tokio::test]
+    async fn test_object_node_list_store() {
+        let objects = Arc::new(MemoryObjectStore::default());
+        let store = ObjectNodeListStore::new(
+            objects.clone(),
+            S3Path {
+                bucket: "b".into(),
+                key: "cluster.json".into(),
+            },
+        );
+        assert!(store.fetch(None).await.is_err());
+
+        let data = NodeListData {
+            nodes: vec![NodeListNode { host: "a".into() }],
+        };
+        store.put(&data).await.unwrap();
+        let obj = objects.get_object("b", "cluster.json").unwrap();
+        assert_eq!(obj.content_type, "application/json");
+
+        let Fetched::Modified(fetched, version) = store.fetch(None).await.unwrap() else {
+            panic!("not fetched");
+        };
+        assert_eq!(fetched, data);
+        assert!(matches!(
+            store.fetch(Some(&version)).await.unwrap(),
+            Fetched::NotModified
+        ));
+        store.put(&data).await.unwrap();
+        assert!(matches!(
+            store.fetch(Some(&version)).await.unwrap(),
+            Fetched::Modified(..)
+        ));
+    }
+}
@@ -1,10 +1,78 @@
 use crate::aws::load_aws_config;
 use anyhow::Result;
-#[derive(Clone, Debug, Eq, PartialEq)]
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+mod dns;
+pub use dns::{DnsNodeSource, DnsRecordType};
+mod file;
+pub use file::FileNodeSource;
+mod http;
+pub use http::HttpNodeSource;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Eq, PartialEq)]
 pub struct Node {
     pub public_ip: String,
 }
 
+/// Body of the file and HTTP sources: `{"nodes":[{"public_ip":"1.2.3.4"}]}`
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct NodeListDocument {
+    pub nodes: Vec<Node>,
+}
+
+/// Where the cluster manager finds the nodes of the cluster.
+#[async_trait]
+pub trait NodeSource: Send + Sync {
+    async fn get_nodes(&self) -> Result<Vec<Node>>;
+}
+
+/// Fixed list given by the config.
+pub struct StaticNodeSource {
+    nodes: Vec<Node>,
+}
+impl StaticNodeSource {
+    pub fn new<S: AsRef<str>>(ips: &[S]) -> Self {
+        StaticNodeSource {
+            nodes: ips
+                .iter()
+                .map(|v| Node {
+                    public_ip: v.as_ref().trim().to_string(),
+                })
+                .filter(|v| !v.public_ip.is_empty())
+                .collect(),
+        }
+    }
+}
+#[async_trait]
+impl NodeSource for StaticNodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        Ok(self.nodes.clone())
+    }
+}
+
+/// Running instances with the Stage and Role tags.
+pub struct Ec2NodeSource {
+    stage: String,
+    role: String,
+    region: String,
+}
+impl Ec2NodeSource {
+    pub fn new(stage: &str, role: &str, region: &str) -> Self {
+        Ec2NodeSource {
+            stage: stage.into(),
+            role: role.into(),
+            region: region.into(),
+        }
+    }
+}
+#[async_trait]
+impl NodeSource for Ec2NodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        load_nodes_from_ec2(&self.stage, &self.role, &self.region).await
+    }
+}
+
 pub async fn load_nodes_from_ec2(stage: &str, role: &str, region: &str) -> Result<Vec<Node>> {
     use aws_sdk_ec2 as ec2;
     use aws_sdk_ec2::model::Filter;
@@ -45,6 +113,19 @@ pub async fn load_nodes_from_ec2(stage: &str, role: &str, region: &str) -> Resul
 mod tests {
     use super::*;
     #[tokio::test]
+    async fn test_static_node_source() {
+        let src = StaticNodeSource::new(&["1.2.3.4", " 1.2.3.5", ""]);
+        let ips: Vec<String> = src
+            .get_nodes()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|v| v.public_ip)
+            .collect();
+        assert_eq!(ips, vec!["1.2.3.4", "1.2.3.5"]);
+    }
+    #[tokio::test]
+    #[ignore = "needs AWS credentials"]
     async fn test_load_nodes_from_ec2() {
         let res = load_nodes_from_ec2("dev", "CellServer", "ap-northeast-1").await;
         // println!("{:?}", res);
@@ -0,0 +1,169 @@
+use super::{Node, NodeSource};
+use anyhow::Result;
+use async_trait::async_trait;
+use std::net::SocketAddr;
+use trust_dns_resolver::config::{NameServerConfigGroup, ResolverConfig, ResolverOpts};
+use trust_dns_resolver::TokioAsyncResolver;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum DnsRecordType {
+    /// Addresses of the name.
+    A,
+    /// Addresses of the targets of the name. Ports are ignored.
+    Srv,
+}
+
+/// Nodes registered in DNS, ex. a headless service of Kubernetes or Consul.
+pub struct DnsNodeSource {
+    name: String,
+    record_type: DnsRecordType,
+    resolver: TokioAsyncResolver,
+}
+impl DnsNodeSource {
+    /// Uses the system config (/etc/resolv.conf) if `name_server` is None.
+    pub fn new(
+        name: &str,
+        record_type: DnsRecordType,
+        name_server: Option<SocketAddr>,
+    ) -> Result<Self> {
+        let resolver = match name_server {
+            Some(addr) => TokioAsyncResolver::tokio(
+                ResolverConfig::from_parts(
+                    None,
+                    vec![],
+                    NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true),
+                ),
+                ResolverOpts::default(),
+            )?,
+            None => TokioAsyncResolver::tokio_from_system_conf()?,
+        };
+        Ok(DnsNodeSource {
+            name: name.into(),
+            record_type,
+            resolver,
+        })
+    }
+
+    async fn lookup_ipv4(&self, name: &str) -> Result<Vec<String>> {
+        Ok(self
+            .resolver
+            .ipv4_lookup(name)
+            .await?
+            .iter()
+            .map(|v| v.to_string())
+            .collect())
+    }
+}
+#[async_trait]
+impl NodeSource for DnsNodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        let ips = match self.record_type {
+            DnsRecordType::A => self.lookup_ipv4(&self.name).await?,
+            DnsRecordType::Srv => {
+                let targets: Vec<String> = self
+                    .resolver
+                    .srv_lookup(self.name.as_str())
+                    .await?
+                    .iter()
+                    .map(|v| v.target().to_string())
+                    .collect();
+                let mut ips = Vec::new();
+                for target in targets {
+                    for ip in self.lookup_ipv4(&target).await? {
+                        if !ips.contains(&ip) {
+                            ips.push(ip);
+                        }
+                    }
+                }
+                ips
+            }
+        };
+        Ok(ips
+            .into_iter()
+            .map(|public_ip| Node { public_ip })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+    use tokio::net::UdpSocket;
+    use trust_dns_resolver::proto::op::{Message, MessageType, ResponseCode};
+    use trust_dns_resolver::proto::rr::rdata::SRV;
+    use trust_dns_resolver::proto::rr::{Name, RData, Record, RecordType};
+
+    /// Authoritative answers of the zone example.test on loopback.
+    async fn start_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, from)) = socket.recv_from(&mut buf).await {
+                let req = Message::from_vec(&buf[..n]).unwrap();
+                let query = req.queries()[0].clone();
+                let name = query.name().clone();
+                let mut res = Message::new();
+                res.set_id(req.id())
+                    .set_message_type(MessageType::Response)
+                    .set_authoritative(true);
+                res.add_query(query.clone());
+                let a =
+                    |ip: &str| Record::from_rdata(name.clone(), 60, RData::A(ip.parse().unwrap()));
+                let srv = |target: &str| {
+                    Record::from_rdata(
+                        name.clone(),
+                        60,
+                        RData::SRV(SRV::new(0, 0, 8000, Name::from_str(target).unwrap())),
+                    )
+                };
+                match (name.to_string().as_str(), query.query_type()) {
+                    ("nodes.example.test.", RecordType::A) => {
+                        res.add_answer(a("1.2.3.4"));
+                        res.add_answer(a("1.2.3.5"));
+                    }
+                    ("_hub._udp.example.test.", RecordType::SRV) => {
+                        res.add_answer(srv("n1.example.test."));
+                        res.add_answer(srv("n2.example.test."));
+                        res.add_answer(srv("n3.example.test."));
+                    }
+                    ("n1.example.test.", RecordType::A) => {
+                        res.add_answer(a("1.2.3.6"));
+                    }
+                    ("n2.example.test.", RecordType::A) | ("n3.example.test.", RecordType::A) => {
+                        res.add_answer(a("1.2.3.7"));
+                    }
+                    _ => {
+                        res.set_response_code(ResponseCode::NXDomain);
+                    }
+                }
+                socket.send_to(&res.to_vec().unwrap(), from).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    async fn get_ips(src: &DnsNodeSource) -> Result<Vec<String>> {
+        Ok(src
+            .get_nodes()
+            .await?
+            .into_iter()
+            .map(|v| v.public_ip)
+            .collect())
+    }
+
+    #[tokio::test]
+    async fn test_dns_node_source() {
+        let addr = start_server().await;
+        let src = DnsNodeSource::new("nodes.example.test.", DnsRecordType::A, Some(addr)).unwrap();
+        assert_eq!(get_ips(&src).await.unwrap(), vec!["1.2.3.4", "1.2.3.5"]);
+
+        let src =
+            DnsNodeSource::new("_hub._udp.example.test.", DnsRecordType::Srv, Some(addr)).unwrap();
+        assert_eq!(get_ips(&src).await.unwrap(), vec!["1.2.3.6", "1.2.3.7"]);
+
+        let src = DnsNodeSource::new("none.example.test.", DnsRecordType::A, Some(addr)).unwrap();
+        assert!(get_ips(&src).await.is_err());
+    }
+}
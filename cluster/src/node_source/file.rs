@@ -0,0 +1,94 @@
+use super::{Node, NodeListDocument, NodeSource};
+use anyhow::{Context, Result};
+use async_trait::async_trait;
+use parking_lot::Mutex;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// JSON or TOML (by the extension) file of NodeListDocument.
+/// The file is read again when its modification time changes.
+pub struct FileNodeSource {
+    path: PathBuf,
+    cache: Mutex<Option<(SystemTime, Vec<Node>)>>,
+}
+impl FileNodeSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileNodeSource {
+            path: path.into(),
+            cache: Mutex::new(None),
+        }
+    }
+}
+#[async_trait]
+impl NodeSource for FileNodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        let modified = tokio::fs::metadata(&self.path)
+            .await
+            .and_then(|v| v.modified())
+            .with_context(|| format!("{}", self.path.display()))?;
+        if let Some((t, nodes)) = self.cache.lock().as_ref() {
+            if *t == modified {
+                return Ok(nodes.clone());
+            }
+        }
+        let s = tokio::fs::read_to_string(&self.path)
+            .await
+            .with_context(|| format!("{}", self.path.display()))?;
+        let nodes = parse_node_list(&self.path, &s)?;
+        *self.cache.lock() = Some((modified, nodes.clone()));
+        Ok(nodes)
+    }
+}
+
+fn parse_node_list(path: &Path, s: &str) -> Result<Vec<Node>> {
+    let doc: NodeListDocument = match path.extension().and_then(|v| v.to_str()) {
+        Some("toml") => toml::from_str(s)?,
+        _ => serde_json::from_str(s)?,
+    };
+    Ok(doc.nodes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_parse_node_list() {
+        let nodes = parse_node_list(
+            Path::new("nodes.json"),
+            r#"{"nodes":[{"public_ip":"1.2.3.4"},{"public_ip":"1.2.3.5"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].public_ip, "1.2.3.5");
+
+        let nodes = parse_node_list(
+            Path::new("nodes.toml"),
+            r#"
+[[nodes]]
+public_ip = "1.2.3.4"
+"#,
+        )
+        .unwrap();
+        assert_eq!(nodes[0].public_ip, "1.2.3.4");
+
+        assert!(parse_node_list(Path::new("nodes.toml"), r#"{"nodes":[]}"#).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_file_node_source() {
+        let path = std::env::temp_dir().join(format!("nodes-{}.json", std::process::id()));
+        let src = FileNodeSource::new(&path);
+        assert!(src.get_nodes().await.is_err());
+
+        std::fs::write(&path, r#"{"nodes":[{"public_ip":"1.2.3.4"}]}"#).unwrap();
+        assert_eq!(src.get_nodes().await.unwrap()[0].public_ip, "1.2.3.4");
+
+        // some file systems have a coarse mtime
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        std::fs::write(&path, r#"{"nodes":[{"public_ip":"1.2.3.5"}]}"#).unwrap();
+        assert_eq!(src.get_nodes().await.unwrap()[0].public_ip, "1.2.3.5");
+        std::fs::remove_file(&path).unwrap();
+    }
+}
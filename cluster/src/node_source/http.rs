@@ -0,0 +1,67 @@
+use super::{Node, NodeListDocument, NodeSource};
+use anyhow::Result;
+use async_trait::async_trait;
+
+/// GET of a NodeListDocument JSON.
+pub struct HttpNodeSource {
+    url: String,
+    client: reqwest::Client,
+}
+impl HttpNodeSource {
+    pub fn new(url: &str) -> Self {
+        HttpNodeSource {
+            url: url.into(),
+            client: reqwest::Client::new(),
+        }
+    }
+}
+#[async_trait]
+impl NodeSource for HttpNodeSource {
+    async fn get_nodes(&self) -> Result<Vec<Node>> {
+        let res = self
+            .client
+            .get(&self.url)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(res.json::<NodeListDocument>().await?.nodes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers each connection with `status` and `body`.
+    async fn serve(status: &'static str, body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/nodes.json", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let res = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = stream.write_all(res.as_bytes()).await;
+            }
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_http_node_source() {
+        let url = serve("200 OK", r#"{"nodes":[{"public_ip":"1.2.3.4"}]}"#).await;
+        let nodes = HttpNodeSource::new(&url).get_nodes().await.unwrap();
+        assert_eq!(nodes.len(), 1);
+        assert_eq!(nodes[0].public_ip, "1.2.3.4");
+
+        let url = serve("404 Not Found", "{}").await;
+        assert!(HttpNodeSource::new(&url).get_nodes().await.is_err());
+    }
+}
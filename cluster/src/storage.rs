@@ -0,0 +1,100 @@
+use crate::aws::load_aws_config;
+use anyhow::Result;
+use async_trait::async_trait;
+
+pub struct S3Path {
+    pub bucket: String,
+    pub key: String,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct StoredObject {
+    pub body: Vec<u8>,
+    pub etag: Option<String>,
+}
+
+/// Object storage the cluster JSON is uploaded to.
+#[async_trait]
+pub trait ObjectStore: Send + Sync {
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &str,
+    ) -> Result<()>;
+    /// None if the ETag of the object is still `if_none_match`.
+    async fn fetch_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        if_none_match: Option<&str>,
+    ) -> Result<Option<StoredObject>>;
+}
+
+pub struct S3ObjectStore {
+    region: String,
+}
+impl S3ObjectStore {
+    pub fn new(region: &str) -> Self {
+        S3ObjectStore {
+            region: region.into(),
+        }
+    }
+    async fn create_client(&self) -> aws_sdk_s3::Client {
+        let config = load_aws_config(&Some(self.region.to_owned())).await;
+        aws_sdk_s3::Client::new(&config)
+    }
+}
+#[async_trait]
+impl ObjectStore for S3ObjectStore {
+    async fn put_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        body: Vec<u8>,
+        content_type: &str,
+    ) -> Result<()> {
+        use aws_sdk_s3::types::ByteStream;
+
+        self.create_client()
+            .await
+            .put_object()
+            .bucket(bucket.to_owned())
+            .key(key.to_owned())
+            .content_type(content_type.to_owned())
+            .body(ByteStream::from(body))
+            .send()
+            .await?;
+        Ok(())
+    }
+    async fn fetch_object(
+        &self,
+        bucket: &str,
+        key: &str,
+        if_none_match: Option<&str>,
+    ) -> Result<Option<StoredObject>> {
+        let client = self.create_client().await;
+        // 304 of GetObject is an error of the SDK, HEAD is cheaper to compare
+        if let Some(etag) = if_none_match {
+            let head = client
+                .head_object()
+                .bucket(bucket.to_owned())
+                .key(key.to_owned())
+                .send()
+                .await?;
+            if head.e_tag() == Some(etag) {
+                return Ok(None);
+            }
+        }
+        let res = client
+            .get_object()
+            .bucket(bucket.to_owned())
+            .key(key.to_owned())
+            .send()
+            .await?;
+        let etag = res.e_tag().map(|v| v.to_owned());
+        let body = res.body.collect().await?.into_bytes().to_vec();
+        Ok(Some(StoredObject { body, etag }))
+    }
+}
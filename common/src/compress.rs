@@ -1,49 +1,272 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use flate2::read::ZlibDecoder;
 use flate2::write::ZlibEncoder;
 use flate2::Compression;
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use std::io::prelude::*;
+use std::sync::atomic::{AtomicU64, Ordering};
+use thiserror::Error;
 
 const MIN_LENGTH: usize = 100;
 const COMP_LEVEL: u32 = 6;
+#[cfg(not(target_family = "wasm"))]
+const ZSTD_LEVEL: i32 = 3;
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_LGWIN: u32 = 22;
+const BROTLI_BUFFER_SIZE: usize = 4096;
+const RATIO_CHECK_MIN_LENGTH: usize = 64 * 1024;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Codec {
+    Zlib,
+    Zstd,
+    Brotli,
+    Lz4,
+}
+impl Codec {
+    pub const ALL: [Codec; 4] = [Codec::Zlib, Codec::Zstd, Codec::Brotli, Codec::Lz4];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Codec::Zlib => "zlib",
+            Codec::Zstd => "zstd",
+            Codec::Brotli => "brotli",
+            Codec::Lz4 => "lz4",
+        }
+    }
+    /// zstd is not available on wasm.
+    pub fn is_available(&self) -> bool {
+        !(cfg!(target_family = "wasm") && *self == Codec::Zstd)
+    }
+    fn index(&self) -> usize {
+        match self {
+            Codec::Zlib => 0,
+            Codec::Zstd => 1,
+            Codec::Brotli => 2,
+            Codec::Lz4 => 3,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DecompressLimits {
+    /// Maximum size of the decompressed data.
+    pub max_size: usize,
+    /// Maximum decompressed / compressed ratio. Only checked once the output
+    /// is larger than RATIO_CHECK_MIN_LENGTH.
+    pub max_ratio: usize,
+}
+impl Default for DecompressLimits {
+    fn default() -> Self {
+        DecompressLimits {
+            max_size: 4 * 1024 * 1024,
+            max_ratio: 100,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DecompressError {
+    #[error("decompressed size exceeds {0}")]
+    TooLarge(usize),
+    #[error("compression ratio exceeds {0}")]
+    RatioExceeded(usize),
+    #[error("{} is not available", .0.name())]
+    Unavailable(Codec),
+    #[error("corrupt data: {0}")]
+    Corrupt(#[from] std::io::Error),
+}
+impl DecompressError {
+    /// True if the data may be a decompression bomb.
+    pub fn is_limit_exceeded(&self) -> bool {
+        matches!(
+            self,
+            DecompressError::TooLarge(_) | DecompressError::RatioExceeded(_)
+        )
+    }
+}
+
+struct Stats {
+    compress_count: AtomicU64,
+    uncompressed_bytes: AtomicU64,
+    compressed_bytes: AtomicU64,
+    compress_usec: AtomicU64,
+    decompress_count: AtomicU64,
+    decompress_usec: AtomicU64,
+    limit_exceeded_count: AtomicU64,
+}
+#[allow(clippy::declare_interior_mutable_const)]
+const STATS_INIT: Stats = Stats {
+    compress_count: AtomicU64::new(0),
+    uncompressed_bytes: AtomicU64::new(0),
+    compressed_bytes: AtomicU64::new(0),
+    compress_usec: AtomicU64::new(0),
+    decompress_count: AtomicU64::new(0),
+    decompress_usec: AtomicU64::new(0),
+    limit_exceeded_count: AtomicU64::new(0),
+};
+static STATS: [Stats; 4] = [STATS_INIT; 4];
+
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct CodecStats {
+    /// Number of compressions that reduced the size.
+    pub compress_count: u64,
+    pub uncompressed_bytes: u64,
+    pub compressed_bytes: u64,
+    pub compress_usec: u64,
+    pub decompress_count: u64,
+    pub decompress_usec: u64,
+    /// Number of decompressions rejected by DecompressLimits.
+    pub limit_exceeded_count: u64,
+}
+impl CodecStats {
+    /// compressed / uncompressed. 0 if nothing has been compressed yet.
+    pub fn ratio(&self) -> f64 {
+        if self.uncompressed_bytes == 0 {
+            return 0.0;
+        }
+        self.compressed_bytes as f64 / self.uncompressed_bytes as f64
+    }
+}
+pub fn get_codec_stats(codec: Codec) -> CodecStats {
+    let s = &STATS[codec.index()];
+    CodecStats {
+        compress_count: s.compress_count.load(Ordering::Relaxed),
+        uncompressed_bytes: s.uncompressed_bytes.load(Ordering::Relaxed),
+        compressed_bytes: s.compressed_bytes.load(Ordering::Relaxed),
+        compress_usec: s.compress_usec.load(Ordering::Relaxed),
+        decompress_count: s.decompress_count.load(Ordering::Relaxed),
+        decompress_usec: s.decompress_usec.load(Ordering::Relaxed),
+        limit_exceeded_count: s.limit_exceeded_count.load(Ordering::Relaxed),
+    }
+}
+
+cfg_if::cfg_if! {
+    if #[cfg(target_family = "wasm")] {
+        struct Stopwatch;
+        impl Stopwatch {
+            fn start() -> Self {
+                Stopwatch
+            }
+            fn elapsed_usec(&self) -> u64 {
+                0
+            }
+        }
+    } else {
+        struct Stopwatch(std::time::Instant);
+        impl Stopwatch {
+            fn start() -> Self {
+                Stopwatch(std::time::Instant::now())
+            }
+            fn elapsed_usec(&self) -> u64 {
+                self.0.elapsed().as_micros() as u64
+            }
+        }
+    }
+}
 
 pub fn compress_if_needed(uncompressed: &mut Vec<u8>) -> Result<Option<Vec<u8>>> {
+    compress_with(Codec::Zlib, uncompressed)
+}
+pub fn decompress(compressed: &mut Vec<u8>) -> Result<Vec<u8>> {
+    decompress_with(Codec::Zlib, compressed)
+}
+
+/// Returns None if `uncompressed` is too small or does not get smaller.
+pub fn compress_with(codec: Codec, uncompressed: &[u8]) -> Result<Option<Vec<u8>>> {
     if uncompressed.len() < MIN_LENGTH {
         return Ok(None);
     }
-    let uncompressed = uncompressed as &[u8];
-    let before = uncompressed.len();
-    let mut e = ZlibEncoder::new(Vec::new(), Compression::new(COMP_LEVEL));
-    e.write_all(uncompressed)?;
-    let compressed = e.finish()?;
+    let sw = Stopwatch::start();
+    let compressed = match codec {
+        Codec::Zlib => {
+            let mut e = ZlibEncoder::new(Vec::new(), Compression::new(COMP_LEVEL));
+            e.write_all(uncompressed)?;
+            e.finish()?
+        }
+        #[cfg(not(target_family = "wasm"))]
+        Codec::Zstd => zstd::stream::encode_all(uncompressed, ZSTD_LEVEL)?,
+        #[cfg(target_family = "wasm")]
+        Codec::Zstd => return Err(anyhow!("zstd is not available")),
+        Codec::Brotli => {
+            let mut e = brotli::CompressorWriter::new(
+                Vec::new(),
+                BROTLI_BUFFER_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_LGWIN,
+            );
+            e.write_all(uncompressed)?;
+            e.flush()?;
+            e.into_inner()
+        }
+        Codec::Lz4 => {
+            let mut e = lz4_flex::frame::FrameEncoder::new(Vec::new());
+            e.write_all(uncompressed)?;
+            e.finish().map_err(|e| anyhow!("lz4: {}", e))?
+        }
+    };
+    let elapsed = sw.elapsed_usec();
 
+    let before = uncompressed.len();
     let after = compressed.len();
+    let s = &STATS[codec.index()];
+    s.compress_usec.fetch_add(elapsed, Ordering::Relaxed);
     if after >= before {
         return Ok(None);
     }
-    /* info!(
-        "copress: {} => {}     {}",
-        before,
-        after,
-        (after as f64) / (before as f64)
-    ); */
+    s.compress_count.fetch_add(1, Ordering::Relaxed);
+    s.uncompressed_bytes
+        .fetch_add(before as u64, Ordering::Relaxed);
+    s.compressed_bytes
+        .fetch_add(after as u64, Ordering::Relaxed);
     Ok(Some(compressed))
 }
-pub fn decompress(compressed: &mut Vec<u8>) -> Result<Vec<u8>> {
+/// Decompresses with the default DecompressLimits.
+pub fn decompress_with(codec: Codec, compressed: &[u8]) -> Result<Vec<u8>> {
+    Ok(decompress_with_limits(
+        codec,
+        compressed,
+        &Default::default(),
+    )?)
+}
+pub fn decompress_with_limits(
+    codec: Codec,
+    compressed: &[u8],
+    limits: &DecompressLimits,
+) -> Result<Vec<u8>, DecompressError> {
+    let sw = Stopwatch::start();
+    let reader: Box<dyn Read + '_> = match codec {
+        Codec::Zlib => Box::new(ZlibDecoder::new(compressed)),
+        #[cfg(not(target_family = "wasm"))]
+        Codec::Zstd => Box::new(zstd::stream::read::Decoder::new(compressed)?),
+        #[cfg(target_family = "wasm")]
+        Codec::Zstd => return Err(DecompressError::Unavailable(codec)),
+        Codec::Brotli => Box::new(brotli::Decompressor::new(compressed, BROTLI_BUFFER_SIZE)),
+        Codec::Lz4 => Box::new(lz4_flex::frame::FrameDecoder::new(compressed)),
+    };
+    let by_ratio = compressed
+        .len()
+        .saturating_mul(limits.max_ratio)
+        .max(RATIO_CHECK_MIN_LENGTH);
+    let allowed = limits.max_size.min(by_ratio);
+
     let mut decompressed = Vec::new();
-    let compressed = compressed as &[u8];
-    // let before = compressed.len();
-    let mut d = ZlibDecoder::new(compressed);
-    d.read_to_end(&mut decompressed)?;
-    // let after = decompressed.len();
-    /* info!(
-        "decopress: {} => {}     {}",
-        before,
-        after,
-        (after as f64) / (before as f64)
-    ); */
+    reader
+        .take(allowed as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+    let s = &STATS[codec.index()];
+    if decompressed.len() > allowed {
+        s.limit_exceeded_count.fetch_add(1, Ordering::Relaxed);
+        return Err(if allowed == limits.max_size {
+            DecompressError::TooLarge(limits.max_size)
+        } else {
+            DecompressError::RatioExceeded(limits.max_ratio)
+        });
+    }
+    s.decompress_count.fetch_add(1, Ordering::Relaxed);
+    s.decompress_usec
+        .fetch_add(sw.elapsed_usec(), Ordering::Relaxed);
     Ok(decompressed)
 }
 
@@ -68,4 +291,75 @@ mod tests {
         let res = res.unwrap();
         assert_eq!(src, res);
     }
+
+    #[test]
+    fn test_codecs() {
+        let src: Vec<u8> = (0..5000u32).map(|v| (v % 7) as u8).collect();
+        for codec in Codec::ALL.iter().filter(|v| v.is_available()) {
+            let before = get_codec_stats(*codec);
+            let compressed = compress_with(*codec, &src).unwrap().unwrap();
+            assert!(compressed.len() < src.len(), "{}", codec.name());
+            assert_eq!(decompress_with(*codec, &compressed).unwrap(), src);
+
+            let after = get_codec_stats(*codec);
+            assert_eq!(after.compress_count, before.compress_count + 1);
+            assert_eq!(after.decompress_count, before.decompress_count + 1);
+            assert!(after.ratio() > 0.0 && after.ratio() < 1.0);
+        }
+        assert!(decompress_with(Codec::Lz4, &src).is_err());
+    }
+
+    #[test]
+    fn test_decompress_limits() {
+        let src = vec![0u8; 1024 * 1024];
+        for codec in Codec::ALL.iter().filter(|v| v.is_available()) {
+            let compressed = compress_with(*codec, &src).unwrap().unwrap();
+            let before = get_codec_stats(*codec);
+
+            let res = decompress_with_limits(
+                *codec,
+                &compressed,
+                &DecompressLimits {
+                    max_size: src.len() - 1,
+                    max_ratio: usize::MAX,
+                },
+            );
+            assert!(
+                matches!(res, Err(DecompressError::TooLarge(_))),
+                "{}",
+                codec.name()
+            );
+
+            let res = decompress_with_limits(
+                *codec,
+                &compressed,
+                &DecompressLimits {
+                    max_size: usize::MAX,
+                    max_ratio: 2,
+                },
+            );
+            assert!(
+                matches!(res, Err(DecompressError::RatioExceeded(_))),
+                "{}",
+                codec.name()
+            );
+            assert!(res.unwrap_err().is_limit_exceeded());
+
+            let res = decompress_with_limits(
+                *codec,
+                &compressed,
+                &DecompressLimits {
+                    max_size: src.len(),
+                    max_ratio: usize::MAX,
+                },
+            );
+            assert_eq!(res.unwrap(), src);
+
+            let after = get_codec_stats(*codec);
+            assert_eq!(after.limit_exceeded_count, before.limit_exceeded_count + 2);
+        }
+
+        let res = decompress_with_limits(Codec::Zlib, &src[..200], &Default::default());
+        assert!(matches!(res, Err(DecompressError::Corrupt(_))));
+    }
 }
@@ -1,8 +1,15 @@
+use crate::cluster::{NodeListStoreKind, NodeSourceKind};
+use crate::dns::DnsProviderKind;
+use crate::state::OverflowPolicy;
+use crate::swarm::{
+    AreaOfInterest, CrossWorldPolicy, RoutingStrategy, WorldAreaOfInterest, WorldRoutingStrategy,
+};
 use anyhow::Result;
 use clap::Parser;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use std::fmt;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -23,14 +30,127 @@ pub struct Args {
     pub udp_port: u16,
     #[clap(long, default_value = "9098")]
     pub status_port: u16,
+    /// /topology of the status server shows session ids instead of serial numbers
+    #[clap(long)]
+    pub status_topology_raw: bool,
+    /// Port of SwarmBackendService (gRPC). Not served if omitted.
+    #[clap(long, env)]
+    pub grpc_port: Option<u16>,
+    /// PEM certificate chain of the gRPC server. Plaintext if omitted
+    #[clap(long, env, requires = "grpc_tls_key")]
+    pub grpc_tls_cert: Option<String>,
+    /// PEM private key of the gRPC server
+    #[clap(long, env, requires = "grpc_tls_cert")]
+    pub grpc_tls_key: Option<String>,
+    /// Streams opened per second per remote address of SwarmBackendService
+    #[clap(long, default_value = "1")]
+    pub grpc_stream_rate_limit: f64,
+    #[clap(long, default_value = "10")]
+    pub grpc_stream_rate_limit_burst: f64,
 
     #[clap(long)]
     pub max_connections: Option<usize>,
     #[clap(long)]
     pub max_connections_by_url: Option<usize>,
 
-    #[clap(long, default_value = "10")]
+    #[clap(long, default_value = "10", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
     pub max_routing_results: usize,
+    /// How to choose ExchangeRoutingInfo results when a world has more members
+    #[clap(long, value_enum, default_value = "random")]
+    pub routing_strategy: RoutingStrategy,
+    /// Strategy of a world: <url>=<strategy>
+    #[clap(long)]
+    pub routing_strategy_by_url: Vec<WorldRoutingStrategy>,
+    /// Return the peers near the requester's position: nearest or a radius
+    #[clap(long)]
+    pub area_of_interest: Option<AreaOfInterest>,
+    /// Area of interest of a world: <url>=<nearest|radius>
+    #[clap(long)]
+    pub area_of_interest_by_url: Vec<WorldAreaOfInterest>,
+
+    /// Relations of a reported RoutingInfo deeper than this are replaced with their count
+    #[clap(long, default_value = "4")]
+    pub routing_info_max_depth: u32,
+    /// Relations of a node in a reported RoutingInfo beyond this are dropped
+    #[clap(long, default_value = "100")]
+    pub routing_info_max_breadth: usize,
+    /// Upper limit of the absolute value of each coordinate of a position
+    #[clap(long, default_value = "1000000000")]
+    pub routing_info_max_position: f64,
+    /// Upper limit of the encoded size of a reported RoutingInfo
+    #[clap(long, default_value = "65536")]
+    pub routing_info_max_size: usize,
+
+    /// The hub asks Normal nodes to become gateways in a world with fewer. 0: disabled
+    #[clap(long, default_value = "0")]
+    pub min_gateways: usize,
+    /// A gateway connected to the tracker for less than this is not healthy
+    #[clap(long, default_value = "60")]
+    pub gateway_min_uptime_secs: u64,
+    #[clap(long, default_value = "30")]
+    pub gateway_check_interval_secs: i64,
+
+    /// Suggest peers to connect to for the members of a partitioned part of a world
+    #[clap(long)]
+    pub partition_merge: bool,
+    /// Also how often the world health metrics are updated
+    #[clap(long, default_value = "30")]
+    pub partition_check_interval_secs: i64,
+    #[clap(long, default_value = "3")]
+    pub partition_merge_max_peers: usize,
+
+    #[clap(long, default_value = "50")]
+    pub rpc_rate_limit: f64,
+    #[clap(long, default_value = "100")]
+    pub rpc_rate_limit_burst: f64,
+
+    /// Maximum size of a decompressed RPC param
+    #[clap(long, default_value = "4194304")]
+    pub max_decompressed_size: usize,
+    /// Maximum decompressed / compressed ratio of an RPC param
+    #[clap(long, default_value = "100")]
+    pub max_compression_ratio: usize,
+    /// Maximum size of a fragment sent by a client, advertised by Negotiate (1024 - 65535).
+    /// A fragmented message is limited by --max-decompressed-size
+    #[clap(long, default_value = "16384")]
+    pub max_fragment_size: usize,
+    /// Maximum number of fragmented messages being reassembled at once for a client
+    #[clap(long, default_value = "2")]
+    pub max_pending_fragmented_messages: usize,
+
+    /// Maximum number of messages waiting to be sent to a client
+    #[clap(long, default_value = "256")]
+    pub send_queue_capacity: usize,
+    #[clap(long, default_value = "1048576")]
+    pub send_queue_max_buffered_amount: usize,
+    #[clap(long, value_enum, default_value = "drop-oldest")]
+    pub send_queue_overflow_policy: OverflowPolicy,
+
+    /// Whether Transfer may forward to a session in another world
+    #[clap(long, value_enum, default_value = "deny")]
+    pub cross_world_transfer: CrossWorldPolicy,
+    /// Upper limit of the ttl of Transfer
+    #[clap(long, default_value = "8")]
+    pub transfer_max_hops: u32,
+    /// Duplicated Transfers within this period are dropped
+    #[clap(long, default_value = "300")]
+    pub transfer_replay_window_secs: u64,
+    /// The oldest Transfers are forgotten while the cache is full
+    #[clap(long, default_value = "100000")]
+    pub transfer_replay_cache_size: usize,
+    /// Transfers remembered per sender, a sender over it pushes out its own oldest ones
+    #[clap(long, default_value = "10000")]
+    pub transfer_replay_sender_quota: usize,
+
+    /// Multicasts per second per client
+    #[clap(long, default_value = "1")]
+    pub multicast_rate_limit: f64,
+    #[clap(long, default_value = "5")]
+    pub multicast_rate_limit_burst: f64,
+    #[clap(long, default_value = "16384")]
+    pub multicast_max_payload_size: usize,
+    #[clap(long, default_value = "1000")]
+    pub multicast_max_recipients: usize,
 
     #[clap(long, env)]
     pub public_ip: Option<String>,
@@ -38,10 +158,27 @@ pub struct Args {
     #[clap(long, default_value = "stun:stun.l.google.com:19302")]
     pub ice_servers: Vec<String>,
 
+    /// Where the A/AAAA records of http_host and the cluster are registered
+    #[clap(long, env, value_enum, default_value = "cloudflare")]
+    pub dns_provider: DnsProviderKind,
+    /// Scoped API token with Zone:Read and DNS:Edit, preferred to the global API key
+    #[clap(long, env)]
+    pub cloudflare_api_token: Option<String>,
     #[clap(long, env)]
     pub cloudflare_api_key: Option<String>,
     #[clap(long, env)]
     pub cloudflare_email: Option<String>,
+    /// Primary name server accepting dynamic updates, ex. 10.0.0.53:53
+    #[clap(long, env)]
+    pub rfc2136_server: Option<SocketAddr>,
+    /// TSIG key of the updates and zone transfers. Unsigned if omitted
+    #[clap(long, env)]
+    pub rfc2136_key_name: Option<String>,
+    #[clap(long, env, default_value = "hmac-sha256")]
+    pub rfc2136_key_algorithm: String,
+    /// Base64 secret of the TSIG key
+    #[clap(long, env)]
+    pub rfc2136_key_secret: Option<String>,
 
     #[clap(long, env)]
     pub aws_secretsmanager_name: Option<String>,
@@ -64,8 +201,23 @@ pub struct Args {
     #[clap(long, env)]
     pub http_log_path: Option<String>,
 
+    /// URL of the node list for the http store and source
     #[clap(long, env)]
     pub cluster_node_list_url: Option<String>,
+    /// Where the cluster manager puts the node list
+    #[clap(long, env, value_enum, default_value = "s3")]
+    pub cluster_node_list_store: NodeListStoreKind,
+    /// Where the nodes fetch the node list from
+    #[clap(long, env, value_enum, default_value = "http")]
+    pub cluster_node_list_source: NodeListStoreKind,
+    /// Node list file for the file store and source
+    #[clap(long, env)]
+    pub cluster_node_list_file: Option<PathBuf>,
+    #[clap(long, env, default_value = "60")]
+    pub cluster_node_list_interval_secs: u64,
+    /// How long the last node list is used while fetches fail. Forever if omitted
+    #[clap(long, env)]
+    pub cluster_node_list_max_stale_secs: Option<u64>,
 
     pub cluster_node_host: Option<String>,
 
@@ -73,6 +225,27 @@ pub struct Args {
     pub cluster_node_role: Option<String>,
     #[clap(long, env)]
     pub cluster_node_stage: Option<String>,
+    /// Where the cluster manager finds the nodes. ec2 uses the role and stage tags
+    #[clap(long, env, value_enum, default_value = "ec2")]
+    pub cluster_node_source: NodeSourceKind,
+    /// Public IPs of the nodes for the static source
+    #[clap(long, env, value_delimiter = ',')]
+    pub cluster_nodes: Vec<String>,
+    /// JSON or TOML file for the file source: {"nodes":[{"public_ip":"1.2.3.4"}]}
+    #[clap(long, env)]
+    pub cluster_node_file: Option<PathBuf>,
+    /// Name to look up for the dns-a and dns-srv sources
+    #[clap(long, env)]
+    pub cluster_node_dns_name: Option<String>,
+    /// Name server of the dns sources. The system config if omitted
+    #[clap(long, env)]
+    pub cluster_node_dns_server: Option<SocketAddr>,
+    /// URL of the JSON for the http source, same format as the file source
+    #[clap(long, env)]
+    pub cluster_node_url: Option<String>,
+    /// Polls the node source and updates the cluster when the nodes change
+    #[clap(long, env)]
+    pub cluster_update_interval_secs: Option<u64>,
     #[clap(long, env)]
     pub cluster_json_s3_bucket: Option<String>,
     #[clap(long, env)]
@@ -139,10 +312,90 @@ impl fmt::Debug for Args {
             .field("http_port", &self.http_port)
             .field("udp_port", &self.udp_port)
             .field("status_port", &self.status_port)
+            .field("status_topology_raw", &self.status_topology_raw)
+            .field("grpc_port", &self.grpc_port)
+            .field("grpc_tls_cert", &self.grpc_tls_cert)
+            .field("grpc_tls_key", &self.grpc_tls_key)
+            .field("grpc_stream_rate_limit", &self.grpc_stream_rate_limit)
+            .field(
+                "grpc_stream_rate_limit_burst",
+                &self.grpc_stream_rate_limit_burst,
+            )
             .field("max_connections", &self.max_connections)
             .field("max_connections_by_url", &self.max_connections_by_url)
+            .field("max_routing_results", &self.max_routing_results)
+            .field("routing_strategy", &self.routing_strategy)
+            .field("routing_strategy_by_url", &self.routing_strategy_by_url)
+            .field("area_of_interest", &self.area_of_interest)
+            .field("area_of_interest_by_url", &self.area_of_interest_by_url)
+            .field("routing_info_max_depth", &self.routing_info_max_depth)
+            .field("routing_info_max_breadth", &self.routing_info_max_breadth)
+            .field("routing_info_max_position", &self.routing_info_max_position)
+            .field("routing_info_max_size", &self.routing_info_max_size)
+            .field("min_gateways", &self.min_gateways)
+            .field("gateway_min_uptime_secs", &self.gateway_min_uptime_secs)
+            .field(
+                "gateway_check_interval_secs",
+                &self.gateway_check_interval_secs,
+            )
+            .field("partition_merge", &self.partition_merge)
+            .field(
+                "partition_check_interval_secs",
+                &self.partition_check_interval_secs,
+            )
+            .field("partition_merge_max_peers", &self.partition_merge_max_peers)
+            .field("rpc_rate_limit", &self.rpc_rate_limit)
+            .field("rpc_rate_limit_burst", &self.rpc_rate_limit_burst)
+            .field("max_decompressed_size", &self.max_decompressed_size)
+            .field("max_compression_ratio", &self.max_compression_ratio)
+            .field("max_fragment_size", &self.max_fragment_size)
+            .field(
+                "max_pending_fragmented_messages",
+                &self.max_pending_fragmented_messages,
+            )
+            .field("send_queue_capacity", &self.send_queue_capacity)
+            .field(
+                "send_queue_max_buffered_amount",
+                &self.send_queue_max_buffered_amount,
+            )
+            .field(
+                "send_queue_overflow_policy",
+                &self.send_queue_overflow_policy,
+            )
+            .field("cross_world_transfer", &self.cross_world_transfer)
+            .field("transfer_max_hops", &self.transfer_max_hops)
+            .field(
+                "transfer_replay_window_secs",
+                &self.transfer_replay_window_secs,
+            )
+            .field(
+                "transfer_replay_cache_size",
+                &self.transfer_replay_cache_size,
+            )
+            .field(
+                "transfer_replay_sender_quota",
+                &self.transfer_replay_sender_quota,
+            )
+            .field("multicast_rate_limit", &self.multicast_rate_limit)
+            .field(
+                "multicast_rate_limit_burst",
+                &self.multicast_rate_limit_burst,
+            )
+            .field(
+                "multicast_max_payload_size",
+                &self.multicast_max_payload_size,
+            )
+            .field("multicast_max_recipients", &self.multicast_max_recipients)
             .field("public_ip", &self.public_ip)
             .field("ice_servers", &self.ice_servers)
+            .field("dns_provider", &self.dns_provider)
+            .field(
+                "cloudflare_api_token",
+                &self
+                    .cloudflare_api_token
+                    .as_ref()
+                    .map(|v| "*".repeat(v.len())),
+            )
             .field(
                 "cloudflare_api_key",
                 &self
@@ -151,6 +404,16 @@ impl fmt::Debug for Args {
                     .map(|v| "*".repeat(v.len())),
             )
             .field("cloudflare_email", &self.cloudflare_email)
+            .field("rfc2136_server", &self.rfc2136_server)
+            .field("rfc2136_key_name", &self.rfc2136_key_name)
+            .field("rfc2136_key_algorithm", &self.rfc2136_key_algorithm)
+            .field(
+                "rfc2136_key_secret",
+                &self
+                    .rfc2136_key_secret
+                    .as_ref()
+                    .map(|v| "*".repeat(v.len())),
+            )
             .field(
                 "aws_secretsmanager_enabled",
                 &self.aws_secretsmanager_enabled,
@@ -162,9 +425,30 @@ impl fmt::Debug for Args {
             .field("access_log_path", &self.access_log_path)
             .field("http_log_path", &self.http_log_path)
             .field("cluster_node_list_url", &self.cluster_node_list_url)
+            .field("cluster_node_list_store", &self.cluster_node_list_store)
+            .field("cluster_node_list_source", &self.cluster_node_list_source)
+            .field("cluster_node_list_file", &self.cluster_node_list_file)
+            .field(
+                "cluster_node_list_interval_secs",
+                &self.cluster_node_list_interval_secs,
+            )
+            .field(
+                "cluster_node_list_max_stale_secs",
+                &self.cluster_node_list_max_stale_secs,
+            )
             .field("cluster_node_host", &self.cluster_node_host)
             .field("cluster_node_role", &self.cluster_node_role)
             .field("cluster_node_stage", &self.cluster_node_stage)
+            .field("cluster_node_source", &self.cluster_node_source)
+            .field("cluster_nodes", &self.cluster_nodes)
+            .field("cluster_node_file", &self.cluster_node_file)
+            .field("cluster_node_dns_name", &self.cluster_node_dns_name)
+            .field("cluster_node_dns_server", &self.cluster_node_dns_server)
+            .field("cluster_node_url", &self.cluster_node_url)
+            .field(
+                "cluster_update_interval_secs",
+                &self.cluster_update_interval_secs,
+            )
             .field("cluster_json_s3_bucket", &self.cluster_json_s3_bucket)
             .field("cluster_json_s3_key", &self.cluster_json_s3_key)
             .finish()
@@ -1,11 +1,121 @@
 use crate::args::Args;
 use crate::state::SharedState;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use axum::response::{IntoResponse, Redirect};
 #[allow(unused_imports)]
 use log::{debug, error, info, trace, warn};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::time::sleep;
+use verse_cluster::manager::{Manager, S3Path};
+use verse_cluster::node_list_store::*;
+use verse_cluster::node_source::*;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NodeSourceKind {
+    Ec2,
+    Static,
+    File,
+    DnsA,
+    DnsSrv,
+    Http,
+}
+
+pub fn create_node_source(args: &Args) -> Result<Arc<dyn NodeSource>> {
+    fn required<'a>(v: &'a Option<String>, name: &str) -> Result<&'a str> {
+        v.as_deref()
+            .ok_or_else(|| anyhow!("--{} is required by the node source", name))
+    }
+    let dns = |record_type| -> Result<Arc<dyn NodeSource>> {
+        Ok(Arc::new(DnsNodeSource::new(
+            required(&args.cluster_node_dns_name, "cluster-node-dns-name")?,
+            record_type,
+            args.cluster_node_dns_server,
+        )?))
+    };
+    Ok(match args.cluster_node_source {
+        NodeSourceKind::Ec2 => Arc::new(Ec2NodeSource::new(
+            required(&args.cluster_node_stage, "cluster-node-stage")?,
+            required(&args.cluster_node_role, "cluster-node-role")?,
+            required(&args.aws_ec2_region, "aws-ec2-region")?,
+        )),
+        NodeSourceKind::Static => Arc::new(StaticNodeSource::new(&args.cluster_nodes)),
+        NodeSourceKind::File => Arc::new(FileNodeSource::new(
+            args.cluster_node_file
+                .clone()
+                .ok_or_else(|| anyhow!("--cluster-node-file is required by the node source"))?,
+        )),
+        NodeSourceKind::DnsA => dns(DnsRecordType::A)?,
+        NodeSourceKind::DnsSrv => dns(DnsRecordType::Srv)?,
+        NodeSourceKind::Http => Arc::new(HttpNodeSource::new(required(
+            &args.cluster_node_url,
+            "cluster-node-url",
+        )?)),
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum NodeListStoreKind {
+    /// cluster_json_s3_bucket and cluster_json_s3_key
+    S3,
+    /// cluster_node_list_url
+    Http,
+    /// cluster_node_list_file
+    File,
+}
+
+/// None if the location of `kind` is not configured.
+pub fn create_node_list_store(
+    args: &Args,
+    kind: NodeListStoreKind,
+) -> Result<Option<Arc<dyn NodeListStore>>> {
+    Ok(match kind {
+        NodeListStoreKind::S3 => {
+            let (Some(bucket), Some(key)) = (
+                args.cluster_json_s3_bucket.as_ref(),
+                args.cluster_json_s3_key.as_ref(),
+            ) else {
+                return Ok(None);
+            };
+            let region = args
+                .aws_ec2_region
+                .as_ref()
+                .ok_or_else(|| anyhow!("--aws-ec2-region is required by the s3 node list"))?;
+            Some(Arc::new(ObjectNodeListStore::s3(
+                region,
+                S3Path {
+                    bucket: bucket.clone(),
+                    key: key.clone(),
+                },
+            )))
+        }
+        NodeListStoreKind::Http => args
+            .cluster_node_list_url
+            .as_ref()
+            .map(|v| Arc::new(HttpNodeListStore::new(v)) as Arc<dyn NodeListStore>),
+        NodeListStoreKind::File => args
+            .cluster_node_list_file
+            .as_ref()
+            .map(|v| Arc::new(FileNodeListStore::new(v)) as Arc<dyn NodeListStore>),
+    })
+}
+
+/// Updates the cluster every `cluster_update_interval_secs` if the nodes changed.
+pub fn start_manager(args: &Args, manager: Arc<Manager>) {
+    let Some(interval) = args.cluster_update_interval_secs else {
+        return;
+    };
+    tokio::task::spawn(async move {
+        loop {
+            sleep(tokio::time::Duration::from_secs(interval)).await;
+            match manager.update_if_changed().await {
+                Ok(true) => info!("cluster updated"),
+                Ok(false) => {}
+                Err(e) => warn!("failed to update cluster: {:?}", e),
+            }
+        }
+    });
+}
 
 pub fn create_client(args: &Args) -> Option<Arc<verse_cluster::Client>> {
     let Some(cluster_host) = args.http_host.as_ref() else {
@@ -22,16 +132,21 @@ pub fn create_client(args: &Args) -> Option<Arc<verse_cluster::Client>> {
 }
 
 pub async fn start_client(args: &Args, app_state: SharedState) -> Result<()> {
-    let Some(node_list_url) = args.cluster_node_list_url.clone() else {
+    let Some(store) = create_node_list_store(args, args.cluster_node_list_source)? else {
         return Ok(());
     };
     if app_state.cluster_client.is_none() {
         warn!("cluster client is none");
         return Ok(());
     };
+    let cache = Arc::new(NodeListCache::new(
+        store,
+        args.cluster_node_list_max_stale_secs
+            .map(Duration::from_secs),
+    ));
 
     for n in 0..5 {
-        match load_node_list(&node_list_url, app_state.clone()).await {
+        match load_node_list(&cache, &app_state).await {
             Ok(_) => break,
             Err(e) => {
                 warn!("failed to load node list: {:?}", e);
@@ -40,11 +155,12 @@ pub async fn start_client(args: &Args, app_state: SharedState) -> Result<()> {
         }
     }
 
+    let interval = args.cluster_node_list_interval_secs;
     tokio::task::spawn({
         async move {
             loop {
-                sleep(tokio::time::Duration::from_secs(60)).await;
-                if let Err(e) = load_node_list(&node_list_url, app_state.clone()).await {
+                sleep(tokio::time::Duration::from_secs(interval)).await;
+                if let Err(e) = load_node_list(&cache, &app_state).await {
                     warn!("failed to update node list: {:?}", e);
                 }
             }
@@ -54,20 +170,26 @@ pub async fn start_client(args: &Args, app_state: SharedState) -> Result<()> {
     Ok(())
 }
 
-async fn load_node_list(url: &str, app_state: SharedState) -> Result<()> {
-    let client = reqwest::Client::new();
-    let res = client.get(url).send().await?;
-    let data = res.json::<verse_cluster::data::NodeListData>().await?;
+/// Fetches the node list if it changed. A stale list is dropped even when the fetch fails.
+async fn load_node_list(cache: &NodeListCache, app_state: &SharedState) -> Result<()> {
+    let res = cache.refresh().await;
     if let Some(cluster_client) = app_state.cluster_client.as_ref() {
-        if let Some(exists) = cluster_client.get_node_list() {
-            if *exists == data.nodes {
-                return Ok(());
+        match cache.get() {
+            Some(data) => {
+                if cluster_client.get_node_list().as_deref() != Some(&data.nodes) {
+                    cluster_client.set_node_list(data);
+                    warn!("node list updated");
+                }
+            }
+            None => {
+                if cluster_client.get_node_list().is_some() {
+                    cluster_client.clear_node_list();
+                    warn!("node list is stale, cleared");
+                }
             }
         }
-        cluster_client.set_node_list(data);
-        warn!("node list updated");
     }
-    Ok(())
+    res.map(|_| ())
 }
 
 pub fn redirect_if_needed(
@@ -95,3 +217,73 @@ pub fn redirect_if_needed(
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    #[tokio::test]
+    async fn test_create_node_source() {
+        let args = Args::parse_from([
+            "hubserv",
+            "--cluster-node-source",
+            "static",
+            "--cluster-nodes",
+            "1.2.3.4,1.2.3.5",
+        ]);
+        let nodes = create_node_source(&args)
+            .unwrap()
+            .get_nodes()
+            .await
+            .unwrap();
+        assert_eq!(nodes.len(), 2);
+        assert_eq!(nodes[1].public_ip, "1.2.3.5");
+
+        for v in ["ec2", "file", "dns-a", "dns-srv", "http"] {
+            let args = Args::parse_from(["hubserv", "--cluster-node-source", v]);
+            assert!(create_node_source(&args).is_err(), "{}", v);
+        }
+        let args = Args::parse_from([
+            "hubserv",
+            "--cluster-node-source",
+            "dns-srv",
+            "--cluster-node-dns-name",
+            "_hub._udp.example.test",
+            "--cluster-node-dns-server",
+            "127.0.0.1:53",
+        ]);
+        assert!(create_node_source(&args).is_ok());
+    }
+
+    #[test]
+    fn test_create_node_list_store() {
+        let args = Args::parse_from(["hubserv"]);
+        for kind in [
+            NodeListStoreKind::S3,
+            NodeListStoreKind::Http,
+            NodeListStoreKind::File,
+        ] {
+            assert!(create_node_list_store(&args, kind).unwrap().is_none());
+        }
+
+        let args = Args::parse_from([
+            "hubserv",
+            "--cluster-json-s3-bucket",
+            "b",
+            "--cluster-json-s3-key",
+            "cluster.json",
+            "--cluster-node-list-url",
+            "http://127.0.0.1/cluster.json",
+            "--cluster-node-list-file",
+            "cluster.json",
+        ]);
+        assert!(create_node_list_store(&args, NodeListStoreKind::S3).is_err());
+        assert!(create_node_list_store(&args, NodeListStoreKind::Http)
+            .unwrap()
+            .is_some());
+        assert!(create_node_list_store(&args, NodeListStoreKind::File)
+            .unwrap()
+            .is_some());
+    }
+}
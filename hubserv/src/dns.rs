@@ -1,88 +1,94 @@
-use anyhow::Result;
-use cloudflare::endpoints::{dns, zone};
-use cloudflare::framework::{
-    async_api::{ApiClient, Client},
-    auth::Credentials,
-    Environment, HttpApiClientConfig,
-};
-use std::net::Ipv4Addr;
+use crate::args::Args;
+use anyhow::{anyhow, Result};
+use std::sync::Arc;
+use verse_cluster::dns_provider::*;
 
-pub async fn set_arecord(
-    name: &str,
-    ip: &str,
-    cf_email: &str,
-    cf_api_key: &str,
-    proxy: bool,
-) -> Result<()> {
-    let credentials = Credentials::UserAuthKey {
-        email: cf_email.to_string(),
-        key: cf_api_key.to_string(),
-    };
-    let client = Client::new(
-        credentials,
-        HttpApiClientConfig::default(),
-        Environment::Production,
-    )?;
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum DnsProviderKind {
+    Cloudflare,
+    /// Dynamic updates of RFC 2136, ex. BIND or Knot
+    Rfc2136,
+}
 
-    let ar: Vec<&str> = name.splitn(2, '.').collect();
-    let domain = ar[ar.len() - 1].to_string();
+/// None if the provider is not configured.
+pub fn create_dns_provider(args: &Args) -> Result<Option<Arc<dyn DnsProvider>>> {
+    Ok(match args.dns_provider {
+        DnsProviderKind::Cloudflare => {
+            let auth = if let Some(token) = args.cloudflare_api_token.as_ref() {
+                CfAuthInfo::ApiToken(token.clone())
+            } else if let (Some(email), Some(api_key)) = (
+                args.cloudflare_email.as_ref(),
+                args.cloudflare_api_key.as_ref(),
+            ) {
+                CfAuthInfo::ApiKey {
+                    email: email.clone(),
+                    api_key: api_key.clone(),
+                }
+            } else {
+                return Ok(None);
+            };
+            Some(Arc::new(CfDnsProvider::new(auth)))
+        }
+        DnsProviderKind::Rfc2136 => {
+            let server = args
+                .rfc2136_server
+                .ok_or_else(|| anyhow!("--rfc2136-server is required by rfc2136"))?;
+            let key = match (
+                args.rfc2136_key_name.as_ref(),
+                args.rfc2136_key_secret.as_ref(),
+            ) {
+                (Some(name), Some(secret)) => Some(TsigKey::new(
+                    name,
+                    &args.rfc2136_key_algorithm,
+                    base64::decode(secret)?,
+                )?),
+                (None, None) => None,
+                _ => {
+                    return Err(anyhow!(
+                        "--rfc2136-key-name and --rfc2136-key-secret are required together"
+                    ))
+                }
+            };
+            Some(Arc::new(Rfc2136DnsProvider::new(server, key)))
+        }
+    })
+}
 
-    let res = client
-        .request(&zone::ListZones {
-            params: zone::ListZonesParams {
-                name: Some(domain.clone()),
-                ..Default::default()
-            },
-        })
-        .await?;
-    if res.result.len() != 1 {
-        return Err(anyhow::anyhow!("unsupported host name: {}", domain));
-    }
-    let zone_id = res.result[0].id.to_owned();
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
 
-    let ip: Ipv4Addr = ip.parse()?;
+    #[test]
+    fn test_create_dns_provider() {
+        let args = Args::parse_from(["hubserv"]);
+        assert!(create_dns_provider(&args).unwrap().is_none());
+        let args = Args::parse_from(["hubserv", "--cloudflare-api-token", "t"]);
+        assert!(create_dns_provider(&args).unwrap().is_some());
 
-    let res: Vec<dns::DnsRecord> = client
-        .request(&dns::ListDnsRecords {
-            zone_identifier: &zone_id,
-            params: dns::ListDnsRecordsParams {
-                name: Some(name.to_string()),
-                // record_type: Some(dns::DnsContent::A { content: ip }),
-                ..Default::default()
-            },
-        })
-        .await?
-        .result
-        .into_iter()
-        .filter(|v| matches!(v.content, dns::DnsContent::A { content: _ }))
-        .collect();
-    if res.is_empty() {
-        client
-            .request(&dns::CreateDnsRecord {
-                zone_identifier: &zone_id,
-                params: dns::CreateDnsRecordParams {
-                    proxied: Some(proxy),
-                    name: ar[0],
-                    content: dns::DnsContent::A { content: ip },
-                    ttl: None,
-                    priority: None,
-                },
-            })
-            .await?;
-    } else {
-        client
-            .request(&dns::UpdateDnsRecord {
-                zone_identifier: &zone_id,
-                identifier: &res[0].id,
-                params: dns::UpdateDnsRecordParams {
-                    proxied: Some(proxy),
-                    name: ar[0],
-                    content: dns::DnsContent::A { content: ip },
-                    ttl: None,
-                },
-            })
-            .await?;
+        let args = Args::parse_from(["hubserv", "--dns-provider", "rfc2136"]);
+        assert!(create_dns_provider(&args).is_err());
+        let args = Args::parse_from([
+            "hubserv",
+            "--dns-provider",
+            "rfc2136",
+            "--rfc2136-server",
+            "127.0.0.1:53",
+            "--rfc2136-key-name",
+            "hub-key",
+        ]);
+        assert!(create_dns_provider(&args).is_err());
+        let args = Args::parse_from([
+            "hubserv",
+            "--dns-provider",
+            "rfc2136",
+            "--rfc2136-server",
+            "127.0.0.1:53",
+            "--rfc2136-key-name",
+            "hub-key",
+            "--rfc2136-key-secret",
+            "MDEyMzQ1Njc4OWFiY2RlZg==",
+        ]);
+        assert!(create_dns_provider(&args).unwrap().is_some());
     }
-
-    Ok(())
 }
@@ -0,0 +1,261 @@
+//! Drives in-process WebRTC peers through the entrance API of a hub on loopback ports.
+use crate::args::Args;
+use crate::entrance_server_router;
+use crate::state::{SharedState, State};
+use crate::status_server;
+use clap::Parser;
+use std::future::Future;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use verse_hub_client::{CallError, Client, ClientConfig, Incoming};
+use verse_proto::rpc::RpcErrorCode;
+use verse_proto::swarm::*;
+use verse_session_id::{new_session_id_pair, SessionId};
+
+const WORLD_URL: &str = "https://example.com/e2e";
+const WAIT_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// The router, state and rpc handlers of main, with the UDP mux and HTTP on ephemeral ports.
+struct TestHub {
+    state: SharedState,
+    entrance_url: String,
+}
+impl TestHub {
+    async fn start(args: &[&str]) -> Self {
+        let args = Args::parse_from(["hubserv"].iter().chain(args));
+        let udp_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // candidates of the hub point to the mux on loopback
+        let api = crate::create_webrtc_api(Some("127.0.0.1".to_string()), Some(udp_socket));
+        let state = crate::create_state(&args, api, None);
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let entrance_url = format!("http://{}", listener.local_addr().unwrap());
+        let router = entrance_server_router::create_router(&state);
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(router.into_make_service()),
+        );
+        TestHub {
+            state,
+            entrance_url,
+        }
+    }
+
+    async fn connect(&self, world_url: &str) -> Client {
+        let config = ClientConfig {
+            timeout: WAIT_TIMEOUT,
+            ..ClientConfig::new(self.entrance_url.clone(), world_url)
+        };
+        Client::connect(config, new_session_id_pair().unwrap())
+            .await
+            .unwrap()
+    }
+
+    /// Polls until `f` holds, false on timeout.
+    async fn wait_until(&self, f: impl Fn(&State) -> bool) -> bool {
+        wait_until(|| async { f(&self.state) }).await
+    }
+
+    async fn get_metric(&self, name: &str) -> Option<i64> {
+        status_server::get_metrics(self.state.clone())
+            .await
+            .into_iter()
+            .find(|v| v.0 == name)
+            .map(|v| v.1)
+    }
+}
+
+async fn wait_until<F: Future<Output = bool>>(f: impl Fn() -> F) -> bool {
+    let started = std::time::Instant::now();
+    while started.elapsed() < WAIT_TIMEOUT {
+        if f().await {
+            return true;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    false
+}
+
+async fn recv(client: &Client) -> Incoming {
+    recv_within(client, WAIT_TIMEOUT)
+        .await
+        .expect("nothing received")
+}
+
+/// None if nothing is received within `timeout`, or the client is closed.
+async fn recv_within(client: &Client, timeout: Duration) -> Option<Incoming> {
+    tokio::time::timeout(timeout, client.recv())
+        .await
+        .ok()
+        .flatten()
+}
+
+fn routing_info(session_id: &SessionId) -> RoutingInfo {
+    RoutingInfo {
+        node_type: NodeType::Normal.into(),
+        session_id: Some(session_id.to_vec()),
+        ..Default::default()
+    }
+}
+
+fn rpc_metric(metric: &str, rpc: &str, id: &str) -> String {
+    format!("{}{{rpc=\"{}\",id=\"{}\"}}", metric, rpc, id)
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_enter_and_transfer() {
+    let hub = TestHub::start(&[]).await;
+    let a = hub.connect(WORLD_URL).await;
+    let b = hub.connect(WORLD_URL).await;
+    let c = hub.connect(WORLD_URL).await;
+
+    // enter and candidate
+    assert!(
+        hub.wait_until(|s| s.client_count.load(std::sync::atomic::Ordering::Relaxed) == 3)
+            .await
+    );
+    let ud = hub.state.get_url_data(WORLD_URL).unwrap();
+    assert_eq!(ud.get_client_count(), 3);
+    for v in [&a, &b, &c] {
+        let cd = hub.state.get_connection(&v.session_id()).unwrap();
+        assert_eq!(cd.url, WORLD_URL);
+        assert!(!cd.is_backend());
+    }
+
+    // routing exchange
+    for v in [&a, &b, &c] {
+        let res = v
+            .exchange_routing_info(routing_info(&v.session_id()))
+            .await
+            .unwrap();
+        assert_eq!(res.node_type(), NodeType::Tracker);
+    }
+    let cd = hub.state.get_connection(&a.session_id()).unwrap();
+    let ri = cd.get_routing_info().unwrap();
+    assert_eq!(ri.node_type(), NodeType::Normal);
+    assert_eq!(ri.session_id, Some(a.session_id().to_vec()));
+    // RoutingInfo of another session is rejected with the nested rpc id
+    let err = a
+        .exchange_routing_info(routing_info(&b.session_id()))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(
+            err.downcast_ref::<CallError>(),
+            Some(CallError::Rpc {
+                code: RpcErrorCode::BadRequest,
+                ..
+            })
+        ),
+        "{:?}",
+        err
+    );
+
+    // transfer
+    let res = a
+        .transfer(&b.session_id(), b"hello".to_vec(), 3)
+        .await
+        .unwrap();
+    assert!(res.result);
+    assert_eq!(res.route(), TransferRoute::Direct);
+    assert_eq!(res.dest_session_id, b.session_id().to_vec());
+    match recv(&b).await {
+        Incoming::Transfer(req) => {
+            assert_eq!(req.payload, b"hello");
+            assert_eq!(req.ttl, 2);
+            let ss = req.signature.unwrap();
+            assert_eq!(ss.from_session_id, a.session_id().to_vec());
+        }
+        v => panic!("{:?}", v),
+    }
+    // unknown destination
+    let unknown = new_session_id_pair().unwrap().get_id();
+    let res = a.transfer(&unknown, b"hello".to_vec(), 3).await.unwrap();
+    assert!(!res.result);
+
+    // large payloads are fragmented on both sides
+    let payload: Vec<u8> = (0..100_000u32).map(|v| ((v * 7919) >> 3) as u8).collect();
+    let res = b
+        .transfer(&a.session_id(), payload.clone(), 1)
+        .await
+        .unwrap();
+    assert!(res.result);
+    match recv(&a).await {
+        Incoming::Transfer(req) => assert_eq!(req.payload, payload),
+        v => panic!("{:?}", v),
+    }
+
+    assert_eq!(
+        hub.get_metric(&rpc_metric("rpc_calls", "transfer", "1/1"))
+            .await,
+        Some(3)
+    );
+    assert_eq!(
+        hub.get_metric(&rpc_metric("rpc_errors", "exchange_routing_info", "1/2"))
+            .await,
+        Some(1)
+    );
+    assert_eq!(hub.get_metric("client_count").await, Some(3));
+
+    // disconnect
+    c.subscribe(true).await.unwrap();
+    a.close().await.unwrap();
+    assert!(
+        hub.wait_until(|s| s.get_connection(&a.session_id()).is_none())
+            .await
+    );
+    assert_eq!(
+        hub.state
+            .get_url_data(WORLD_URL)
+            .unwrap()
+            .get_client_count(),
+        2
+    );
+    let leave = wait_until(|| async {
+        match recv_within(&c, Duration::from_millis(100)).await {
+            Some(Incoming::MembershipEvents(v)) => v.events.iter().any(|e| {
+                e.r#type() == MembershipEventType::Leave && e.session_id == a.session_id().to_vec()
+            }),
+            _ => false,
+        }
+    })
+    .await;
+    assert!(leave);
+    assert_eq!(hub.get_metric("client_count").await, Some(2));
+
+    let res = b
+        .transfer(&a.session_id(), b"bye".to_vec(), 3)
+        .await
+        .unwrap();
+    assert!(!res.result);
+
+    b.close().await.unwrap();
+    c.close().await.unwrap();
+    assert!(
+        hub.wait_until(|s| s.get_url_data(WORLD_URL).is_none())
+            .await
+    );
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_enter_limits() {
+    let hub = TestHub::start(&["--max-connections-by-url", "1"]).await;
+    let a = hub.connect(WORLD_URL).await;
+    // /enter answers 503
+    let config = ClientConfig {
+        timeout: WAIT_TIMEOUT,
+        ..ClientConfig::new(hub.entrance_url.clone(), WORLD_URL)
+    };
+    assert!(Client::connect(config, new_session_id_pair().unwrap())
+        .await
+        .is_err());
+    // other worlds are not limited, and can't receive transfers from it
+    let b = hub.connect("https://example.com/other").await;
+    let res = a
+        .transfer(&b.session_id(), b"hello".to_vec(), 3)
+        .await
+        .unwrap();
+    assert!(!res.result);
+    assert_eq!(hub.get_metric("transfer_rejected_count").await, Some(1));
+}
@@ -164,9 +164,16 @@ async fn enter(
             cd.dispose();
         } */
         state.clone().remove_connection(&session_id);
-        if !state
+        if state
             .clone()
-            .add_connection(ClientData::new(session_id, pc.clone(), payload.url))
+            .add_connection(ClientData::with_config(
+                session_id,
+                pc.clone(),
+                payload.url,
+                state.new_send_queue(),
+                state.fragment_limits.clone(),
+            ))
+            .is_err()
         {
             pc.close()
                 .await
@@ -198,8 +205,8 @@ async fn candidate(
     }
 
     let Some(cd) = state.get_connection(&session_id) else {
-            debug!("no connection");
-            return Err(axum::http::StatusCode::BAD_REQUEST.into_response());
+        debug!("no connection");
+        return Err(axum::http::StatusCode::BAD_REQUEST.into_response());
     };
     cd.add_ice_candidate(payload.sdp)
         .await
@@ -0,0 +1,360 @@
+use crate::args::Args;
+use crate::ids::*;
+use crate::rpc_handler::{RateLimiter, RpcError, RpcKey};
+use crate::state::{AddConnectionError, ClientData, SharedState};
+use crate::swarm::ReplayCache;
+use crate::types;
+use anyhow::{anyhow, Result};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::net::{IpAddr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::metadata::MetadataMap;
+use tonic::transport::{Identity, ServerTlsConfig};
+use tonic::{Code, Request, Response, Status, Streaming};
+use verse_common::prelude::*;
+use verse_proto::rpc::RpcErrorCode;
+use verse_proto::swarm::swarm_backend_service_server::{
+    SwarmBackendService, SwarmBackendServiceServer,
+};
+use verse_proto::swarm::*;
+use verse_session_id::SessionId;
+
+/// Metadata with the JSON of SignedRequest, its payload is BackendRequestPayload.
+pub const SIGNED_REQUEST_KEY: &str = "x-signed-request";
+const MAX_CLOCK_SKEW_MSEC: i64 = 60 * 1000;
+// signed requests seen within the clock skew on both sides, the oldest ones are
+// forgotten when full since the timestamp keeps them from being replayed for long
+const REPLAY_CACHE_SIZE: usize = 100_000;
+// a backend opens a few streams within the window
+const REPLAY_SENDER_QUOTA: usize = 16;
+// packets to a backend waiting to be sent, newer ones are dropped when full
+const STREAM_BUFFER_SIZE: usize = 256;
+
+/// Serves SwarmBackendService, if `grpc_port` is given.
+pub async fn start_server(args: &Args, app_state: SharedState) {
+    let Some(port) = args.grpc_port else {
+        return;
+    };
+    let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, port));
+    info!("grpc server 0.0.0.0:{}", port);
+    let mut builder = tonic::transport::Server::builder();
+    if let (Some(cert), Some(key)) = (&args.grpc_tls_cert, &args.grpc_tls_key) {
+        let identity =
+            Identity::from_pem(std::fs::read(cert).unwrap(), std::fs::read(key).unwrap());
+        builder = builder
+            .tls_config(ServerTlsConfig::new().identity(identity))
+            .unwrap();
+    }
+    builder
+        .http2_keepalive_interval(Some(Duration::from_secs(30)))
+        .add_service(SwarmBackendServiceServer::new(BackendService {
+            state: app_state,
+            limiter: RateLimiter::new(
+                args.grpc_stream_rate_limit,
+                args.grpc_stream_rate_limit_burst,
+            ),
+            replay_cache: ReplayCache::new(
+                Duration::from_millis(MAX_CLOCK_SKEW_MSEC as u64 * 2),
+                REPLAY_CACHE_SIZE,
+                REPLAY_SENDER_QUOTA,
+            ),
+        }))
+        .serve(addr)
+        .await
+        .unwrap();
+}
+
+/// A backend joins the world of the signed url, and is handled like a WebRTC client.
+/// The error of a request ends the stream with its Status, as SwarmPacket can't carry it.
+struct BackendService {
+    state: SharedState,
+    /// Streams opened per remote address.
+    limiter: RateLimiter<IpAddr>,
+    replay_cache: ReplayCache,
+}
+impl BackendService {
+    /// Authenticates the signed request, and adds the backend as a connection.
+    #[allow(clippy::result_large_err)]
+    fn connect(
+        &self,
+        remote_addr: Option<SocketAddr>,
+        metadata: &MetadataMap,
+        tx: mpsc::Sender<SwarmPacket>,
+    ) -> Result<(Arc<ClientData>, types::BackendRequestPayload), Status> {
+        if let Some(addr) = remote_addr {
+            if !self.limiter.check(addr.ip()) {
+                return Err(Status::resource_exhausted("too many streams"));
+            }
+        }
+        let (session_id, payload, salt) =
+            authenticate(metadata, get_now_msec() as i64).map_err(|ex| {
+                warn!("failed: authenticate backend: {:?}", ex);
+                Status::unauthenticated("bad signed request")
+            })?;
+        if payload.url.is_empty() {
+            return Err(Status::invalid_argument("bad url"));
+        }
+        if let Some(cluster_client) = self.state.cluster_client.as_ref() {
+            if !matches!(
+                cluster_client.get_worker(&payload.url),
+                verse_cluster::Worker::Me
+            ) {
+                return Err(Status::failed_precondition("not served by this node"));
+            }
+        }
+        if !self.state.is_new_connection_available(&payload.url) {
+            return Err(Status::resource_exhausted("too many connections"));
+        }
+        if !self.replay_cache.insert(session_id, &salt, Instant::now()) {
+            return Err(Status::unauthenticated("signed request replayed"));
+        }
+
+        let cd = ClientData::new_backend(session_id, payload.url.clone(), tx);
+        match self.state.add_connection(cd.clone()) {
+            Ok(()) => Ok((cd, payload)),
+            Err(AddConnectionError::AlreadyConnected) => {
+                Err(Status::already_exists("session already connected"))
+            }
+            Err(AddConnectionError::TooManyConnections) => {
+                Err(Status::resource_exhausted("too many connections"))
+            }
+        }
+    }
+}
+#[tonic::async_trait]
+impl SwarmBackendService for BackendService {
+    type SwarmStream = Pin<Box<dyn Stream<Item = Result<SwarmPacket, Status>> + Send>>;
+
+    async fn swarm(
+        &self,
+        req: Request<Streaming<SwarmPacket>>,
+    ) -> Result<Response<Self::SwarmStream>, Status> {
+        let (tx, rx) = mpsc::channel(STREAM_BUFFER_SIZE);
+        let (error_tx, error_rx) = mpsc::channel(1);
+        let (cd, payload) = self.connect(req.remote_addr(), req.metadata(), tx)?;
+        let client_count = self
+            .state
+            .get_url_data(&payload.url)
+            .map_or(1, |ud| ud.get_client_count());
+        let headers = req.metadata().clone().into_headers();
+        self.state
+            .append_access_log(client_count, &payload.raw_url, &headers);
+        debug!("backend connected: {}", cd.session_id.to_debug_string());
+
+        tokio::spawn(receive(self.state.clone(), cd, req.into_inner(), error_tx));
+        Ok(Response::new(Box::pin(
+            ReceiverStream::new(rx)
+                .map(Ok)
+                .merge(ReceiverStream::new(error_rx).map(Err)),
+        )))
+    }
+}
+
+/// Returns the salt of the signature too, to detect a replay.
+fn authenticate(
+    metadata: &MetadataMap,
+    now_msec: i64,
+) -> Result<(SessionId, types::BackendRequestPayload, Vec<u8>)> {
+    let req: types::SignedRequest = serde_json::from_str(
+        metadata
+            .get(SIGNED_REQUEST_KEY)
+            .ok_or_else(|| anyhow!("no signed request"))?
+            .to_str()?,
+    )?;
+    let (session_id, payload) = req.verify::<types::BackendRequestPayload>()?;
+    if (now_msec - payload.timestamp).abs() > MAX_CLOCK_SKEW_MSEC {
+        return Err(anyhow!("expired: {}", payload.timestamp));
+    }
+    Ok((session_id, payload, req.sign.salt.to_vec()))
+}
+
+/// Dispatches the requests of a backend until the stream ends or a request fails.
+async fn receive(
+    state: SharedState,
+    cd: Arc<ClientData>,
+    mut stream: Streaming<SwarmPacket>,
+    error_tx: mpsc::Sender<Status>,
+) {
+    loop {
+        let packet = match stream.message().await {
+            Ok(Some(packet)) => packet,
+            Ok(None) => break,
+            Err(e) => {
+                debug!(
+                    "backend stream closed: {}: {}",
+                    cd.session_id.to_debug_string(),
+                    e
+                );
+                break;
+            }
+        };
+        // responses to the requests from the hub are not used
+        let Some(swarm_packet::Data::Request(req)) = packet.data.as_ref() else {
+            continue;
+        };
+        let rpc_id = req.rpc_id;
+        let res = state
+            .rpc_registry
+            .dispatch(
+                RpcKey::top(RPC_ID_SWARM),
+                state.clone(),
+                cd.clone(),
+                packet.encode_to_vec(),
+            )
+            .await;
+        let res = match res {
+            Ok(res) => res,
+            Err(e) => {
+                if e.is_internal() {
+                    info!("backend rpc failed: {} {:?}", rpc_id, e);
+                } else {
+                    debug!("backend rpc failed: {} {}", rpc_id, e);
+                }
+                let _ = error_tx.send(to_status(&e)).await;
+                break;
+            }
+        };
+        cd.send_rpc_response(RPC_ID_SWARM, res)
+            .await
+            .if_err_info(logmsg!("can't send to backend"));
+    }
+    // the session may have been removed in the meantime, and connected again
+    if state
+        .get_connection(&cd.session_id)
+        .is_some_and(|v| Arc::ptr_eq(&v, &cd))
+    {
+        state.remove_connection(&cd.session_id);
+    }
+}
+
+fn to_status(e: &RpcError) -> Status {
+    let code = match e.code() {
+        RpcErrorCode::UnknownRpcId => Code::Unimplemented,
+        RpcErrorCode::BadRequest => Code::InvalidArgument,
+        RpcErrorCode::Unauthorized => Code::PermissionDenied,
+        RpcErrorCode::RateLimited => Code::ResourceExhausted,
+        RpcErrorCode::Internal | RpcErrorCode::Unspecified => Code::Internal,
+    };
+    Status::new(code, e.client_message())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc_handler::RpcRegistry;
+    use crate::state::State;
+    use verse_session_id::{new_session_id_pair, SessionIdPair};
+    use webrtc::api::APIBuilder;
+
+    fn metadata(url: &str, timestamp: i64) -> (SessionId, MetadataMap) {
+        let pair = new_session_id_pair().unwrap();
+        (pair.get_id(), signed_metadata(&pair, url, timestamp))
+    }
+    fn signed_metadata(pair: &SessionIdPair, url: &str, timestamp: i64) -> MetadataMap {
+        let payload = serde_json::to_string(&types::BackendRequestPayload {
+            url: url.to_string(),
+            timestamp,
+            ..Default::default()
+        })
+        .unwrap();
+        let sign = pair.sign(vec![payload.as_bytes()]).unwrap();
+        let req = serde_json::json!({
+            "sessionId": pair.get_id().to_string(),
+            "sign": sign,
+            "payload": payload,
+        });
+        let mut res = MetadataMap::new();
+        res.insert(SIGNED_REQUEST_KEY, req.to_string().parse().unwrap());
+        res
+    }
+
+    #[test]
+    fn test_authenticate() {
+        let now = 1_000_000_000;
+        let (session_id, m) = metadata("https://example.com/a/", now - 1000);
+        let (id, payload, _) = authenticate(&m, now).unwrap();
+        assert_eq!(id, session_id);
+        assert_eq!(payload.url, "https://example.com/a");
+        assert_eq!(payload.raw_url, "https://example.com/a/");
+
+        let (_, m) = metadata("https://example.com", now - MAX_CLOCK_SKEW_MSEC - 1);
+        assert!(authenticate(&m, now).is_err());
+        assert!(authenticate(&MetadataMap::new(), now).is_err());
+
+        // payload of another session
+        let (_, mut m) = metadata("https://example.com", now);
+        let (_, other) = metadata("https://example.com", now);
+        let v = m.get(SIGNED_REQUEST_KEY).unwrap().to_str().unwrap();
+        let mut req: serde_json::Value = serde_json::from_str(v).unwrap();
+        let other: serde_json::Value =
+            serde_json::from_str(other.get(SIGNED_REQUEST_KEY).unwrap().to_str().unwrap()).unwrap();
+        req["sessionId"] = other["sessionId"].clone();
+        m.insert(SIGNED_REQUEST_KEY, req.to_string().parse().unwrap());
+        assert!(authenticate(&m, now).is_err());
+    }
+
+    #[test]
+    fn test_connect() {
+        let service = BackendService {
+            state: State::new(
+                APIBuilder::new().build(),
+                None,
+                None,
+                10,
+                vec![],
+                None,
+                None,
+                None,
+                RpcRegistry::new(),
+                Default::default(),
+                Default::default(),
+                Default::default(),
+            ),
+            limiter: RateLimiter::new(0.0, 2.0),
+            replay_cache: ReplayCache::new(Duration::from_secs(120), 100, 2),
+        };
+        let connect = |addr: &str, m: &MetadataMap| {
+            let (tx, _) = mpsc::channel(1);
+            service
+                .connect(Some(addr.parse().unwrap()), m, tx)
+                .map(|(cd, _)| cd.session_id)
+                .map_err(|e| e.code())
+        };
+        let url = "https://example.com/a";
+        let now = get_now_msec() as i64;
+        let pair = new_session_id_pair().unwrap();
+        let m = signed_metadata(&pair, url, now);
+
+        assert_eq!(connect("192.0.2.1:1000", &m), Ok(pair.get_id()));
+        assert_eq!(connect("192.0.2.2:1000", &m), Err(Code::Unauthenticated));
+        // the same session with another salt
+        let m = signed_metadata(&pair, url, now);
+        assert_eq!(connect("192.0.2.3:1000", &m), Err(Code::AlreadyExists));
+
+        // streams per address
+        let (_, m) = metadata("", now);
+        assert_eq!(connect("192.0.2.1:1001", &m), Err(Code::InvalidArgument));
+        assert_eq!(connect("192.0.2.1:1002", &m), Err(Code::ResourceExhausted));
+    }
+
+    #[test]
+    fn test_to_status() {
+        assert_eq!(
+            to_status(&RpcError::BadRequest("bad".into())).code(),
+            Code::InvalidArgument
+        );
+        let status = to_status(&RpcError::Nested(
+            RPC_ID_TRANSFER,
+            Box::new(RpcError::Internal(anyhow!("secret"))),
+        ));
+        assert_eq!(status.code(), Code::Internal);
+        assert_eq!(status.message(), "internal error");
+    }
+}
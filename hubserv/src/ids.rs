@@ -1,5 +1,8 @@
-pub const RPC_ID_KEEP_ALIVE: u32 = 0;
-pub const RPC_ID_SWARM: u32 = 1;
+// generated from the services in proto/pb
+pub use verse_proto::rpc::rpc_service::{RPC_ID_KEEP_ALIVE, RPC_ID_SWARM};
 
-pub const RPC_ID_TRANSFER: u32 = 1;
-pub const RPC_ID_EXCHANGE_ROUTING_INFO: u32 = 2;
+pub use verse_proto::swarm::swarm_node_service::{RPC_ID_MULTICAST, RPC_ID_TRANSFER};
+// pushed from the hub
+pub use verse_proto::swarm::swarm_hub_service::{
+    RPC_ID_BECOME_GATEWAY, RPC_ID_MEMBERSHIP_EVENTS, RPC_ID_MERGE_SUGGESTION,
+};
@@ -2,21 +2,26 @@
 use log::{debug, error, info, warn};
 use std::sync::Arc;
 use tokio::net::UdpSocket;
+use verse_proto::rpc::{DecompressLimits, FragmentLimits, MIN_FRAGMENT_SIZE};
 use webrtc::{dtls_transport::dtls_role::DTLSRole, ice::mdns::MulticastDnsMode};
 mod entrance_server_router;
 mod ids;
+mod rpc_handler;
 mod rtc_api;
 mod state;
 mod types;
-use crate::state::State;
+use crate::state::{SendQueueConfig, State};
 mod args;
 use args::Args;
 mod api_server;
 mod cluster;
 mod dns;
+mod grpc_server;
 mod status_server;
 mod swarm;
 mod version;
+#[cfg(test)]
+mod e2e_tests;
 
 // Ex: https://github.com/FlorianUekermann/rustls-acme/tree/main/examples
 
@@ -27,48 +32,33 @@ async fn main() {
     let args = Args::load().await.unwrap();
     info!("start hub\n{:#?}", args);
 
+    let dns_provider = dns::create_dns_provider(&args).unwrap();
     let mut cluster_manager: Option<Arc<verse_cluster::manager::Manager>> = None;
     if args.cluster_node_host.is_some() {
         let http_host = args.http_host.as_ref().unwrap();
-        let cf_email = args.cloudflare_email.as_ref().unwrap();
-        let cf_api_key = args.cloudflare_api_key.as_ref().unwrap();
-        let aws_region = args.aws_ec2_region.as_ref().unwrap();
-        let role = args.cluster_node_role.as_ref().unwrap();
-        let stage = args.cluster_node_stage.as_ref().unwrap();
-        let cluster_json_s3_bucket = args.cluster_json_s3_bucket.as_ref().unwrap();
-        let cluster_json_s3_key = args.cluster_json_s3_key.as_ref().unwrap();
+        let node_source = cluster::create_node_source(&args).unwrap();
+        let node_list_store = cluster::create_node_list_store(&args, args.cluster_node_list_store)
+            .unwrap()
+            .expect("no node list store");
         let cm = verse_cluster::manager::Manager::new(
             http_host,
-            stage,
-            role,
-            aws_region,
-            verse_cluster::manager::S3Path {
-                bucket: cluster_json_s3_bucket.into(),
-                key: cluster_json_s3_key.into(),
-            },
-            verse_cluster::manager::CfAuthInfo {
-                email: cf_email.to_owned(),
-                api_key: cf_api_key.to_owned(),
-            },
+            node_source,
+            dns_provider.expect("no dns provider"),
+            node_list_store,
         );
         cm.update().await.unwrap();
-        cluster_manager = Some(Arc::new(cm));
-    } else if let (
-        Some(http_host),
-        Some(public_ip),
-        Some(cloudflare_email),
-        Some(cloudflare_api_key),
-    ) = (
+        let cm = Arc::new(cm);
+        cluster::start_manager(&args, cm.clone());
+        cluster_manager = Some(cm);
+    } else if let (Some(http_host), Some(public_ip), Some(dns_provider)) = (
         args.http_host.as_ref(),
         args.public_ip.as_ref(),
-        args.cloudflare_email.as_ref(),
-        args.cloudflare_api_key.as_ref(),
+        dns_provider,
     ) {
-        dns::set_arecord(
+        verse_cluster::dns_provider::set_record(
+            dns_provider.as_ref(),
             http_host,
-            public_ip,
-            cloudflare_email,
-            cloudflare_api_key,
+            public_ip.parse().unwrap(),
             true,
             // !args.use_https,
         )
@@ -81,14 +71,9 @@ async fn main() {
         .await
         .unwrap();
 
-    let app_state = State::new(
+    let app_state = create_state(
+        &args,
         create_webrtc_api(args.public_ip.clone(), Some(udp_socket)),
-        args.max_connections,
-        args.max_connections_by_url,
-        args.max_routing_results,
-        args.ice_servers.clone(),
-        args.access_log_path.as_deref(),
-        cluster::create_client(&args),
         cluster_manager,
     );
 
@@ -97,10 +82,100 @@ async fn main() {
         .unwrap();
     tokio::join!(
         api_server::start_server(&args, app_state.clone()),
+        grpc_server::start_server(&args, app_state.clone()),
         status_server::start_server(&args, app_state),
     );
 }
 
+fn create_state(
+    args: &Args,
+    api: webrtc::api::API,
+    cluster_manager: Option<Arc<verse_cluster::manager::Manager>>,
+) -> state::SharedState {
+    State::new(
+        api,
+        args.max_connections,
+        args.max_connections_by_url,
+        args.max_routing_results,
+        args.ice_servers.clone(),
+        args.access_log_path.as_deref(),
+        cluster::create_client(args),
+        cluster_manager,
+        create_rpc_registry(args),
+        DecompressLimits {
+            max_size: args.max_decompressed_size,
+            max_ratio: args.max_compression_ratio,
+        },
+        FragmentLimits {
+            max_message_size: args.max_decompressed_size,
+            max_fragment_size: args
+                .max_fragment_size
+                .clamp(MIN_FRAGMENT_SIZE, u16::MAX as usize),
+            max_pending_messages: args.max_pending_fragmented_messages,
+            ..Default::default()
+        },
+        SendQueueConfig {
+            capacity: args.send_queue_capacity,
+            max_buffered_amount: args.send_queue_max_buffered_amount,
+            policy: args.send_queue_overflow_policy,
+        },
+    )
+}
+
+fn create_rpc_registry(args: &Args) -> rpc_handler::RpcRegistry {
+    let mut registry = rpc_handler::RpcRegistry::new();
+    registry.add_middleware(Box::new(rpc_handler::TraceMiddleware));
+    registry.add_middleware(Box::new(rpc_handler::AuthMiddleware));
+    registry.add_middleware(Box::new(rpc_handler::RateLimitMiddleware::new(
+        args.rpc_rate_limit,
+        args.rpc_rate_limit_burst,
+    )));
+    rtc_api::register_handlers(&mut registry);
+    swarm::register_handlers(
+        &mut registry,
+        swarm::TransferConfig {
+            cross_world_policy: args.cross_world_transfer,
+            max_hops: args.transfer_max_hops,
+            replay_window: std::time::Duration::from_secs(args.transfer_replay_window_secs),
+            replay_cache_size: args.transfer_replay_cache_size,
+            replay_sender_quota: args.transfer_replay_sender_quota,
+        },
+        swarm::MulticastConfig {
+            rate_per_sec: args.multicast_rate_limit,
+            burst: args.multicast_rate_limit_burst,
+            max_payload_size: args.multicast_max_payload_size,
+            max_recipients: args.multicast_max_recipients,
+        },
+        swarm::RoutingConfig {
+            selectors: args.routing_strategy_by_url.iter().fold(
+                swarm::RoutingSelectors::new(args.routing_strategy.to_selector()),
+                |v, w| v.with_world(w.url.clone(), w.strategy.to_selector()),
+            ),
+            area_of_interest: args.area_of_interest_by_url.iter().fold(
+                swarm::AreaOfInterestConfig::new(args.area_of_interest),
+                |v, w| v.with_world(w.url.clone(), w.area),
+            ),
+            limits: swarm::RoutingInfoLimits {
+                max_depth: args.routing_info_max_depth,
+                max_breadth: args.routing_info_max_breadth,
+                max_position: args.routing_info_max_position,
+                max_size: args.routing_info_max_size,
+            },
+            gateway: swarm::GatewayConfig {
+                min_gateways: args.min_gateways,
+                min_uptime_msec: args.gateway_min_uptime_secs * 1000,
+                check_interval_sec: args.gateway_check_interval_secs,
+            },
+            partition: swarm::PartitionConfig {
+                merge: args.partition_merge,
+                check_interval_sec: args.partition_check_interval_secs,
+                max_peers: args.partition_merge_max_peers,
+            },
+        },
+    );
+    registry
+}
+
 fn create_webrtc_api(public_ip: Option<String>, sock: Option<UdpSocket>) -> webrtc::api::API {
     use webrtc::ice::udp_mux::{UDPMuxDefault, UDPMuxParams};
     let mut se = webrtc::api::setting_engine::SettingEngine::default();
@@ -0,0 +1,547 @@
+use crate::state::{ClientData, State};
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::Cursor;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+use verse_proto::rpc::RpcErrorCode;
+use verse_proto::service::{RpcMethod, RpcService};
+
+mod middleware;
+pub use middleware::{AuthMiddleware, RateLimitMiddleware, RateLimiter, TraceMiddleware};
+
+#[derive(Error, Debug)]
+pub enum RpcError {
+    #[error("unknown rpc id: {0}")]
+    UnknownRpcId(RpcKey),
+    #[error("bad request: {0}")]
+    BadRequest(String),
+    #[error("unauthorized")]
+    Unauthorized,
+    #[error("rate limited")]
+    RateLimited,
+    #[error("internal: {0:?}")]
+    Internal(anyhow::Error),
+    #[error("{1} (nested rpc id: {0})")]
+    Nested(u32, Box<RpcError>),
+}
+impl RpcError {
+    pub fn code(&self) -> RpcErrorCode {
+        match self {
+            RpcError::UnknownRpcId(_) => RpcErrorCode::UnknownRpcId,
+            RpcError::BadRequest(_) => RpcErrorCode::BadRequest,
+            RpcError::Unauthorized => RpcErrorCode::Unauthorized,
+            RpcError::RateLimited => RpcErrorCode::RateLimited,
+            RpcError::Internal(_) => RpcErrorCode::Internal,
+            RpcError::Nested(_, e) => e.code(),
+        }
+    }
+    pub fn nested_rpc_id(&self) -> Option<u32> {
+        match self {
+            RpcError::UnknownRpcId(key) => key.nested_rpc_id,
+            RpcError::Nested(nested_rpc_id, _) => Some(*nested_rpc_id),
+            _ => None,
+        }
+    }
+    pub fn is_internal(&self) -> bool {
+        match self {
+            RpcError::Internal(_) => true,
+            RpcError::Nested(_, e) => e.is_internal(),
+            _ => false,
+        }
+    }
+    /// Message sent to the client. Details of internal errors are only logged.
+    pub fn client_message(&self) -> String {
+        if self.is_internal() {
+            "internal error".to_string()
+        } else {
+            self.to_string()
+        }
+    }
+}
+impl From<anyhow::Error> for RpcError {
+    fn from(e: anyhow::Error) -> Self {
+        RpcError::Internal(e)
+    }
+}
+impl From<prost::DecodeError> for RpcError {
+    fn from(e: prost::DecodeError) -> Self {
+        RpcError::BadRequest(e.to_string())
+    }
+}
+
+/// `rpc_id` of RpcRequest, and `nested_rpc_id` of the request carried in its param
+/// (ex: SwarmRequest in RPC_ID_SWARM).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct RpcKey {
+    pub rpc_id: u32,
+    pub nested_rpc_id: Option<u32>,
+}
+impl RpcKey {
+    pub const fn top(rpc_id: u32) -> Self {
+        RpcKey {
+            rpc_id,
+            nested_rpc_id: None,
+        }
+    }
+    pub const fn nested(rpc_id: u32, nested_rpc_id: u32) -> Self {
+        RpcKey {
+            rpc_id,
+            nested_rpc_id: Some(nested_rpc_id),
+        }
+    }
+}
+impl fmt::Display for RpcKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(nested_rpc_id) = self.nested_rpc_id {
+            write!(f, "{}/{}", self.rpc_id, nested_rpc_id)
+        } else {
+            write!(f, "{}", self.rpc_id)
+        }
+    }
+}
+
+pub struct RpcContext<'a> {
+    pub key: RpcKey,
+    pub name: &'static str,
+    pub is_router: bool,
+    pub state: &'a Arc<State>,
+    pub cd: &'a Arc<ClientData>,
+}
+
+#[async_trait]
+pub trait RpcHandler: Send + Sync {
+    /// Used as the metrics label.
+    fn name(&self) -> &'static str;
+
+    /// True for handlers that only decode the param and dispatch it again by
+    /// nested rpc id. Middlewares can use this to avoid counting a request twice.
+    fn is_router(&self) -> bool {
+        false
+    }
+
+    /// Returns the encoded response param.
+    /// On error, the dispatcher answers the request with an RpcError instead.
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        param: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError>;
+
+    /// Handler specific metrics, appended to those of RpcRegistry.
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        Vec::new()
+    }
+}
+
+/// RpcHandler of a method generated from the proto services, with the typed request and response.
+#[async_trait]
+pub trait MethodHandler<M: RpcMethod>: Send + Sync {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: M::Request,
+    ) -> Result<M::Response, RpcError>;
+
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        Vec::new()
+    }
+}
+struct Typed<M, H> {
+    handler: H,
+    _method: PhantomData<fn() -> M>,
+}
+#[async_trait]
+impl<M: RpcMethod, H: MethodHandler<M>> RpcHandler for Typed<M, H> {
+    fn name(&self) -> &'static str {
+        M::NAME
+    }
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        param: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError> {
+        let req = M::Request::decode(Cursor::new(&param))?;
+        Ok(self.handler.call(state, cd, req).await?.encode_to_vec())
+    }
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        self.handler.get_metrics()
+    }
+}
+
+/// Context of the generated `Server` traits served by RpcRegistry.
+pub type ServiceContext = (Arc<State>, Arc<ClientData>);
+
+/// RpcHandler of one method of a generated service (see RpcRegistry::register_service).
+struct ServiceMethod<S> {
+    service: Arc<S>,
+    rpc_id: u32,
+    name: &'static str,
+}
+#[async_trait]
+impl<S> RpcHandler for ServiceMethod<S>
+where
+    S: RpcService<Context = ServiceContext, Error = RpcError> + 'static,
+{
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        param: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError> {
+        match self
+            .service
+            .dispatch((state, cd), self.rpc_id, &param)
+            .await
+        {
+            Some(res) => res,
+            None => Err(RpcError::UnknownRpcId(RpcKey::top(self.rpc_id))),
+        }
+    }
+}
+
+pub trait RpcMiddleware: Send + Sync {
+    fn before(&self, _ctx: &RpcContext) -> Result<(), RpcError> {
+        Ok(())
+    }
+    fn after(&self, _ctx: &RpcContext, _result: &Result<Vec<u8>, RpcError>, _elapsed: Duration) {}
+}
+
+#[derive(Default)]
+pub struct RpcMetrics {
+    pub calls: AtomicU64,
+    pub errors: AtomicU64,
+    pub rejected: AtomicU64,
+    pub elapsed_usec: AtomicU64,
+}
+
+struct Entry {
+    name: &'static str,
+    handler: Arc<dyn RpcHandler>,
+    metrics: RpcMetrics,
+}
+
+type GetMetrics = Box<dyn Fn() -> Vec<(String, i64)> + Send + Sync>;
+
+#[derive(Default)]
+pub struct RpcRegistry {
+    handlers: HashMap<RpcKey, Entry>,
+    middlewares: Vec<Box<dyn RpcMiddleware>>,
+    metrics: Vec<GetMetrics>,
+    unknown_count: AtomicU64,
+}
+impl RpcRegistry {
+    pub fn new() -> Self {
+        Default::default()
+    }
+    pub fn register(&mut self, key: RpcKey, handler: Arc<dyn RpcHandler>) {
+        let name = handler.name();
+        if self
+            .handlers
+            .insert(
+                key,
+                Entry {
+                    name,
+                    handler,
+                    metrics: Default::default(),
+                },
+            )
+            .is_some()
+        {
+            warn!("rpc handler replaced: {} {}", key, name);
+        }
+    }
+    /// Registers `handler` for the method `M`, nested in `rpc_id` if given.
+    pub fn register_method<M, H>(&mut self, rpc_id: Option<u32>, handler: H)
+    where
+        M: RpcMethod + 'static,
+        H: MethodHandler<M> + 'static,
+    {
+        let key = match rpc_id {
+            Some(rpc_id) => RpcKey::nested(rpc_id, M::RPC_ID),
+            None => RpcKey::top(M::RPC_ID),
+        };
+        self.register(
+            key,
+            Arc::new(Typed {
+                handler,
+                _method: PhantomData,
+            }),
+        );
+    }
+    /// Registers every method of a generated service (ex: `swarm_node_service::Service`),
+    /// nested in `rpc_id` if given.
+    pub fn register_service<S>(&mut self, rpc_id: Option<u32>, service: S)
+    where
+        S: RpcService<Context = ServiceContext, Error = RpcError> + 'static,
+    {
+        let service = Arc::new(service);
+        for &(method_id, name) in service.methods() {
+            let key = match rpc_id {
+                Some(rpc_id) => RpcKey::nested(rpc_id, method_id),
+                None => RpcKey::top(method_id),
+            };
+            self.register(
+                key,
+                Arc::new(ServiceMethod {
+                    service: service.clone(),
+                    rpc_id: method_id,
+                    name,
+                }),
+            );
+        }
+    }
+    /// Metrics appended to those of the handlers, ex: of a service.
+    pub fn add_metrics<F>(&mut self, get_metrics: F)
+    where
+        F: Fn() -> Vec<(String, i64)> + Send + Sync + 'static,
+    {
+        self.metrics.push(Box::new(get_metrics));
+    }
+    pub fn add_middleware(&mut self, middleware: Box<dyn RpcMiddleware>) {
+        self.middlewares.push(middleware);
+    }
+
+    pub async fn dispatch(
+        &self,
+        key: RpcKey,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        param: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError> {
+        let Some(entry) = self.handlers.get(&key) else {
+            self.unknown_count.fetch_add(1, Ordering::Relaxed);
+            return Err(RpcError::UnknownRpcId(key));
+        };
+        entry.metrics.calls.fetch_add(1, Ordering::Relaxed);
+
+        let ctx = RpcContext {
+            key,
+            name: entry.name,
+            is_router: entry.handler.is_router(),
+            state: &state,
+            cd: &cd,
+        };
+        for m in self.middlewares.iter() {
+            if let Err(e) = m.before(&ctx) {
+                entry.metrics.rejected.fetch_add(1, Ordering::Relaxed);
+                return Err(e);
+            }
+        }
+
+        let start = Instant::now();
+        let res = entry.handler.call(state.clone(), cd.clone(), param).await;
+        let elapsed = start.elapsed();
+        entry
+            .metrics
+            .elapsed_usec
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+        if res.is_err() {
+            entry.metrics.errors.fetch_add(1, Ordering::Relaxed);
+        }
+
+        for m in self.middlewares.iter().rev() {
+            m.after(&ctx, &res, elapsed);
+        }
+        res
+    }
+
+    pub fn get_metrics(&self) -> Vec<(String, i64)> {
+        let mut res = vec![(
+            "rpc_unknown_count".to_string(),
+            self.unknown_count.load(Ordering::Relaxed) as i64,
+        )];
+        let mut entries: Vec<(&RpcKey, &Entry)> = self.handlers.iter().collect();
+        entries.sort_by_key(|v| (v.0.rpc_id, v.0.nested_rpc_id));
+        for (key, entry) in entries {
+            let labels = format!("{{rpc=\"{}\",id=\"{}\"}}", entry.name, key);
+            let m = &entry.metrics;
+            res.push((
+                format!("rpc_calls{}", labels),
+                m.calls.load(Ordering::Relaxed) as i64,
+            ));
+            res.push((
+                format!("rpc_errors{}", labels),
+                m.errors.load(Ordering::Relaxed) as i64,
+            ));
+            res.push((
+                format!("rpc_rejected{}", labels),
+                m.rejected.load(Ordering::Relaxed) as i64,
+            ));
+            res.push((
+                format!("rpc_elapsed_usec{}", labels),
+                m.elapsed_usec.load(Ordering::Relaxed) as i64,
+            ));
+            res.append(&mut entry.handler.get_metrics());
+        }
+        for get_metrics in self.metrics.iter() {
+            res.append(&mut get_metrics());
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use verse_proto::rpc::{rpc_service, RpcCapabilities};
+    use verse_proto::swarm::{SwarmPacket, Void};
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    struct EchoHandler;
+    #[async_trait]
+    impl RpcHandler for EchoHandler {
+        fn name(&self) -> &'static str {
+            "echo"
+        }
+        async fn call(
+            &self,
+            _state: Arc<State>,
+            _cd: Arc<ClientData>,
+            param: Vec<u8>,
+        ) -> Result<Vec<u8>, RpcError> {
+            if param.is_empty() {
+                return Err(RpcError::BadRequest("empty".into()));
+            }
+            Ok(param)
+        }
+    }
+    struct EchoService;
+    #[async_trait]
+    impl rpc_service::Server for EchoService {
+        type Context = ServiceContext;
+        type Error = RpcError;
+        async fn keep_alive(&self, _ctx: ServiceContext, req: Void) -> Result<Void, RpcError> {
+            Ok(req)
+        }
+        async fn swarm(
+            &self,
+            _ctx: ServiceContext,
+            req: SwarmPacket,
+        ) -> Result<SwarmPacket, RpcError> {
+            Ok(req)
+        }
+        async fn negotiate(
+            &self,
+            _ctx: ServiceContext,
+            _req: RpcCapabilities,
+        ) -> Result<RpcCapabilities, RpcError> {
+            Err(RpcError::BadRequest("negotiate".into()))
+        }
+    }
+    struct DenyMiddleware;
+    impl RpcMiddleware for DenyMiddleware {
+        fn before(&self, ctx: &RpcContext) -> Result<(), RpcError> {
+            if ctx.key.nested_rpc_id.is_some() {
+                return Err(RpcError::Unauthorized);
+            }
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let state = State::new(
+            APIBuilder::new().build(),
+            None,
+            None,
+            10,
+            vec![],
+            None,
+            None,
+            None,
+            RpcRegistry::new(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let pc = Arc::new(
+            state
+                .api
+                .new_peer_connection(RTCConfiguration::default())
+                .await
+                .unwrap(),
+        );
+        let cd = ClientData::new([0; 32].into(), pc, "".to_string());
+
+        let mut registry = RpcRegistry::new();
+        registry.register(RpcKey::top(10), Arc::new(EchoHandler));
+        registry.register(RpcKey::nested(10, 1), Arc::new(EchoHandler));
+        registry.register_service(None, rpc_service::Service(Arc::new(EchoService)));
+        registry.add_metrics(|| vec![("echo_count".to_string(), 1)]);
+        registry.add_middleware(Box::new(DenyMiddleware));
+
+        let res = registry
+            .dispatch(RpcKey::top(10), state.clone(), cd.clone(), vec![1, 2])
+            .await;
+        assert_eq!(res.unwrap(), vec![1, 2]);
+
+        let res = registry
+            .dispatch(RpcKey::top(10), state.clone(), cd.clone(), vec![])
+            .await;
+        assert_eq!(res.unwrap_err().code(), RpcErrorCode::BadRequest);
+
+        let res = registry
+            .dispatch(RpcKey::top(11), state.clone(), cd.clone(), vec![1])
+            .await;
+        assert!(matches!(res, Err(RpcError::UnknownRpcId(k)) if k == RpcKey::top(11)));
+
+        let res = registry
+            .dispatch(RpcKey::nested(10, 1), state.clone(), cd.clone(), vec![1])
+            .await;
+        assert_eq!(res.unwrap_err().code(), RpcErrorCode::Unauthorized);
+
+        // methods of a service
+        let packet = SwarmPacket::default().encode_to_vec();
+        let res = registry
+            .dispatch(
+                RpcKey::top(rpc_service::RPC_ID_SWARM),
+                state.clone(),
+                cd.clone(),
+                packet.clone(),
+            )
+            .await;
+        assert_eq!(res.unwrap(), packet);
+        let res = registry
+            .dispatch(
+                RpcKey::top(rpc_service::RPC_ID_SWARM),
+                state.clone(),
+                cd.clone(),
+                vec![0xff],
+            )
+            .await;
+        assert_eq!(res.unwrap_err().code(), RpcErrorCode::BadRequest);
+        let res = registry
+            .dispatch(
+                RpcKey::top(rpc_service::RPC_ID_NEGOTIATE),
+                state,
+                cd,
+                vec![],
+            )
+            .await;
+        assert_eq!(res.unwrap_err().code(), RpcErrorCode::BadRequest);
+
+        let metrics: HashMap<String, i64> = registry.get_metrics().into_iter().collect();
+        assert_eq!(metrics["rpc_unknown_count"], 1);
+        assert_eq!(metrics["rpc_calls{rpc=\"echo\",id=\"10\"}"], 2);
+        assert_eq!(metrics["rpc_errors{rpc=\"echo\",id=\"10\"}"], 1);
+        assert_eq!(metrics["rpc_rejected{rpc=\"echo\",id=\"10/1\"}"], 1);
+        assert_eq!(metrics["rpc_errors{rpc=\"swarm\",id=\"1\"}"], 1);
+        assert_eq!(metrics["rpc_calls{rpc=\"negotiate\",id=\"2\"}"], 1);
+        assert_eq!(metrics["echo_count"], 1);
+    }
+}
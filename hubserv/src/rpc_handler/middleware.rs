@@ -0,0 +1,138 @@
+use super::{RpcContext, RpcError, RpcMiddleware};
+use dashmap::DashMap;
+use fxhash::FxBuildHasher;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use parking_lot::Mutex;
+use std::hash::Hash;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use verse_session_id::SessionId;
+
+const RATE_LIMIT_GC_THRESHOLD: usize = 10_000;
+const RATE_LIMIT_IDLE_SECONDS: u64 = 60;
+
+/// Rejects requests from a ClientData that is no longer the registered
+/// connection for its session id (disconnected or replaced by a new /enter).
+pub struct AuthMiddleware;
+impl RpcMiddleware for AuthMiddleware {
+    fn before(&self, ctx: &RpcContext) -> Result<(), RpcError> {
+        match ctx.state.get_connection(&ctx.cd.session_id) {
+            Some(cd) if Arc::ptr_eq(&cd, ctx.cd) => Ok(()),
+            _ => Err(RpcError::Unauthorized),
+        }
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per session, or per another key like a remote address.
+pub struct RateLimiter<K = SessionId> {
+    rate_per_sec: f64,
+    burst: f64,
+    buckets: DashMap<K, Mutex<Bucket>, FxBuildHasher>,
+}
+impl<K: Eq + Hash> RateLimiter<K> {
+    pub fn new(rate_per_sec: f64, burst: f64) -> Self {
+        RateLimiter {
+            rate_per_sec,
+            burst,
+            buckets: DashMap::with_hasher(FxBuildHasher::default()),
+        }
+    }
+    /// Takes a token. Returns false if none is left.
+    pub fn check(&self, key: K) -> bool {
+        let now = Instant::now();
+        self.gc(now);
+        let bucket = self.buckets.entry(key).or_insert_with(|| {
+            Mutex::new(Bucket {
+                tokens: self.burst,
+                updated: now,
+            })
+        });
+        let mut b = bucket.lock();
+        let elapsed = now.duration_since(b.updated).as_secs_f64();
+        b.tokens = (b.tokens + elapsed * self.rate_per_sec).min(self.burst);
+        b.updated = now;
+        if b.tokens < 1.0 {
+            return false;
+        }
+        b.tokens -= 1.0;
+        true
+    }
+    fn gc(&self, now: Instant) {
+        if self.buckets.len() < RATE_LIMIT_GC_THRESHOLD {
+            return;
+        }
+        self.buckets.retain(|_, b| {
+            now.duration_since(b.lock().updated) < Duration::from_secs(RATE_LIMIT_IDLE_SECONDS)
+        });
+    }
+}
+
+pub struct RateLimitMiddleware {
+    limiter: RateLimiter,
+}
+impl RateLimitMiddleware {
+    pub fn new(rate_per_sec: f64, burst: f64) -> Self {
+        RateLimitMiddleware {
+            limiter: RateLimiter::new(rate_per_sec, burst),
+        }
+    }
+}
+impl RpcMiddleware for RateLimitMiddleware {
+    fn before(&self, ctx: &RpcContext) -> Result<(), RpcError> {
+        if ctx.is_router {
+            return Ok(());
+        }
+        if !self.limiter.check(ctx.cd.session_id) {
+            return Err(RpcError::RateLimited);
+        }
+        Ok(())
+    }
+}
+
+pub struct TraceMiddleware;
+impl RpcMiddleware for TraceMiddleware {
+    fn after(&self, ctx: &RpcContext, result: &Result<Vec<u8>, RpcError>, elapsed: Duration) {
+        if !log::log_enabled!(log::Level::Trace) {
+            return;
+        }
+        match result {
+            Ok(_) => trace!(
+                "rpc {} {} from {}: ok {:?}",
+                ctx.name,
+                ctx.key,
+                ctx.cd.session_id.to_debug_string(),
+                elapsed
+            ),
+            Err(e) => trace!(
+                "rpc {} {} from {}: {} {:?}",
+                ctx.name,
+                ctx.key,
+                ctx.cd.session_id.to_debug_string(),
+                e,
+                elapsed
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let l = RateLimiter::new(0.0, 2.0);
+        let a: SessionId = [1; 32].into();
+        let b: SessionId = [2; 32].into();
+        assert!(l.check(a));
+        assert!(l.check(a));
+        assert!(!l.check(a));
+        assert!(l.check(b));
+    }
+}
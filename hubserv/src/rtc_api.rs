@@ -1,16 +1,26 @@
 use crate::ids::*;
+use crate::rpc_handler::{MethodHandler, RpcError, RpcKey, RpcRegistry};
 use crate::state::{ClientData, State};
-use crate::swarm::on_swarm_message;
 use anyhow::Result;
-use prost::Message;
-use std::io::Cursor;
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
 use std::sync::Arc;
 use verse_proto::rpc::*;
 use verse_proto::rpc::{rpc_packet, RpcPacket};
-use verse_proto::swarm::*;
+
+pub fn register_handlers(registry: &mut RpcRegistry) {
+    registry.register_method::<rpc_service::Negotiate, _>(None, NegotiateHandler);
+}
 
 pub async fn on_rtc_message(state: Arc<State>, cd: Arc<ClientData>, data: Vec<u8>) -> Result<()> {
-    let packet = RpcPacket::decode_packet(&data)?;
+    let mut packet = decode_packet(&state, &cd, &data)?;
+    if let Some(rpc_packet::Data::Fragment(fragment)) = packet.data {
+        let Some(data) = cd.push_fragment(fragment)? else {
+            return Ok(());
+        };
+        packet = decode_packet(&state, &cd, &data)?;
+    }
     let Some(rpc_packet::Data::Request(req)) = packet.data else {
         // bad request
         return Ok(());
@@ -18,20 +28,61 @@ pub async fn on_rtc_message(state: Arc<State>, cd: Arc<ClientData>, data: Vec<u8
     if req.rpc_id == RPC_ID_KEEP_ALIVE {
         return Ok(());
     }
-    let res = match req.rpc_id {
-        RPC_ID_SWARM => {
-            let res = on_swarm_message(
-                state.clone(),
-                cd.clone(),
-                SwarmPacket::decode(Cursor::new(&req.param))?,
-            )
-            .await?;
-            res.map(|res| res.encode_to_vec())
+    let res = state
+        .rpc_registry
+        .dispatch(
+            RpcKey::top(req.rpc_id),
+            state.clone(),
+            cd.clone(),
+            req.param,
+        )
+        .await;
+    match res {
+        Ok(res) => {
+            cd.send_rpc_reply(req.rpc_id, req.request_id, res).await?;
         }
-        _ => None,
-    };
-    if let Some(res) = res {
-        cd.send_rpc_response(req.rpc_id, res).await?;
-    };
+        Err(e) => {
+            if e.is_internal() {
+                info!("rpc failed: {} {:?}", req.rpc_id, e);
+            } else if let RpcError::UnknownRpcId(key) = &e {
+                debug!("unknown rpc id: {}", key);
+            }
+            cd.send_rpc_error(req.rpc_id, req.request_id, &e).await?;
+        }
+    }
     Ok(())
 }
+
+/// Disconnects the session if the packet looks like a decompression bomb.
+fn decode_packet(state: &Arc<State>, cd: &Arc<ClientData>, data: &[u8]) -> Result<RpcPacket> {
+    let res = RpcPacket::decode_packet_with_limits(data, &state.decompress_limits);
+    if let Err(e) = &res {
+        if let Some(e) = e.downcast_ref::<DecompressError>() {
+            if e.is_limit_exceeded() {
+                warn!("disconnect {}: {}", cd.session_id.to_debug_string(), e);
+                state.remove_connection(&cd.session_id);
+            }
+        }
+    }
+    res
+}
+
+/// Exchanges RpcCapabilities. Clients that never call this keep the old behavior.
+/// The response carries the codec chosen for packets sent to the client.
+struct NegotiateHandler;
+#[async_trait]
+impl MethodHandler<rpc_service::Negotiate> for NegotiateHandler {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: RpcCapabilities,
+    ) -> Result<RpcCapabilities, RpcError> {
+        cd.set_capabilities(&req);
+        Ok(RpcCapabilities {
+            fragmentation: true,
+            max_fragment_size: state.fragment_limits.max_fragment_size as u32,
+            codecs: vec![cd.get_codec() as i32],
+        })
+    }
+}
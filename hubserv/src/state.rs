@@ -1,3 +1,4 @@
+use crate::rpc_handler::RpcRegistry;
 use anyhow::Result;
 use axum::http::header::HeaderMap;
 use dashmap::DashMap;
@@ -14,12 +15,33 @@ use std::fmt::Display;
 use std::sync::atomic::AtomicU64;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use thiserror::Error;
+use verse_proto::rpc::{DecompressLimits, FragmentLimits};
+use verse_proto::swarm::{MembershipEventType, NodeType};
 use verse_session_id::SessionId;
 
 mod client_data;
 pub use client_data::ClientData;
+mod membership;
+pub use membership::Membership;
+mod send_queue;
+pub use send_queue::{OverflowPolicy, SendQueue, SendQueueConfig, SendQueueStats};
+mod relay_index;
+pub use relay_index::RelayIndex;
+mod spatial_index;
+pub use spatial_index::{position_to_point, SpatialIndex};
 mod url_data;
 pub use url_data::UrlData;
+mod world_graph;
+pub use world_graph::{WorldGraph, WorldHealth};
+
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddConnectionError {
+    #[error("too many connections")]
+    TooManyConnections,
+    #[error("session already connected")]
+    AlreadyConnected,
+}
 
 pub struct State {
     pub api: webrtc::api::API,
@@ -38,6 +60,13 @@ pub struct State {
 
     pub cluster_client: Option<Arc<verse_cluster::Client>>,
     pub cluster_manager: Option<Arc<verse_cluster::manager::Manager>>,
+
+    pub rpc_registry: RpcRegistry,
+    pub decompress_limits: DecompressLimits,
+    /// Limits of the fragments sent by each client.
+    pub fragment_limits: FragmentLimits,
+    send_queue_config: SendQueueConfig,
+    pub send_queue_stats: Arc<SendQueueStats>,
 }
 impl State {
     pub fn new(
@@ -49,6 +78,10 @@ impl State {
         access_log_path: Option<&str>,
         cluster_client: Option<Arc<verse_cluster::Client>>,
         cluster_manager: Option<Arc<verse_cluster::manager::Manager>>,
+        rpc_registry: RpcRegistry,
+        decompress_limits: DecompressLimits,
+        fragment_limits: FragmentLimits,
+        send_queue_config: SendQueueConfig,
     ) -> SharedState {
         let ft_logger = access_log_path.map(|access_log_path| {
             ftlog::builder()
@@ -77,6 +110,11 @@ impl State {
             ft_logger,
             cluster_client,
             cluster_manager,
+            rpc_registry,
+            decompress_limits,
+            fragment_limits,
+            send_queue_config,
+            send_queue_stats: Default::default(),
         })
     }
     pub fn is_new_connection_available(&self, url: &str) -> bool {
@@ -91,27 +129,19 @@ impl State {
         }
         true
     }
-    pub fn add_connection(self: &Arc<Self>, cd: Arc<ClientData>) -> bool {
-        loop {
-            let client_count = self.client_count.load(Ordering::SeqCst);
-            if self.max_connections.unwrap_or(usize::MAX) <= client_count as usize {
-                return false;
+    /// Fails if the session id is connected, remove_connection it first to replace.
+    pub fn add_connection(self: &Arc<Self>, cd: Arc<ClientData>) -> Result<(), AddConnectionError> {
+        match self.connection_map.entry(cd.session_id) {
+            dashmap::mapref::entry::Entry::Occupied(_) => {
+                return Err(AddConnectionError::AlreadyConnected);
             }
-            if self
-                .client_count
-                .compare_exchange(
-                    client_count,
-                    client_count + 1,
-                    Ordering::Acquire,
-                    Ordering::Relaxed,
-                )
-                .is_err()
-            {
-                continue;
+            dashmap::mapref::entry::Entry::Vacant(v) => {
+                if !self.reserve_client() {
+                    return Err(AddConnectionError::TooManyConnections);
+                }
+                v.insert(cd.clone());
             }
-            break;
         }
-        self.connection_map.insert(cd.session_id, cd.clone());
 
         if !match self.url_data_map.entry(cd.url.clone()) {
             dashmap::mapref::entry::Entry::Occupied(ref ud) => ud
@@ -125,9 +155,37 @@ impl State {
         } {
             self.client_count.fetch_sub(1, Ordering::SeqCst);
             self.connection_map.remove(&cd.session_id);
-            return false;
+            return Err(AddConnectionError::TooManyConnections);
+        }
+        if let Some(ud) = self.get_url_data(&cd.url) {
+            ud.push_membership_event(
+                MembershipEventType::Join,
+                &cd.session_id,
+                NodeType::Unspecified,
+            );
+        }
+        Ok(())
+    }
+    /// Counts up a client unless max_connections is reached.
+    fn reserve_client(&self) -> bool {
+        loop {
+            let client_count = self.client_count.load(Ordering::SeqCst);
+            if self.max_connections.unwrap_or(usize::MAX) <= client_count as usize {
+                return false;
+            }
+            if self
+                .client_count
+                .compare_exchange(
+                    client_count,
+                    client_count + 1,
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                )
+                .is_ok()
+            {
+                return true;
+            }
         }
-        true
     }
     pub fn remove_connection(self: &Arc<Self>, session_id: &SessionId) {
         if let Some((_, cd)) = self.connection_map.remove(session_id) {
@@ -136,6 +194,11 @@ impl State {
 
             if let Some(ud) = self.url_data_map.get(&cd.url).map(|v| v.clone()) {
                 ud.remove_connection(session_id);
+                ud.push_membership_event(
+                    MembershipEventType::Leave,
+                    session_id,
+                    NodeType::Unspecified,
+                );
             }
             self.url_data_map.remove_if(&cd.url, |_, ud| ud.is_empty());
         }
@@ -146,6 +209,113 @@ impl State {
     pub fn get_url_data(&self, url: &str) -> Option<Arc<UrlData>> {
         self.url_data_map.get(url).map(|v| v.clone())
     }
+    pub fn new_send_queue(&self) -> Arc<SendQueue> {
+        SendQueue::new(
+            self.send_queue_config.clone(),
+            self.send_queue_stats.clone(),
+        )
+    }
+    /// Gateway counts of each world, as of the last check.
+    pub fn get_gateway_metrics(&self) -> Vec<(String, i64)> {
+        let mut res = Vec::new();
+        for v in self.url_data_map.iter() {
+            let stats = v.get_gateway_stats();
+            let labels = format!("{{url=\"{}\"}}", escape_label(v.key()));
+            res.push((
+                format!("world_gateway_count{}", labels),
+                stats.gateway_count as i64,
+            ));
+            res.push((
+                format!("world_healthy_gateway_count{}", labels),
+                stats.healthy_gateway_count as i64,
+            ));
+            res.push((
+                format!("world_normal_count{}", labels),
+                stats.normal_count as i64,
+            ));
+            res.push((
+                format!("world_gateway_max_uptime_msec{}", labels),
+                stats.max_uptime_msec as i64,
+            ));
+        }
+        res
+    }
+    /// Partitions and gateway coverage of each world, as of the last partition check.
+    pub fn get_health_metrics(&self) -> Vec<(String, i64)> {
+        let worlds: Vec<(String, Arc<UrlData>)> = self
+            .url_data_map
+            .iter()
+            .map(|v| (v.key().clone(), v.value().clone()))
+            .collect();
+        let mut res = Vec::new();
+        for (url, ud) in worlds {
+            let Some(health) = ud.get_world_health() else {
+                continue;
+            };
+            let labels = format!("{{url=\"{}\"}}", escape_label(&url));
+            res.push((
+                format!("world_component_count{}", labels),
+                health.component_count as i64,
+            ));
+            res.push((
+                format!("world_isolated_count{}", labels),
+                health.isolated_count as i64,
+            ));
+            res.push((
+                format!("world_average_degree_permille{}", labels),
+                (health.average_degree * 1000.0) as i64,
+            ));
+            res.push((
+                format!("world_gateway_coverage_permille{}", labels),
+                (health.gateway_coverage * 1000.0) as i64,
+            ));
+            res.push((
+                format!("world_max_gateway_depth{}", labels),
+                health.max_gateway_depth as i64,
+            ));
+        }
+        res
+    }
+    pub fn get_membership_metrics(&self) -> Vec<(String, i64)> {
+        let mut subscribers = 0;
+        let mut dropped = 0;
+        for ud in self.url_data_map.iter() {
+            subscribers += ud.get_membership().get_subscriber_count();
+            dropped += ud.get_membership().get_dropped_count();
+        }
+        vec![
+            (
+                "membership_subscriber_count".to_string(),
+                subscribers as i64,
+            ),
+            ("membership_event_dropped_count".to_string(), dropped as i64),
+        ]
+    }
+    /// Total and max depth of the send queues.
+    /// Not per session, which would add a metric series for every client.
+    pub fn get_send_queue_metrics(&self) -> Vec<(String, i64)> {
+        let mut total = 0;
+        let mut max = 0;
+        for v in self.connection_map.iter() {
+            let depth = v.get_send_queue_depth();
+            total += depth;
+            max = max.max(depth);
+        }
+        vec![
+            ("send_queue_depth_total".to_string(), total as i64),
+            ("send_queue_depth_max".to_string(), max as i64),
+            (
+                "send_queue_dropped_count".to_string(),
+                self.send_queue_stats.dropped.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "send_queue_overflow_disconnect_count".to_string(),
+                self.send_queue_stats
+                    .overflow_disconnects
+                    .load(Ordering::Relaxed) as i64,
+            ),
+        ]
+    }
     pub async fn send_rpc_response(
         &self,
         to_session_id: &SessionId,
@@ -178,6 +348,13 @@ impl State {
 }
 pub type SharedState = Arc<State>;
 
+/// For a label value of prometheus.
+fn escape_label(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
 struct AccessLogFormatter {}
 impl FtLogFormat for AccessLogFormatter {
     #[inline]
@@ -219,30 +396,56 @@ mod tests {
             "/dev/null".into(),
             None,
             None,
+            RpcRegistry::new(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
         );
 
         let config = RTCConfiguration::default();
         let pc = Arc::new(state.api.new_peer_connection(config).await.unwrap());
 
-        state.add_connection(ClientData::new(
-            sid(1),
-            pc.clone(),
-            "https://example.domain/1".to_string(),
-        ));
+        state
+            .add_connection(ClientData::new(
+                sid(1),
+                pc.clone(),
+                "https://example.domain/1".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(
+            state.add_connection(ClientData::new(
+                sid(1),
+                pc.clone(),
+                "https://example.domain/1".to_string(),
+            )),
+            Err(AddConnectionError::AlreadyConnected)
+        );
         assert!(state.is_new_connection_available("https://example.domain/1"));
-        state.add_connection(ClientData::new(
-            sid(2),
-            pc.clone(),
-            "https://example.domain/1".to_string(),
-        ));
+        state
+            .add_connection(ClientData::new(
+                sid(2),
+                pc.clone(),
+                "https://example.domain/1".to_string(),
+            ))
+            .unwrap();
         assert!(!state.is_new_connection_available("https://example.domain/1"));
         assert!(state.is_new_connection_available("https://example.domain/2"));
 
-        state.add_connection(ClientData::new(
-            sid(3),
-            pc,
-            "https://example.domain/3".to_string(),
-        ));
+        state
+            .add_connection(ClientData::new(
+                sid(3),
+                pc.clone(),
+                "https://example.domain/3".to_string(),
+            ))
+            .unwrap();
+        assert_eq!(
+            state.add_connection(ClientData::new(
+                sid(4),
+                pc,
+                "https://example.domain/4".to_string(),
+            )),
+            Err(AddConnectionError::TooManyConnections)
+        );
         assert!(!state.is_new_connection_available("https://example.domain/1"));
         assert!(!state.is_new_connection_available("https://example.domain/2"));
         assert!(!state.is_new_connection_available("https://example.domain/3"));
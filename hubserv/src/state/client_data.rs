@@ -1,10 +1,18 @@
-use anyhow::Result;
+use super::send_queue::{PushResult, SendQueue, SendQueueConfig};
+use super::{RelayIndex, SpatialIndex};
+use crate::ids::RPC_ID_SWARM;
+use crate::rpc_handler::RpcError;
+use anyhow::{anyhow, Result};
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use once_cell::race::OnceBox;
 use parking_lot::Mutex;
+use prost::Message;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::Arc;
+use tokio::sync::mpsc;
 use verse_common::prelude::*;
+use verse_proto::rpc::RpcError as RpcErrorPacket;
 use verse_proto::rpc::*;
 use verse_proto::rpc::{rpc_packet, RpcPacket, RpcResponse};
 use verse_proto::swarm::*;
@@ -13,18 +21,38 @@ use webrtc::data_channel::RTCDataChannel;
 use webrtc::ice_transport::ice_candidate::RTCIceCandidateInit;
 use webrtc::peer_connection::RTCPeerConnection;
 
+// depth of the reported RoutingInfo kept for routing
+const NEIGHBORS_DEPTH: u32 = 4;
+
 pub struct ClientData {
     pub session_id: verse_session_id::SessionId,
-    pc: Arc<RTCPeerConnection>,
+    // None for a backend
+    pc: Option<Arc<RTCPeerConnection>>,
     dc: OnceBox<Arc<RTCDataChannel>>,
+    // SwarmPackets to a backend connected with SwarmBackendService, taken on dispose
+    backend: Mutex<Option<mpsc::Sender<SwarmPacket>>>,
     pub url: String,
     routing_info: Mutex<Option<Arc<RoutingInfo>>>,
+    // routing_info with relations, used to find a route to a non local session
+    neighbors: Mutex<Option<Arc<RoutingInfo>>>,
+    // the index of the world, updated with the reported position
+    spatial_index: Mutex<Option<Arc<SpatialIndex>>>,
+    // the relay index of the world, updated with the reported relations
+    relay_index: Mutex<Option<Arc<RelayIndex>>>,
+    // receives MembershipEvents of the world
+    subscribed: AtomicBool,
+    // 0: the client does not support RpcFragment
+    fragment_size: AtomicUsize,
+    fragment_message_id: AtomicU32,
+    fragment_assembler: Mutex<FragmentAssembler>,
+    codec: Mutex<RpcCodec>,
+    send_queue: Arc<SendQueue>,
 }
 impl Drop for ClientData {
     fn drop(&mut self) {
         debug!(
             "drop client {}, {}",
-            Arc::strong_count(&self.pc),
+            self.pc.as_ref().map_or_else(|| 0, Arc::strong_count),
             self.dc.get().map_or_else(|| 0, Arc::strong_count),
         );
     }
@@ -35,24 +63,95 @@ impl ClientData {
         session_id: verse_session_id::SessionId,
         pc: Arc<RTCPeerConnection>,
         url: String,
+    ) -> Arc<Self> {
+        Self::with_config(
+            session_id,
+            pc,
+            url,
+            SendQueue::new(SendQueueConfig::default(), Default::default()),
+            Default::default(),
+        )
+    }
+    pub fn with_config(
+        session_id: verse_session_id::SessionId,
+        pc: Arc<RTCPeerConnection>,
+        url: String,
+        send_queue: Arc<SendQueue>,
+        fragment_limits: FragmentLimits,
+    ) -> Arc<Self> {
+        Self::build(session_id, Some(pc), None, url, send_queue, fragment_limits)
+    }
+    /// A backend connected with SwarmBackendService. Swarm responses and requests
+    /// from the hub are sent to `tx` instead of a data channel.
+    pub fn new_backend(
+        session_id: verse_session_id::SessionId,
+        url: String,
+        tx: mpsc::Sender<SwarmPacket>,
+    ) -> Arc<Self> {
+        Self::build(
+            session_id,
+            None,
+            Some(tx),
+            url,
+            SendQueue::new(SendQueueConfig::default(), Default::default()),
+            Default::default(),
+        )
+    }
+    fn build(
+        session_id: verse_session_id::SessionId,
+        pc: Option<Arc<RTCPeerConnection>>,
+        backend: Option<mpsc::Sender<SwarmPacket>>,
+        url: String,
+        send_queue: Arc<SendQueue>,
+        fragment_limits: FragmentLimits,
     ) -> Arc<Self> {
         Arc::new(ClientData {
             session_id,
             pc,
             dc: Default::default(),
+            backend: Mutex::new(backend),
             url,
             routing_info: Mutex::new(None),
+            neighbors: Mutex::new(None),
+            spatial_index: Mutex::new(None),
+            relay_index: Mutex::new(None),
+            subscribed: AtomicBool::new(false),
+            fragment_size: AtomicUsize::new(0),
+            fragment_message_id: AtomicU32::new(0),
+            fragment_assembler: Mutex::new(FragmentAssembler::new(fragment_limits)),
+            codec: Mutex::new(RpcCodec::Zlib),
+            send_queue,
         })
     }
+    pub fn is_backend(&self) -> bool {
+        self.pc.is_none()
+    }
     pub fn get_dc(&self) -> Option<Arc<RTCDataChannel>> {
         self.dc.get().cloned()
     }
     pub fn set_dc(&self, dc: Arc<RTCDataChannel>) {
+        self.send_queue.start_writer(&dc);
         if self.dc.set(Box::new(dc)).is_err() {
             error!("dc already set");
         }
     }
+    /// Number of messages waiting to be sent.
+    pub fn get_send_queue_depth(&self) -> usize {
+        self.send_queue.depth()
+    }
     pub fn set_routing_info(&self, mut ri: RoutingInfo) {
+        let spatial_index = self.spatial_index.lock();
+        if let Some(index) = spatial_index.as_ref() {
+            index.update(self.session_id, ri.position.as_ref());
+        }
+        let neighbors = Arc::new(ri.to_send_data(NEIGHBORS_DEPTH));
+        {
+            let relay_index = self.relay_index.lock();
+            if let Some(index) = relay_index.as_ref() {
+                index.update(self.session_id, &neighbors);
+            }
+            *self.neighbors.lock() = Some(neighbors);
+        }
         ri.set_count(ri.get_relation_count() as u32);
         ri.known_gateway_session_ids.clear();
         *self.routing_info.lock() = Some(Arc::new(ri));
@@ -60,18 +159,68 @@ impl ClientData {
     pub fn get_routing_info(&self) -> Option<Arc<RoutingInfo>> {
         self.routing_info.lock().as_ref().cloned()
     }
+    /// None: stops updating the index, ex: after leaving the world.
+    pub fn set_spatial_index(&self, index: Option<Arc<SpatialIndex>>) {
+        let mut spatial_index = self.spatial_index.lock();
+        if let (Some(index), Some(ri)) = (index.as_ref(), self.get_routing_info()) {
+            index.update(self.session_id, ri.position.as_ref());
+        }
+        *spatial_index = index;
+    }
+    /// None: stops updating the index, ex: after leaving the world.
+    pub fn set_relay_index(&self, index: Option<Arc<RelayIndex>>) {
+        let mut relay_index = self.relay_index.lock();
+        if let (Some(index), Some(neighbors)) = (index.as_ref(), self.get_neighbors()) {
+            index.update(self.session_id, &neighbors);
+        }
+        *relay_index = index;
+    }
+    /// The reported RoutingInfo with its relations.
+    pub fn get_neighbors(&self) -> Option<Arc<RoutingInfo>> {
+        self.neighbors.lock().as_ref().cloned()
+    }
+    /// Returns the previous value.
+    pub fn set_subscribed(&self, subscribed: bool) -> bool {
+        self.subscribed.swap(subscribed, Ordering::AcqRel)
+    }
+    pub fn is_subscribed(&self) -> bool {
+        self.subscribed.load(Ordering::Acquire)
+    }
+    pub fn set_capabilities(&self, capabilities: &RpcCapabilities) {
+        self.fragment_size.store(
+            capabilities.get_fragment_size().unwrap_or(0),
+            Ordering::Relaxed,
+        );
+        *self.codec.lock() = capabilities.select_codec().unwrap_or(RpcCodec::Zlib);
+    }
+    /// Codec used to compress packets sent to this client.
+    pub fn get_codec(&self) -> RpcCodec {
+        *self.codec.lock()
+    }
+    pub fn push_fragment(&self, fragment: RpcFragment) -> Result<Option<Vec<u8>>> {
+        self.fragment_assembler
+            .lock()
+            .push(fragment, get_now_msec())
+    }
     pub async fn add_ice_candidate(&self, candidate: RTCIceCandidateInit) -> Result<()> {
-        self.pc
-            .add_ice_candidate(candidate)
+        let Some(pc) = self.pc.as_ref() else {
+            return Err(anyhow!("not a webrtc client"));
+        };
+        pc.add_ice_candidate(candidate)
             .await
             .map_err(anyhow::Error::from)
     }
     pub fn dispose(&self) {
+        self.send_queue.close();
+        // ends the response stream of the backend
+        self.backend.lock().take();
+        let Some(pc) = self.pc.clone() else {
+            return;
+        };
         {
-            self.pc
-                .on_data_channel(Box::new(move |_: Arc<RTCDataChannel>| {
-                    Box::pin(async move {})
-                }));
+            pc.on_data_channel(Box::new(move |_: Arc<RTCDataChannel>| {
+                Box::pin(async move {})
+            }));
             if let Some(dc) = self.get_dc() {
                 dc.on_message(Box::new(move |_: DataChannelMessage| {
                     Box::pin(async move {})
@@ -79,7 +228,6 @@ impl ClientData {
             }
         }
 
-        let pc = self.pc.clone();
         let dc = self.get_dc();
         if let Some(dc) = dc {
             let dc = Arc::downgrade(&dc);
@@ -122,21 +270,100 @@ impl ClientData {
     }
 
     pub async fn send_rpc_response(&self, rpc_id: u32, param: Vec<u8>) -> Result<bool> {
+        self.send_rpc_reply(rpc_id, 0, param).await
+    }
+    /// The response to the RpcRequest of `request_id`.
+    pub async fn send_rpc_reply(
+        &self,
+        rpc_id: u32,
+        request_id: u32,
+        param: Vec<u8>,
+    ) -> Result<bool> {
+        if self.is_backend() {
+            return self.send_backend_packet(rpc_id, param);
+        }
         let res_packet = RpcPacket {
-            data: Some(rpc_packet::Data::Response(RpcResponse { rpc_id, param })),
+            data: Some(rpc_packet::Data::Response(RpcResponse {
+                rpc_id,
+                param,
+                request_id,
+            })),
             ..Default::default()
         }
-        .encode_packet();
-        if res_packet.len() > 65535 {
-            error!("large data: {}", res_packet.len());
+        .encode_packet_as(self.get_codec());
+        self.send_packet(res_packet).await
+    }
+    pub async fn send_rpc_error(&self, rpc_id: u32, request_id: u32, e: &RpcError) -> Result<bool> {
+        if self.is_backend() {
+            // SwarmPacket can't carry RpcError
+            return Ok(false);
+        }
+        let mut err =
+            RpcErrorPacket::new(rpc_id, e.code(), e.client_message()).with_request_id(request_id);
+        if let Some(nested_rpc_id) = e.nested_rpc_id() {
+            err = err.with_nested_rpc_id(nested_rpc_id);
+        }
+        let mut res_packet = RpcPacket::default();
+        res_packet.set_error(err);
+        self.send_packet(res_packet.encode_packet_as(self.get_codec()))
+            .await
+    }
+    /// Only SwarmPackets reach a backend.
+    fn send_backend_packet(&self, rpc_id: u32, param: Vec<u8>) -> Result<bool> {
+        if rpc_id != RPC_ID_SWARM {
+            return Ok(false);
+        }
+        let packet = SwarmPacket::decode(param.as_slice())?;
+        let Some(tx) = self.backend.lock().clone() else {
+            return Ok(false);
+        };
+        match tx.try_send(packet) {
+            Ok(_) => Ok(true),
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                debug!("backend stream full: {}", self.session_id.to_debug_string());
+                Ok(false)
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => Ok(false),
+        }
+    }
+    /// Queues the packet for the writer task, so a slow receiver does not block the caller.
+    async fn send_packet(&self, res_packet: Vec<u8>) -> Result<bool> {
+        if self.get_dc().is_none() {
+            return Ok(false);
         }
 
-        if let Some(dc) = self.get_dc() {
-            dc.send(&bytes::Bytes::from(res_packet)).await?;
+        let fragment_size = self.fragment_size.load(Ordering::Relaxed);
+        let messages = if fragment_size > 0 {
+            let message_id = self.fragment_message_id.fetch_add(1, Ordering::Relaxed);
+            split_packet(&res_packet, message_id, fragment_size)
+                .map(|v| v.into_iter().map(bytes::Bytes::from).collect())
         } else {
-            return Ok(false);
+            if res_packet.len() > 65535 {
+                error!("large data: {}", res_packet.len());
+            }
+            None
         }
+        .unwrap_or_else(|| vec![bytes::Bytes::from(res_packet)]);
 
-        Ok(true)
+        match self.send_queue.push(messages) {
+            PushResult::Queued => Ok(true),
+            PushResult::DroppedOldest => {
+                debug!("send queue full: {}", self.session_id.to_debug_string());
+                Ok(true)
+            }
+            PushResult::DroppedNew => {
+                debug!("send queue full: {}", self.session_id.to_debug_string());
+                Ok(false)
+            }
+            PushResult::Overflow => {
+                info!(
+                    "send queue overflow, disconnect: {}",
+                    self.session_id.to_debug_string()
+                );
+                self.dispose();
+                Err(anyhow!("send queue overflow"))
+            }
+            PushResult::Closed => Ok(false),
+        }
     }
 }
@@ -0,0 +1,125 @@
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use verse_proto::swarm::*;
+
+/// Events within this period are sent together.
+pub const COALESCE_MSEC: u64 = 100;
+// events beyond this are dropped, subscribers find the gap of the sequence and resync
+const MAX_PENDING_EVENTS: usize = 1000;
+
+struct Pending {
+    sequence: u64,
+    events: Vec<MembershipEvent>,
+}
+
+/// Join, leave and gateway change events of a world waiting to be sent to the subscribers.
+pub struct Membership {
+    pending: Mutex<Pending>,
+    flush_scheduled: AtomicBool,
+    subscriber_count: AtomicUsize,
+    dropped_count: AtomicU64,
+}
+impl Membership {
+    pub fn new() -> Self {
+        Membership {
+            pending: Mutex::new(Pending {
+                sequence: 0,
+                events: Vec::new(),
+            }),
+            flush_scheduled: AtomicBool::new(false),
+            subscriber_count: AtomicUsize::new(0),
+            dropped_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Numbers the event. Returns true if the caller should schedule a flush.
+    pub fn push(&self, mut event: MembershipEvent) -> bool {
+        {
+            let mut pending = self.pending.lock();
+            pending.sequence += 1;
+            if self.get_subscriber_count() == 0 {
+                return false;
+            }
+            event.sequence = pending.sequence;
+            if pending.events.len() < MAX_PENDING_EVENTS {
+                pending.events.push(event);
+            } else {
+                self.dropped_count.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        !self.flush_scheduled.swap(true, Ordering::AcqRel)
+    }
+
+    /// Takes the pending events, in the order of the sequence.
+    pub fn take(&self) -> Vec<MembershipEvent> {
+        let mut pending = self.pending.lock();
+        self.flush_scheduled.store(false, Ordering::Release);
+        std::mem::take(&mut pending.events)
+    }
+
+    /// Sequence of the last event.
+    pub fn get_sequence(&self) -> u64 {
+        self.pending.lock().sequence
+    }
+
+    pub fn add_subscriber(&self) {
+        self.subscriber_count.fetch_add(1, Ordering::Relaxed);
+    }
+    pub fn remove_subscriber(&self) {
+        self.subscriber_count.fetch_sub(1, Ordering::Relaxed);
+    }
+    pub fn get_subscriber_count(&self) -> usize {
+        self.subscriber_count.load(Ordering::Relaxed)
+    }
+    pub fn get_dropped_count(&self) -> u64 {
+        self.dropped_count.load(Ordering::Relaxed)
+    }
+}
+impl Default for Membership {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(n: u8) -> MembershipEvent {
+        MembershipEvent {
+            r#type: MembershipEventType::Join.into(),
+            session_id: vec![n; 32],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_membership() {
+        let m = Membership::new();
+        // not kept without subscribers
+        assert!(!m.push(event(1)));
+        assert_eq!(m.get_sequence(), 1);
+        assert!(m.take().is_empty());
+
+        m.add_subscriber();
+        assert!(m.push(event(2)));
+        // a flush is already scheduled
+        assert!(!m.push(event(3)));
+        let events = m.take();
+        assert_eq!(
+            events.iter().map(|v| v.sequence).collect::<Vec<_>>(),
+            vec![2, 3]
+        );
+        assert!(m.push(event(4)));
+
+        for n in 0..MAX_PENDING_EVENTS {
+            m.push(event(n as u8));
+        }
+        assert_eq!(m.take().len(), MAX_PENDING_EVENTS);
+        assert_eq!(m.get_dropped_count(), 1);
+        assert_eq!(m.get_sequence(), 4 + MAX_PENDING_EVENTS as u64);
+
+        m.remove_subscriber();
+        assert_eq!(m.get_subscriber_count(), 0);
+    }
+}
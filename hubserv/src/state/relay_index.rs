@@ -0,0 +1,129 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use verse_proto::swarm::*;
+use verse_session_id::SessionId;
+
+#[derive(Default)]
+struct Inner {
+    // reachable session -> (relay -> hops)
+    relays: HashMap<SessionId, HashMap<SessionId, u32>>,
+    // relay -> sessions it reaches, to remove them when the relay reports again
+    reachable: HashMap<SessionId, Vec<SessionId>>,
+}
+
+/// Sessions reachable through the RoutingInfo reported by the clients in a world,
+/// so that a route is found without walking every client's relations.
+#[derive(Default)]
+pub struct RelayIndex {
+    inner: Mutex<Inner>,
+}
+impl RelayIndex {
+    /// Replaces the sessions reachable through `relay` with the relations of `neighbors`.
+    pub fn update(&self, relay: SessionId, neighbors: &RoutingInfo) {
+        let mut depths: HashMap<SessionId, u32> = HashMap::new();
+        for (ri, depth) in neighbors.recursive_iter_with_depth() {
+            if depth == 0 {
+                continue;
+            }
+            let Some(session_id) = ri
+                .session_id
+                .as_ref()
+                .and_then(|v| SessionId::try_from(v).ok())
+            else {
+                continue;
+            };
+            if session_id == relay {
+                continue;
+            }
+            // breadth first, the first one is the nearest
+            depths.entry(session_id).or_insert(depth);
+        }
+
+        let mut inner = self.inner.lock();
+        remove_relay(&mut inner, &relay);
+        for (session_id, depth) in depths.iter() {
+            inner
+                .relays
+                .entry(*session_id)
+                .or_default()
+                .insert(relay, *depth);
+        }
+        inner.reachable.insert(relay, depths.into_keys().collect());
+    }
+
+    pub fn remove(&self, relay: &SessionId) {
+        remove_relay(&mut self.inner.lock(), relay);
+    }
+
+    /// The relay that reaches `to` in the fewest hops, within `max_depth`.
+    pub fn find(
+        &self,
+        to: &SessionId,
+        exclude: &SessionId,
+        max_depth: u32,
+    ) -> Option<(SessionId, u32)> {
+        let inner = self.inner.lock();
+        inner
+            .relays
+            .get(to)?
+            .iter()
+            .filter(|(relay, depth)| *relay != exclude && **depth <= max_depth)
+            .min_by_key(|(_, depth)| **depth)
+            .map(|(relay, depth)| (*relay, *depth))
+    }
+}
+
+fn remove_relay(inner: &mut Inner, relay: &SessionId) {
+    let Some(reachable) = inner.reachable.remove(relay) else {
+        return;
+    };
+    for session_id in reachable {
+        if let Some(relays) = inner.relays.get_mut(&session_id) {
+            relays.remove(relay);
+            if relays.is_empty() {
+                inner.relays.remove(&session_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(n: u8) -> SessionId {
+        [n; 32].into()
+    }
+    fn ri(n: u8, relations: Vec<RoutingInfo>) -> RoutingInfo {
+        let mut ri = RoutingInfo {
+            session_id: Some([n; 32].to_vec()),
+            ..Default::default()
+        };
+        ri.set_relations(relations);
+        ri
+    }
+
+    #[test]
+    fn test_relay_index() {
+        let index = RelayIndex::default();
+        index.update(id(0), &ri(0, vec![ri(2, vec![ri(3, vec![ri(9, vec![])])])]));
+        index.update(
+            id(1),
+            &ri(1, vec![ri(3, vec![ri(9, vec![])]), ri(9, vec![])]),
+        );
+        assert_eq!(index.find(&id(9), &id(5), 10), Some((id(1), 1)));
+        assert_eq!(index.find(&id(9), &id(1), 10), Some((id(0), 3)));
+        assert_eq!(index.find(&id(9), &id(1), 2), None);
+        assert_eq!(index.find(&id(0), &id(5), 10), None);
+
+        // reported again without 9
+        index.update(id(1), &ri(1, vec![ri(3, vec![])]));
+        assert_eq!(index.find(&id(9), &id(5), 10), Some((id(0), 3)));
+        assert_eq!(index.find(&id(3), &id(5), 10), Some((id(1), 1)));
+
+        index.remove(&id(0));
+        assert_eq!(index.find(&id(9), &id(5), 10), None);
+        index.remove(&id(1));
+        assert!(index.inner.lock().relays.is_empty());
+    }
+}
@@ -0,0 +1,272 @@
+use bytes::Bytes;
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use parking_lot::Mutex;
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::time::timeout;
+use webrtc::data_channel::RTCDataChannel;
+
+// in case on_buffered_amount_low is not called
+const BUFFERED_AMOUNT_POLL_MSEC: u64 = 100;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum OverflowPolicy {
+    DropOldest,
+    DropNew,
+    Disconnect,
+}
+
+#[derive(Clone, Debug)]
+pub struct SendQueueConfig {
+    /// Maximum number of messages waiting to be sent.
+    pub capacity: usize,
+    /// The writer waits while the data channel buffers more than this.
+    pub max_buffered_amount: usize,
+    pub policy: OverflowPolicy,
+}
+impl Default for SendQueueConfig {
+    fn default() -> Self {
+        SendQueueConfig {
+            capacity: 256,
+            max_buffered_amount: 1024 * 1024,
+            policy: OverflowPolicy::DropOldest,
+        }
+    }
+}
+
+/// Shared by the queues of all clients.
+#[derive(Default)]
+pub struct SendQueueStats {
+    pub dropped: AtomicU64,
+    pub overflow_disconnects: AtomicU64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum PushResult {
+    Queued,
+    /// Queued after dropping the oldest packets.
+    DroppedOldest,
+    /// Not queued. Also for a packet with more messages than the capacity.
+    DroppedNew,
+    /// The queue is full and the policy is Disconnect.
+    Overflow,
+    Closed,
+}
+
+pub struct SendQueue {
+    config: SendQueueConfig,
+    stats: Arc<SendQueueStats>,
+    /// Each entry is the messages of one packet, dropped and sent as a unit
+    /// so that the peer never gets a part of the fragments.
+    queue: Mutex<VecDeque<Vec<Bytes>>>,
+    /// Number of messages in `queue`.
+    depth: AtomicUsize,
+    notify: Notify,
+    closed: AtomicBool,
+}
+impl SendQueue {
+    pub fn new(config: SendQueueConfig, stats: Arc<SendQueueStats>) -> Arc<Self> {
+        Arc::new(SendQueue {
+            config,
+            stats,
+            queue: Mutex::new(VecDeque::new()),
+            depth: AtomicUsize::new(0),
+            notify: Notify::new(),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// Queues `messages` (ex: the fragments of one packet) as a unit.
+    pub fn push(&self, messages: Vec<Bytes>) -> PushResult {
+        if self.is_closed() {
+            return PushResult::Closed;
+        }
+        let mut res = PushResult::Queued;
+        {
+            let mut queue = self.queue.lock();
+            let depth = self.depth.load(Ordering::Relaxed);
+            if depth + messages.len() > self.config.capacity {
+                if messages.len() > self.config.capacity {
+                    self.drop_new(&messages);
+                    return PushResult::DroppedNew;
+                }
+                match self.config.policy {
+                    OverflowPolicy::DropNew => {
+                        self.drop_new(&messages);
+                        return PushResult::DroppedNew;
+                    }
+                    OverflowPolicy::Disconnect => {
+                        self.stats
+                            .overflow_disconnects
+                            .fetch_add(1, Ordering::Relaxed);
+                        return PushResult::Overflow;
+                    }
+                    OverflowPolicy::DropOldest => {
+                        res = PushResult::DroppedOldest;
+                        let mut dropped = 0;
+                        while depth - dropped + messages.len() > self.config.capacity {
+                            let Some(v) = queue.pop_front() else {
+                                break;
+                            };
+                            dropped += v.len();
+                        }
+                        self.depth.fetch_sub(dropped, Ordering::Relaxed);
+                        self.stats
+                            .dropped
+                            .fetch_add(dropped as u64, Ordering::Relaxed);
+                    }
+                }
+            }
+            self.depth.fetch_add(messages.len(), Ordering::Relaxed);
+            queue.push_back(messages);
+        }
+        self.notify.notify_one();
+        res
+    }
+
+    fn drop_new(&self, messages: &[Bytes]) {
+        self.stats
+            .dropped
+            .fetch_add(messages.len() as u64, Ordering::Relaxed);
+    }
+
+    /// Number of queued messages.
+    pub fn depth(&self) -> usize {
+        self.depth.load(Ordering::Relaxed)
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
+
+    /// Stops the writer task. Queued messages are discarded.
+    pub fn close(&self) {
+        self.closed.store(true, Ordering::Relaxed);
+        self.queue.lock().clear();
+        self.depth.store(0, Ordering::Relaxed);
+        self.notify.notify_one();
+    }
+
+    /// The messages of the oldest packet.
+    async fn pop(&self) -> Option<Vec<Bytes>> {
+        loop {
+            if self.is_closed() {
+                return None;
+            }
+            if let Some(v) = self.queue.lock().pop_front() {
+                self.depth.fetch_sub(v.len(), Ordering::Relaxed);
+                return Some(v);
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    /// Spawns the task that sends queued messages to `dc`.
+    pub fn start_writer(self: &Arc<Self>, dc: &Arc<RTCDataChannel>) {
+        let queue = self.clone();
+        let dc = Arc::downgrade(dc);
+        tokio::spawn(async move {
+            queue.run_writer(dc).await;
+        });
+    }
+
+    async fn run_writer(&self, dc: Weak<RTCDataChannel>) {
+        let low = Arc::new(Notify::new());
+        {
+            let Some(dc) = dc.upgrade() else {
+                return;
+            };
+            dc.set_buffered_amount_low_threshold(self.config.max_buffered_amount / 2)
+                .await;
+            let low = low.clone();
+            dc.on_buffered_amount_low(Box::new(move || {
+                let low = low.clone();
+                Box::pin(async move {
+                    low.notify_one();
+                })
+            }))
+            .await;
+        }
+
+        while let Some(messages) = self.pop().await {
+            for data in messages {
+                let Some(dc) = dc.upgrade() else {
+                    return;
+                };
+                while dc.buffered_amount().await > self.config.max_buffered_amount {
+                    let _ = timeout(
+                        Duration::from_millis(BUFFERED_AMOUNT_POLL_MSEC),
+                        low.notified(),
+                    )
+                    .await;
+                    if self.is_closed() {
+                        return;
+                    }
+                }
+                if let Err(e) = dc.send(&data).await {
+                    debug!("send failed: {:?}", e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_queue(policy: OverflowPolicy) -> (Arc<SendQueue>, Arc<SendQueueStats>) {
+        let stats = Arc::new(SendQueueStats::default());
+        let queue = SendQueue::new(
+            SendQueueConfig {
+                capacity: 3,
+                policy,
+                ..Default::default()
+            },
+            stats.clone(),
+        );
+        (queue, stats)
+    }
+    fn msgs(v: &[u8]) -> Vec<Bytes> {
+        v.iter().map(|v| Bytes::from(vec![*v])).collect()
+    }
+
+    #[tokio::test]
+    async fn test_overflow_policy() {
+        let (queue, stats) = new_queue(OverflowPolicy::DropOldest);
+        assert_eq!(queue.push(msgs(&[1])), PushResult::Queued);
+        assert_eq!(queue.push(msgs(&[2, 3])), PushResult::Queued);
+        assert_eq!(queue.push(msgs(&[4])), PushResult::DroppedOldest);
+        assert_eq!(queue.depth(), 3);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 1);
+        // the fragments of a packet are dropped together
+        assert_eq!(queue.push(msgs(&[5])), PushResult::DroppedOldest);
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 3);
+        assert_eq!(queue.pop().await.unwrap(), msgs(&[4]));
+        assert_eq!(queue.depth(), 1);
+        assert_eq!(queue.push(msgs(&[6, 7, 8, 9])), PushResult::DroppedNew);
+        assert_eq!(queue.depth(), 1);
+
+        let (queue, stats) = new_queue(OverflowPolicy::DropNew);
+        assert_eq!(queue.push(msgs(&[1, 2])), PushResult::Queued);
+        assert_eq!(queue.push(msgs(&[3, 4])), PushResult::DroppedNew);
+        assert_eq!(queue.depth(), 2);
+        assert_eq!(stats.dropped.load(Ordering::Relaxed), 2);
+        assert_eq!(queue.pop().await.unwrap(), msgs(&[1, 2]));
+
+        let (queue, stats) = new_queue(OverflowPolicy::Disconnect);
+        assert_eq!(queue.push(msgs(&[1, 2, 3])), PushResult::Queued);
+        assert_eq!(queue.push(msgs(&[4])), PushResult::Overflow);
+        assert_eq!(stats.overflow_disconnects.load(Ordering::Relaxed), 1);
+
+        queue.close();
+        assert_eq!(queue.depth(), 0);
+        assert_eq!(queue.push(msgs(&[1])), PushResult::Closed);
+        assert!(queue.pop().await.is_none());
+    }
+}
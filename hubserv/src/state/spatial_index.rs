@@ -0,0 +1,235 @@
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use verse_proto::primitive::Position3D;
+use verse_session_id::SessionId;
+
+pub const DEFAULT_CELL_SIZE: f64 = 64.0;
+
+type Cell = (i64, i64, i64);
+type Point = [f64; 3];
+
+#[derive(Default)]
+struct Inner {
+    cells: HashMap<Cell, HashSet<SessionId>>,
+    positions: HashMap<SessionId, (Point, Cell)>,
+}
+
+/// Uniform grid of the reported positions of the clients in a world.
+pub struct SpatialIndex {
+    cell_size: f64,
+    inner: Mutex<Inner>,
+}
+impl SpatialIndex {
+    pub fn new(cell_size: f64) -> Self {
+        SpatialIndex {
+            cell_size,
+            inner: Mutex::new(Inner::default()),
+        }
+    }
+
+    /// Removes the session if `position` is None or not finite.
+    pub fn update(&self, session_id: SessionId, position: Option<&Position3D>) {
+        let Some(p) = position.and_then(position_to_point) else {
+            self.remove(&session_id);
+            return;
+        };
+        let cell = self.to_cell(&p);
+        let mut inner = self.inner.lock();
+        if let Some((_, old)) = inner.positions.insert(session_id, (p, cell)) {
+            if old == cell {
+                return;
+            }
+            remove_from_cell(&mut inner.cells, &old, &session_id);
+        }
+        inner.cells.entry(cell).or_default().insert(session_id);
+    }
+
+    pub fn remove(&self, session_id: &SessionId) {
+        let mut inner = self.inner.lock();
+        if let Some((_, cell)) = inner.positions.remove(session_id) {
+            remove_from_cell(&mut inner.cells, &cell, session_id);
+        }
+    }
+
+    pub fn get_position(&self, session_id: &SessionId) -> Option<Point> {
+        self.inner.lock().positions.get(session_id).map(|v| v.0)
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().positions.len()
+    }
+
+    /// Up to `n` sessions nearest to `center`, nearest first.
+    pub fn nearest(&self, center: &Point, n: usize, exclude: &SessionId) -> Vec<SessionId> {
+        if n == 0 {
+            return Vec::new();
+        }
+        let inner = self.inner.lock();
+        let c = self.to_cell(center);
+        let mut found: Vec<(f64, SessionId)> = Vec::new();
+        let mut visited = 0;
+        let mut r: i64 = 0;
+        // visits the cells ring by ring until no unvisited cell can be nearer
+        while visited < inner.positions.len() {
+            if (2 * r + 1).pow(3) as usize > inner.cells.len() * 8 {
+                // sparse, scanning all is cheaper
+                found = collect(&inner, inner.cells.keys(), center, exclude);
+                break;
+            }
+            for cell in ring(&c, r) {
+                if let Some(ids) = inner.cells.get(&cell) {
+                    visited += ids.len();
+                    found.extend(
+                        ids.iter()
+                            .filter(|v| !v.eq(&exclude))
+                            .map(|v| (distance(&inner.positions[v].0, center), *v)),
+                    );
+                }
+            }
+            if n <= found.len() {
+                found.sort_by(|a, b| a.0.total_cmp(&b.0));
+                if found[n - 1].0 <= r as f64 * self.cell_size {
+                    break;
+                }
+            }
+            r += 1;
+        }
+        found.sort_by(|a, b| a.0.total_cmp(&b.0));
+        found.into_iter().take(n).map(|v| v.1).collect()
+    }
+
+    /// Sessions within `radius` of `center`, nearest first.
+    pub fn within(&self, center: &Point, radius: f64, exclude: &SessionId) -> Vec<SessionId> {
+        let inner = self.inner.lock();
+        let c = self.to_cell(center);
+        let r = (radius / self.cell_size).ceil();
+        let mut found = if (2.0 * r + 1.0).powi(3) > inner.cells.len() as f64 {
+            collect(&inner, inner.cells.keys(), center, exclude)
+        } else {
+            let cells: Vec<Cell> = (0..=r as i64).flat_map(|r| ring(&c, r)).collect();
+            collect(&inner, cells.iter(), center, exclude)
+        };
+        found.retain(|v| v.0 <= radius);
+        found.sort_by(|a, b| a.0.total_cmp(&b.0));
+        found.into_iter().map(|v| v.1).collect()
+    }
+
+    fn to_cell(&self, p: &Point) -> Cell {
+        let f = |v: f64| (v / self.cell_size).floor() as i64;
+        (f(p[0]), f(p[1]), f(p[2]))
+    }
+}
+impl Default for SpatialIndex {
+    fn default() -> Self {
+        Self::new(DEFAULT_CELL_SIZE)
+    }
+}
+
+pub fn position_to_point(p: &Position3D) -> Option<Point> {
+    let p = [p.x, p.y as f64, p.z];
+    p.iter().all(|v| v.is_finite()).then_some(p)
+}
+
+fn distance(a: &Point, b: &Point) -> f64 {
+    a.iter()
+        .zip(b.iter())
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f64>()
+        .sqrt()
+}
+
+fn remove_from_cell(cells: &mut HashMap<Cell, HashSet<SessionId>>, cell: &Cell, id: &SessionId) {
+    if let Some(ids) = cells.get_mut(cell) {
+        ids.remove(id);
+        if ids.is_empty() {
+            cells.remove(cell);
+        }
+    }
+}
+
+fn collect<'a>(
+    inner: &Inner,
+    cells: impl Iterator<Item = &'a Cell>,
+    center: &Point,
+    exclude: &SessionId,
+) -> Vec<(f64, SessionId)> {
+    cells
+        .filter_map(|v| inner.cells.get(v))
+        .flatten()
+        .filter(|v| !v.eq(&exclude))
+        .map(|v| (distance(&inner.positions[v].0, center), *v))
+        .collect()
+}
+
+/// The cells whose chebyshev distance from `c` is `r`.
+fn ring(c: &Cell, r: i64) -> impl Iterator<Item = Cell> + '_ {
+    (-r..=r).flat_map(move |x| {
+        (-r..=r).flat_map(move |y| {
+            let on_face = x.abs() == r || y.abs() == r;
+            let zs: Vec<i64> = if on_face {
+                (-r..=r).collect()
+            } else if r == 0 {
+                vec![0]
+            } else {
+                vec![-r, r]
+            };
+            zs.into_iter().map(move |z| (c.0 + x, c.1 + y, c.2 + z))
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pos(x: f64, y: f32, z: f64) -> Position3D {
+        Position3D { x, y, z }
+    }
+    fn id(n: u8) -> SessionId {
+        [n; 32].into()
+    }
+
+    #[test]
+    fn test_ring() {
+        assert_eq!(ring(&(0, 0, 0), 0).count(), 1);
+        assert_eq!(ring(&(0, 0, 0), 1).count(), 26);
+        assert_eq!(ring(&(0, 0, 0), 2).count(), 125 - 27);
+    }
+
+    #[test]
+    fn test_spatial_index() {
+        let index = SpatialIndex::new(10.0);
+        for n in 1..=20u8 {
+            index.update(id(n), Some(&pos(n as f64 * 7.0, 0.0, -(n as f64) * 3.0)));
+        }
+        index.update(id(30), Some(&pos(f64::NAN, 0.0, 0.0)));
+        index.update(id(31), None);
+        assert_eq!(index.len(), 20);
+
+        let center = [7.0 * 5.0, 0.0, -3.0 * 5.0];
+        let res = index.nearest(&center, 3, &id(0));
+        assert_eq!(res[0], id(5));
+        assert!(res.contains(&id(4)) && res.contains(&id(6)));
+        let res = index.nearest(&center, 3, &id(5));
+        assert!(res[..2].contains(&id(4)) && res[..2].contains(&id(6)));
+        assert!(!res.contains(&id(5)));
+        assert_eq!(index.nearest(&center, 100, &id(0)).len(), 20);
+        assert!(index.nearest(&center, 0, &id(0)).is_empty());
+
+        let res = index.within(&center, 8.0, &id(0));
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0], id(5));
+        let res = index.within(&center, 16.0, &id(5));
+        assert_eq!(res.len(), 4);
+
+        // moves to another cell
+        index.update(id(5), Some(&pos(1000.0, 0.0, 1000.0)));
+        assert_eq!(index.nearest(&[1000.0, 0.0, 990.0], 1, &id(0)), vec![id(5)]);
+        index.remove(&id(5));
+        assert_eq!(index.len(), 19);
+        assert!(index.get_position(&id(5)).is_none());
+        assert!(index
+            .within(&[1000.0, 0.0, 1000.0], 100.0, &id(0))
+            .is_empty());
+    }
+}
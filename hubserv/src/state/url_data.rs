@@ -1,27 +1,60 @@
-use super::ClientData;
+use super::membership::COALESCE_MSEC;
+use super::{ClientData, Membership, RelayIndex, SpatialIndex, WorldHealth};
+use crate::ids::*;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use parking_lot::{Mutex, RwLock};
+use prost::Message;
 use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use verse_common::prelude::*;
 use verse_proto::swarm::*;
 const ROUTING_INFO_UPDATE_INTERVAL_SECONDS: i64 = 5;
 const ROUTING_INFO_MAX: usize = 1000;
 
+/// Gateways of a world. Uptime is from the reported GatewayState, in msec.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct GatewayStats {
+    pub gateway_count: usize,
+    /// Connected to the tracker for at least the minimum uptime.
+    pub healthy_gateway_count: usize,
+    pub normal_count: usize,
+    pub max_uptime_msec: u64,
+}
+
 pub struct UrlData {
     clients: Mutex<Vec<Arc<ClientData>>>,
     client_count: AtomicU64,
     routing_info_updated: AtomicI64,
     routing_info: RwLock<Arc<RoutingInfo>>,
+    spatial_index: Arc<SpatialIndex>,
+    relay_index: Arc<RelayIndex>,
+    membership: Membership,
+    gateway_checked: AtomicI64,
+    gateway_stats: Mutex<GatewayStats>,
+    partition_checked: AtomicI64,
+    world_health: Mutex<Option<WorldHealth>>,
 }
 
 impl UrlData {
     pub fn new(cd: Arc<ClientData>) -> Arc<Self> {
+        let spatial_index = Arc::new(SpatialIndex::default());
+        let relay_index = Arc::new(RelayIndex::default());
+        cd.set_spatial_index(Some(spatial_index.clone()));
+        cd.set_relay_index(Some(relay_index.clone()));
         Arc::new(UrlData {
             clients: Mutex::new(vec![cd]),
             client_count: AtomicU64::new(1),
             routing_info_updated: AtomicI64::new(0),
             routing_info: RwLock::new(Arc::new(Self::create_default_routing_info())),
+            spatial_index,
+            relay_index,
+            membership: Default::default(),
+            gateway_checked: AtomicI64::new(0),
+            gateway_stats: Default::default(),
+            partition_checked: AtomicI64::new(0),
+            world_health: Default::default(),
         })
     }
     #[cfg(test)]
@@ -31,6 +64,13 @@ impl UrlData {
             client_count: AtomicU64::new(1),
             routing_info_updated: AtomicI64::new(0),
             routing_info: RwLock::new(Arc::new(Self::create_default_routing_info())),
+            spatial_index: Default::default(),
+            relay_index: Default::default(),
+            membership: Default::default(),
+            gateway_checked: AtomicI64::new(0),
+            gateway_stats: Default::default(),
+            partition_checked: AtomicI64::new(0),
+            world_health: Default::default(),
         })
     }
 
@@ -91,6 +131,141 @@ impl UrlData {
         }
     }
 
+    /// The client whose RoutingInfo reaches `to_session_id` in the fewest hops,
+    /// and the number of hops. Clients further than `max_depth` are ignored.
+    pub fn find_relay(
+        &self,
+        to_session_id: &verse_session_id::SessionId,
+        exclude_session_id: &verse_session_id::SessionId,
+        max_depth: u32,
+    ) -> Option<(verse_session_id::SessionId, u32)> {
+        self.relay_index
+            .find(to_session_id, exclude_session_id, max_depth)
+    }
+
+    /// Positions reported by the clients.
+    pub fn get_spatial_index(&self) -> &SpatialIndex {
+        &self.spatial_index
+    }
+
+    /// Counts the gateways, and keeps the result for get_gateway_stats.
+    pub fn update_gateway_stats(&self, now_msec: u64, min_uptime_msec: u64) -> GatewayStats {
+        let mut stats = GatewayStats::default();
+        for ri in self
+            .get_clients()
+            .iter()
+            .filter_map(|v| v.get_routing_info())
+        {
+            match ri.node_type() {
+                NodeType::Gateway => {
+                    stats.gateway_count += 1;
+                    let Some(uptime) = get_gateway_uptime_msec(&ri, now_msec) else {
+                        continue;
+                    };
+                    if min_uptime_msec <= uptime {
+                        stats.healthy_gateway_count += 1;
+                    }
+                    stats.max_uptime_msec = stats.max_uptime_msec.max(uptime);
+                }
+                NodeType::Normal => stats.normal_count += 1,
+                _ => {}
+            }
+        }
+        *self.gateway_stats.lock() = stats.clone();
+        stats
+    }
+    /// The result of the last update_gateway_stats.
+    pub fn get_gateway_stats(&self) -> GatewayStats {
+        self.gateway_stats.lock().clone()
+    }
+    /// Kept by the partition check, for get_world_health.
+    pub fn set_world_health(&self, health: WorldHealth) {
+        *self.world_health.lock() = Some(health);
+    }
+    /// None until the first partition check.
+    pub fn get_world_health(&self) -> Option<WorldHealth> {
+        self.world_health.lock().clone()
+    }
+    /// Returns false if checked within `interval_sec`, so that one caller checks at a time.
+    pub fn try_start_gateway_check(&self, interval_sec: i64) -> bool {
+        try_start_check(&self.gateway_checked, interval_sec)
+    }
+    /// Same as try_start_gateway_check, for partitions.
+    pub fn try_start_partition_check(&self, interval_sec: i64) -> bool {
+        try_start_check(&self.partition_checked, interval_sec)
+    }
+    /// Normal nodes with the most relations.
+    pub fn get_gateway_candidates(&self, n: usize) -> Vec<Arc<ClientData>> {
+        let mut candidates: Vec<(usize, Arc<ClientData>)> = self
+            .get_clients()
+            .into_iter()
+            .filter_map(|cd| {
+                let ri = cd.get_routing_info()?;
+                (ri.node_type() == NodeType::Normal).then(|| (ri.get_relation_count(), cd))
+            })
+            .collect();
+        candidates.sort_by_key(|v| std::cmp::Reverse(v.0));
+        candidates.into_iter().take(n).map(|v| v.1).collect()
+    }
+
+    pub fn get_membership(&self) -> &Membership {
+        &self.membership
+    }
+    /// Returns the sequence of the last MembershipEvent.
+    pub fn set_subscribed(&self, cd: &ClientData, subscribed: bool) -> u64 {
+        if cd.set_subscribed(subscribed) != subscribed {
+            if subscribed {
+                self.membership.add_subscriber();
+            } else {
+                self.membership.remove_subscriber();
+            }
+        }
+        self.membership.get_sequence()
+    }
+    /// Sends the event to the subscribers after COALESCE_MSEC, with the others in the meantime.
+    pub fn push_membership_event(
+        self: &Arc<Self>,
+        event_type: MembershipEventType,
+        session_id: &verse_session_id::SessionId,
+        node_type: NodeType,
+    ) {
+        let event = MembershipEvent {
+            r#type: event_type.into(),
+            session_id: session_id.to_vec(),
+            node_type: node_type.into(),
+            ..Default::default()
+        };
+        if self.membership.push(event) {
+            let ud = self.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Duration::from_millis(COALESCE_MSEC)).await;
+                ud.flush_membership_events().await;
+            });
+        }
+    }
+    async fn flush_membership_events(&self) {
+        let events = self.membership.take();
+        if events.is_empty() {
+            return;
+        }
+        let packet = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest {
+                rpc_id: RPC_ID_MEMBERSHIP_EVENTS,
+                param: MembershipEvents { events }.encode_to_vec(),
+            })),
+        }
+        .encode_to_vec();
+        for cd in self.get_clients().iter().filter(|v| v.is_subscribed()) {
+            cd.send_rpc_response(RPC_ID_SWARM, packet.clone())
+                .await
+                .if_err_info(logmsg!("can't send membership events"));
+        }
+    }
+
+    pub fn get_clients(&self) -> Vec<Arc<ClientData>> {
+        self.clients.lock().clone()
+    }
+
     pub fn is_empty(&self) -> bool {
         self.get_client_count() == 0
     }
@@ -120,6 +295,8 @@ impl UrlData {
             {
                 continue;
             }
+            cd.set_spatial_index(Some(self.spatial_index.clone()));
+            cd.set_relay_index(Some(self.relay_index.clone()));
             self.clients.lock().push(cd);
             return true;
         }
@@ -128,8 +305,20 @@ impl UrlData {
         self.client_count.fetch_sub(1, Ordering::SeqCst);
         {
             let mut clients = self.clients.lock();
-            clients.retain(|v| !v.session_id.eq(session_id));
+            clients.retain(|v| {
+                if v.session_id.eq(session_id) {
+                    v.set_spatial_index(None);
+                    v.set_relay_index(None);
+                    if v.set_subscribed(false) {
+                        self.membership.remove_subscriber();
+                    }
+                    return false;
+                }
+                true
+            });
         }
+        self.spatial_index.remove(session_id);
+        self.relay_index.remove(session_id);
         let mut routing_info = self.routing_info.write();
         let mut new_ri = (&*routing_info as &RoutingInfo).clone();
         if let Some(routing_info::Relation::RoutingInfos(v)) = new_ri.relation.as_mut() {
@@ -147,6 +336,27 @@ fn get_now_sec() -> i64 {
     chrono::Local::now().timestamp()
 }
 
+fn try_start_check(checked_at: &AtomicI64, interval_sec: i64) -> bool {
+    let now = get_now_sec();
+    let checked = checked_at.load(Ordering::Relaxed);
+    if now - checked < interval_sec {
+        return false;
+    }
+    checked_at
+        .compare_exchange(checked, now, Ordering::Acquire, Ordering::Relaxed)
+        .is_ok()
+}
+
+/// None if not a gateway or disconnected from the tracker.
+fn get_gateway_uptime_msec(ri: &RoutingInfo, now_msec: u64) -> Option<u64> {
+    if ri.node_type() != NodeType::Gateway {
+        return None;
+    }
+    let gs = ri.gateway_state.as_ref()?;
+    (gs.last_disconnect_time < gs.last_connect_time)
+        .then(|| now_msec.saturating_sub(gs.last_connect_time))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -188,6 +398,13 @@ mod tests {
             clients: parking_lot::Mutex::new(vec![cd0, cd1]),
             routing_info_updated: AtomicI64::new(0),
             routing_info: RwLock::new(Arc::new(UrlData::create_default_routing_info())),
+            spatial_index: Default::default(),
+            relay_index: Default::default(),
+            membership: Default::default(),
+            gateway_checked: AtomicI64::new(0),
+            gateway_stats: Default::default(),
+            partition_checked: AtomicI64::new(0),
+            world_health: Default::default(),
         });
 
         ud.update_routing_info_if_needed();
@@ -210,6 +427,13 @@ mod tests {
             clients: parking_lot::Mutex::new(Vec::new()),
             routing_info_updated: AtomicI64::new(0),
             routing_info: RwLock::new(Arc::new(UrlData::create_default_routing_info())),
+            spatial_index: Default::default(),
+            relay_index: Default::default(),
+            membership: Default::default(),
+            gateway_checked: AtomicI64::new(0),
+            gateway_stats: Default::default(),
+            partition_checked: AtomicI64::new(0),
+            world_health: Default::default(),
         });
 
         let cd0 = ClientData::new([0; 32].into(), pc.clone(), "".to_string());
@@ -237,6 +461,13 @@ mod tests {
             clients: parking_lot::Mutex::new(Vec::new()),
             routing_info_updated: AtomicI64::new(0),
             routing_info: RwLock::new(Arc::new(UrlData::create_default_routing_info())),
+            spatial_index: Default::default(),
+            relay_index: Default::default(),
+            membership: Default::default(),
+            gateway_checked: AtomicI64::new(0),
+            gateway_stats: Default::default(),
+            partition_checked: AtomicI64::new(0),
+            world_health: Default::default(),
         });
 
         let cd0 = ClientData::new([0; 32].into(), pc.clone(), "".to_string());
@@ -278,4 +509,129 @@ mod tests {
         assert_eq!(ud.get_routing_info().node_type(), NodeType::Tracker);
         assert_eq!(ud.get_routing_info().get_relations().unwrap().len(), 0,);
     }
+    #[tokio::test]
+    async fn test_find_relay() {
+        let config = RTCConfiguration::default();
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(api.new_peer_connection(config).await.unwrap());
+        let ud = UrlData::new_empty();
+        let ri = |n: u8, relations: Vec<RoutingInfo>| {
+            let mut ri = RoutingInfo {
+                node_type: NodeType::Normal.into(),
+                session_id: Some([n; 32].to_vec()),
+                ..Default::default()
+            };
+            ri.set_relations(relations);
+            ri
+        };
+
+        // 0 -> 2 -> 3 -> 9
+        let cd0 = ClientData::new([0; 32].into(), pc.clone(), "".to_string());
+        cd0.set_routing_info(ri(0, vec![ri(2, vec![ri(3, vec![ri(9, vec![])])])]));
+        // 1 -> 3 -> 9
+        let cd1 = ClientData::new([1; 32].into(), pc, "".to_string());
+        cd1.set_routing_info(ri(1, vec![ri(3, vec![ri(9, vec![])])]));
+        ud.add_connection(cd0, None);
+        ud.add_connection(cd1, None);
+
+        let to = [9; 32].into();
+        let (relay, depth) = ud.find_relay(&to, &[5; 32].into(), 10).unwrap();
+        assert_eq!(relay, [1; 32].into());
+        assert_eq!(depth, 2);
+
+        let (relay, depth) = ud.find_relay(&to, &[1; 32].into(), 10).unwrap();
+        assert_eq!(relay, [0; 32].into());
+        assert_eq!(depth, 3);
+
+        assert!(ud.find_relay(&to, &[5; 32].into(), 1).is_none());
+        assert!(ud
+            .find_relay(&[8; 32].into(), &[5; 32].into(), 10)
+            .is_none());
+        // a client is not a relay to itself
+        assert!(ud
+            .find_relay(&[1; 32].into(), &[5; 32].into(), 10)
+            .is_none());
+
+        ud.remove_connection(&[1; 32].into());
+        let (relay, _) = ud.find_relay(&to, &[5; 32].into(), 10).unwrap();
+        assert_eq!(relay, [0; 32].into());
+    }
+    #[tokio::test]
+    async fn test_spatial_index() {
+        let config = RTCConfiguration::default();
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(api.new_peer_connection(config).await.unwrap());
+        let ri = |x: f64| RoutingInfo {
+            position: Some(verse_proto::primitive::Position3D { x, y: 0.0, z: 0.0 }),
+            ..Default::default()
+        };
+
+        // reported before joining
+        let cd0 = ClientData::new([0; 32].into(), pc.clone(), "".to_string());
+        cd0.set_routing_info(ri(1.0));
+        let ud = UrlData::new(cd0.clone());
+        let cd1 = ClientData::new([1; 32].into(), pc, "".to_string());
+        ud.add_connection(cd1.clone(), None);
+        cd1.set_routing_info(ri(2.0));
+        let index = ud.get_spatial_index();
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.get_position(&cd1.session_id), Some([2.0, 0.0, 0.0]));
+
+        ud.remove_connection(&cd1.session_id);
+        cd1.set_routing_info(ri(3.0));
+        assert_eq!(index.len(), 1);
+        assert!(index.get_position(&cd1.session_id).is_none());
+    }
+    #[tokio::test]
+    async fn test_gateway_stats() {
+        let config = RTCConfiguration::default();
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(api.new_peer_connection(config).await.unwrap());
+        let ud = UrlData::new_empty();
+        let ri =
+            |node_type: NodeType, gateway_state: Option<(u64, u64)>, relation_count| RoutingInfo {
+                node_type: node_type.into(),
+                gateway_state: gateway_state.map(|v| GatewayState {
+                    last_connect_time: v.0,
+                    last_disconnect_time: v.1,
+                }),
+                relation: Some(routing_info::Relation::Count(relation_count)),
+                ..Default::default()
+            };
+        let ris = [
+            ri(NodeType::Gateway, Some((1000, 0)), 0),
+            ri(NodeType::Gateway, Some((9500, 0)), 0),
+            // disconnected
+            ri(NodeType::Gateway, Some((1000, 2000)), 0),
+            ri(NodeType::Normal, None, 1),
+            ri(NodeType::Normal, None, 5),
+            ri(NodeType::Normal, None, 3),
+        ];
+        for (n, ri) in ris.into_iter().enumerate() {
+            let cd = ClientData::new([n as u8; 32].into(), pc.clone(), "".to_string());
+            cd.set_routing_info(ri);
+            ud.add_connection(cd, None);
+        }
+
+        assert_eq!(ud.get_gateway_stats(), GatewayStats::default());
+        let stats = ud.update_gateway_stats(10000, 1000);
+        assert_eq!(
+            stats,
+            GatewayStats {
+                gateway_count: 3,
+                healthy_gateway_count: 1,
+                normal_count: 3,
+                max_uptime_msec: 9000,
+            }
+        );
+        assert_eq!(ud.get_gateway_stats(), stats);
+
+        let candidates = ud.get_gateway_candidates(2);
+        assert_eq!(candidates.len(), 2);
+        assert_eq!(candidates[0].session_id, [4; 32].into());
+        assert_eq!(candidates[1].session_id, [5; 32].into());
+
+        assert!(ud.try_start_gateway_check(10));
+        assert!(!ud.try_start_gateway_check(10));
+    }
 }
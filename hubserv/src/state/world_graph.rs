@@ -0,0 +1,209 @@
+use super::UrlData;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use verse_proto::swarm::*;
+
+pub struct WorldNode {
+    pub session_id: Vec<u8>,
+    /// Without relations.
+    pub routing_info: RoutingInfo,
+    /// Connected to this hub.
+    pub connected: bool,
+}
+
+/// Peers of a world and their connections, from the RoutingInfo reported by the members.
+pub struct WorldGraph {
+    pub nodes: Vec<WorldNode>,
+    /// Pairs of indexes of `nodes`, the smaller first.
+    pub edges: BTreeSet<(usize, usize)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WorldHealth {
+    pub node_count: usize,
+    pub component_count: usize,
+    /// Nodes without edges.
+    pub isolated_count: usize,
+    pub average_degree: f64,
+    /// Ratio of the nodes in a component with a gateway.
+    pub gateway_coverage: f64,
+    /// Maximum hops from the nearest gateway, of the nodes reachable from one.
+    pub max_gateway_depth: usize,
+}
+
+impl WorldGraph {
+    pub fn build(ud: &UrlData) -> Self {
+        let mut graph = WorldGraph {
+            nodes: Vec::new(),
+            edges: BTreeSet::new(),
+        };
+        let mut indexes = HashMap::new();
+        let mut trees: Vec<Arc<RoutingInfo>> = Vec::new();
+        for cd in ud.get_clients() {
+            if let Some(ri) = cd.get_routing_info() {
+                graph.add_node(&mut indexes, &ri, true);
+            }
+            if let Some(ri) = cd.get_neighbors() {
+                trees.push(ri);
+            }
+        }
+        for ri in trees.recursive_iter() {
+            let Some(from) = graph.add_node(&mut indexes, ri, false) else {
+                continue;
+            };
+            for child in ri.get_relations().unwrap_or_default() {
+                let Some(to) = graph.add_node(&mut indexes, child, false) else {
+                    continue;
+                };
+                if from != to {
+                    graph.edges.insert((from.min(to), from.max(to)));
+                }
+            }
+        }
+        graph
+    }
+
+    fn add_node(
+        &mut self,
+        indexes: &mut HashMap<Vec<u8>, usize>,
+        ri: &RoutingInfo,
+        connected: bool,
+    ) -> Option<usize> {
+        let id = ri.session_id.as_ref()?;
+        if let Some(i) = indexes.get(id) {
+            return Some(*i);
+        }
+        let mut ri = ri.clone();
+        ri.relation = None;
+        let i = self.nodes.len();
+        self.nodes.push(WorldNode {
+            session_id: id.clone(),
+            routing_info: ri,
+            connected,
+        });
+        indexes.insert(id.clone(), i);
+        Some(i)
+    }
+
+    fn get_adjacency(&self) -> Vec<Vec<usize>> {
+        let mut res = vec![Vec::new(); self.nodes.len()];
+        for (a, b) in self.edges.iter() {
+            res[*a].push(*b);
+            res[*b].push(*a);
+        }
+        res
+    }
+
+    /// Indexes of the nodes of each connected component, the largest first.
+    pub fn get_components(&self) -> Vec<Vec<usize>> {
+        let adjacency = self.get_adjacency();
+        let mut visited = vec![false; self.nodes.len()];
+        let mut res = Vec::new();
+        for start in 0..self.nodes.len() {
+            if visited[start] {
+                continue;
+            }
+            visited[start] = true;
+            let mut component = vec![start];
+            let mut i = 0;
+            while i < component.len() {
+                for next in adjacency[component[i]].iter() {
+                    if !visited[*next] {
+                        visited[*next] = true;
+                        component.push(*next);
+                    }
+                }
+                i += 1;
+            }
+            res.push(component);
+        }
+        res.sort_by_key(|v| std::cmp::Reverse(v.len()));
+        res
+    }
+
+    pub fn get_health(&self) -> WorldHealth {
+        let node_count = self.nodes.len();
+        if node_count == 0 {
+            return WorldHealth::default();
+        }
+        let adjacency = self.get_adjacency();
+        let is_gateway = |i: usize| self.nodes[i].routing_info.node_type() == NodeType::Gateway;
+
+        // BFS from all the gateways
+        let mut depths: Vec<Option<usize>> = vec![None; node_count];
+        let mut queue = VecDeque::new();
+        for i in (0..node_count).filter(|i| is_gateway(*i)) {
+            depths[i] = Some(0);
+            queue.push_back(i);
+        }
+        while let Some(cur) = queue.pop_front() {
+            let depth = depths[cur].unwrap_or_default() + 1;
+            for next in adjacency[cur].iter() {
+                if depths[*next].is_none() {
+                    depths[*next] = Some(depth);
+                    queue.push_back(*next);
+                }
+            }
+        }
+        let covered = depths.iter().filter(|v| v.is_some()).count();
+
+        WorldHealth {
+            node_count,
+            component_count: self.get_components().len(),
+            isolated_count: adjacency.iter().filter(|v| v.is_empty()).count(),
+            average_degree: (self.edges.len() * 2) as f64 / node_count as f64,
+            gateway_coverage: covered as f64 / node_count as f64,
+            max_gateway_depth: depths.iter().flatten().copied().max().unwrap_or(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn graph(node_types: &[NodeType], edges: &[(usize, usize)]) -> WorldGraph {
+        WorldGraph {
+            nodes: node_types
+                .iter()
+                .enumerate()
+                .map(|(i, v)| WorldNode {
+                    session_id: vec![i as u8; 32],
+                    routing_info: RoutingInfo {
+                        node_type: (*v).into(),
+                        ..Default::default()
+                    },
+                    connected: true,
+                })
+                .collect(),
+            edges: edges.iter().cloned().collect(),
+        }
+    }
+
+    #[test]
+    fn test_world_health() {
+        use NodeType::*;
+        assert_eq!(graph(&[], &[]).get_health(), WorldHealth::default());
+
+        // 0(gw) - 1 - 2 - 3, 4 - 5, 6
+        let g = graph(
+            &[Gateway, Normal, Normal, Normal, Normal, Normal, Normal],
+            &[(0, 1), (1, 2), (2, 3), (4, 5)],
+        );
+        assert_eq!(
+            g.get_components(),
+            vec![vec![0, 1, 2, 3], vec![4, 5], vec![6]]
+        );
+        assert_eq!(
+            g.get_health(),
+            WorldHealth {
+                node_count: 7,
+                component_count: 3,
+                isolated_count: 1,
+                average_degree: 8.0 / 7.0,
+                gateway_coverage: 4.0 / 7.0,
+                max_gateway_depth: 3,
+            }
+        );
+    }
+}
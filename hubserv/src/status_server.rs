@@ -2,19 +2,28 @@ use crate::args::Args;
 use crate::state::SharedState;
 use crate::version;
 use axum::{
-    extract::{FromRef, State},
+    extract::{FromRef, Query, State},
+    http::{header, StatusCode},
+    response::{IntoResponse, Response},
     routing::get,
     Json, Router,
 };
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::net::{Ipv6Addr, SocketAddr};
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
+use verse_common::compress::{get_codec_stats, Codec};
+
+mod topology;
+use topology::Topology;
 
 pub struct ServerContext {
     pub prometheus_prefix: Option<String>,
+    /// Session ids are shown in /topology.
+    pub topology_raw: bool,
 }
 
 #[derive(Clone, FromRef)]
@@ -27,10 +36,12 @@ pub async fn start_server(args: &Args, app_state: SharedState) {
     let app = Router::new()
         .route("/", get(index))
         .route("/metrics", get(prometheus))
+        .route("/topology", get(get_topology))
         .with_state(States {
             app_state,
             server_context: Arc::new(ServerContext {
                 prometheus_prefix: args.prometheus_prefix.clone(),
+                topology_raw: args.status_topology_raw,
             }),
         });
     let addr = SocketAddr::from((Ipv6Addr::UNSPECIFIED, args.status_port));
@@ -51,6 +62,31 @@ async fn index(
     }
     Ok(Json(res))
 }
+#[derive(Deserialize)]
+struct TopologyQuery {
+    url: String,
+    /// json or dot
+    format: Option<String>,
+}
+async fn get_topology(
+    State(context): State<Arc<ServerContext>>,
+    State(state): State<SharedState>,
+    Query(query): Query<TopologyQuery>,
+) -> Result<Response, StatusCode> {
+    let ud = state
+        .get_url_data(&query.url)
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let topology = Topology::build(&ud, !context.topology_raw);
+    match query.format.as_deref().unwrap_or("json") {
+        "json" => Ok(Json(topology).into_response()),
+        "dot" => Ok((
+            [(header::CONTENT_TYPE, "text/vnd.graphviz")],
+            topology.to_dot(),
+        )
+            .into_response()),
+        _ => Err(StatusCode::BAD_REQUEST),
+    }
+}
 async fn prometheus(
     State(context): State<Arc<ServerContext>>,
     State(state): State<SharedState>,
@@ -73,7 +109,7 @@ async fn prometheus(
         .join("\n"))
 }
 
-async fn get_metrics(state: SharedState) -> Vec<(String, i64)> {
+pub async fn get_metrics(state: SharedState) -> Vec<(String, i64)> {
     /* let mut res = Vec::<(String, i64)>::new();
     res.push((
         "client_count".to_string(),
@@ -82,8 +118,56 @@ async fn get_metrics(state: SharedState) -> Vec<(String, i64)> {
 
     res */
 
-    vec![(
+    let mut res = vec![(
         "client_count".to_string(),
         state.client_count.load(Ordering::Relaxed) as i64,
-    )]
+    )];
+    res.append(&mut state.rpc_registry.get_metrics());
+    res.append(&mut state.get_send_queue_metrics());
+    res.append(&mut state.get_membership_metrics());
+    res.append(&mut state.get_gateway_metrics());
+    res.append(&mut state.get_health_metrics());
+    res.append(&mut get_codec_metrics());
+    res
+}
+
+fn get_codec_metrics() -> Vec<(String, i64)> {
+    let mut res = Vec::new();
+    for codec in Codec::ALL {
+        let s = get_codec_stats(codec);
+        let labels = format!("{{codec=\"{}\"}}", codec.name());
+        res.push((
+            format!("rpc_codec_compress_count{}", labels),
+            s.compress_count as i64,
+        ));
+        res.push((
+            format!("rpc_codec_uncompressed_bytes{}", labels),
+            s.uncompressed_bytes as i64,
+        ));
+        res.push((
+            format!("rpc_codec_compressed_bytes{}", labels),
+            s.compressed_bytes as i64,
+        ));
+        res.push((
+            format!("rpc_codec_ratio_permille{}", labels),
+            (s.ratio() * 1000.0) as i64,
+        ));
+        res.push((
+            format!("rpc_codec_compress_usec{}", labels),
+            s.compress_usec as i64,
+        ));
+        res.push((
+            format!("rpc_codec_decompress_count{}", labels),
+            s.decompress_count as i64,
+        ));
+        res.push((
+            format!("rpc_codec_decompress_usec{}", labels),
+            s.decompress_usec as i64,
+        ));
+        res.push((
+            format!("rpc_codec_limit_exceeded_count{}", labels),
+            s.limit_exceeded_count as i64,
+        ));
+    }
+    res
 }
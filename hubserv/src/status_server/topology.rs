@@ -0,0 +1,189 @@
+use crate::state::{position_to_point, UrlData, WorldGraph};
+use serde::Serialize;
+use std::fmt::Write;
+use verse_proto::swarm::*;
+
+#[derive(Serialize)]
+pub struct Node {
+    pub id: String,
+    pub node_type: &'static str,
+    pub position: Option<[f64; 3]>,
+    /// Connected to this hub.
+    pub connected: bool,
+}
+
+#[derive(Serialize)]
+pub struct Edge {
+    pub from: String,
+    pub to: String,
+}
+
+/// WorldGraph for export.
+#[derive(Serialize)]
+pub struct Topology {
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+impl Topology {
+    /// `anonymize`: session ids are replaced with serial numbers.
+    pub fn build(ud: &UrlData, anonymize: bool) -> Self {
+        let graph = WorldGraph::build(ud);
+        let names: Vec<String> = graph
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(i, v)| {
+                if anonymize {
+                    format!("n{}", i)
+                } else {
+                    base64::encode_config(&v.session_id, base64::URL_SAFE_NO_PAD)
+                }
+            })
+            .collect();
+        Topology {
+            nodes: graph
+                .nodes
+                .iter()
+                .zip(names.iter())
+                .map(|(v, id)| Node {
+                    id: id.clone(),
+                    node_type: node_type_name(v.routing_info.node_type()),
+                    position: v.routing_info.position.as_ref().and_then(position_to_point),
+                    connected: v.connected,
+                })
+                .collect(),
+            edges: graph
+                .edges
+                .iter()
+                .map(|(from, to)| Edge {
+                    from: names[*from].clone(),
+                    to: names[*to].clone(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut res = String::from("graph world {\n");
+        for v in self.nodes.iter() {
+            let _ = write!(res, "  \"{}\" [type={}", v.id, v.node_type);
+            if v.node_type == "gateway" {
+                res.push_str(" shape=box");
+            }
+            if v.connected {
+                res.push_str(" style=bold");
+            }
+            if let Some(p) = v.position {
+                let _ = write!(res, " pos=\"{},{}\"", p[0], p[2]);
+            }
+            res.push_str("];\n");
+        }
+        for v in self.edges.iter() {
+            let _ = writeln!(res, "  \"{}\" -- \"{}\";", v.from, v.to);
+        }
+        res.push_str("}\n");
+        res
+    }
+}
+
+fn node_type_name(node_type: NodeType) -> &'static str {
+    match node_type {
+        NodeType::Unspecified => "unspecified",
+        NodeType::Normal => "normal",
+        NodeType::Gateway => "gateway",
+        NodeType::Tracker => "tracker",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ClientData;
+    use std::sync::Arc;
+    use verse_proto::primitive::Position3D;
+    use webrtc::api::APIBuilder;
+    use webrtc::peer_connection::configuration::RTCConfiguration;
+
+    fn ri(n: u8, node_type: NodeType, relations: Vec<RoutingInfo>) -> RoutingInfo {
+        let mut ri = RoutingInfo {
+            node_type: node_type.into(),
+            session_id: Some([n; 32].to_vec()),
+            position: Some(Position3D {
+                x: n as f64,
+                y: 0.0,
+                z: 1.0,
+            }),
+            ..Default::default()
+        };
+        ri.set_relations(relations);
+        ri
+    }
+
+    #[tokio::test]
+    async fn test_topology() {
+        let config = RTCConfiguration::default();
+        let api = APIBuilder::new().build();
+        let pc = Arc::new(api.new_peer_connection(config).await.unwrap());
+        let ud = UrlData::new_empty();
+
+        // 0 - 1 - 2, 0 - 2, 1 - 3
+        let cd0 = ClientData::new([0; 32].into(), pc.clone(), "".to_string());
+        cd0.set_routing_info(ri(
+            0,
+            NodeType::Gateway,
+            vec![
+                ri(1, NodeType::Normal, vec![ri(2, NodeType::Normal, vec![])]),
+                ri(2, NodeType::Normal, vec![]),
+            ],
+        ));
+        let cd1 = ClientData::new([1; 32].into(), pc, "".to_string());
+        cd1.set_routing_info(ri(
+            1,
+            NodeType::Normal,
+            vec![
+                ri(3, NodeType::Normal, vec![]),
+                ri(0, NodeType::Gateway, vec![]),
+            ],
+        ));
+        ud.add_connection(cd0, None);
+        ud.add_connection(cd1, None);
+
+        let t = Topology::build(&ud, true);
+        let nodes: Vec<(&str, &str, bool)> = t
+            .nodes
+            .iter()
+            .map(|v| (v.id.as_str(), v.node_type, v.connected))
+            .collect();
+        assert_eq!(
+            nodes,
+            vec![
+                ("n0", "gateway", true),
+                ("n1", "normal", true),
+                ("n2", "normal", false),
+                ("n3", "normal", false),
+            ]
+        );
+        assert_eq!(t.nodes[3].position, Some([3.0, 0.0, 1.0]));
+        let edges: Vec<(&str, &str)> = t
+            .edges
+            .iter()
+            .map(|v| (v.from.as_str(), v.to.as_str()))
+            .collect();
+        assert_eq!(
+            edges,
+            vec![("n0", "n1"), ("n0", "n2"), ("n1", "n2"), ("n1", "n3")]
+        );
+
+        let dot = t.to_dot();
+        assert!(dot.starts_with("graph world {\n"));
+        assert!(dot.contains("  \"n0\" [type=gateway shape=box style=bold pos=\"0,1\"];\n"));
+        assert!(dot.contains("  \"n1\" -- \"n3\";\n"));
+
+        let t = Topology::build(&ud, false);
+        assert_eq!(
+            t.nodes[0].id,
+            base64::encode_config([0; 32], base64::URL_SAFE_NO_PAD)
+        );
+        assert!(serde_json::to_string(&t).is_ok());
+    }
+}
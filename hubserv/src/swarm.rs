@@ -1,85 +1,450 @@
 use crate::ids::*;
-use crate::state::{ClientData, State};
+use crate::rpc_handler::{
+    MethodHandler, RpcError, RpcHandler, RpcKey, RpcRegistry, ServiceContext,
+};
+use crate::state::{position_to_point, ClientData, State};
 use anyhow::Result;
+use async_trait::async_trait;
 #[allow(unused_imports)]
 use log::{debug, error, info, warn};
 use prost::Message;
+use std::collections::HashSet;
 use std::io::Cursor;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::{Duration, Instant};
+use url::Url;
 use verse_proto::swarm::*;
 use verse_session_id::*;
 
-pub async fn on_swarm_message(
-    state: Arc<State>,
-    cd: Arc<ClientData>,
-    packet: SwarmPacket,
-) -> Result<Option<SwarmPacket>> {
-    let Some(swarm_packet::Data::Request(req)) = packet.data else {
-        return Ok(None);
-    };
+mod area_of_interest;
+pub use area_of_interest::{AreaOfInterest, AreaOfInterestConfig, WorldAreaOfInterest};
+mod gateway;
+pub use gateway::GatewayConfig;
+use gateway::GatewayManager;
+mod multicast;
+pub use multicast::MulticastConfig;
+use multicast::MulticastHandler;
+mod partition;
+pub use partition::PartitionConfig;
+use partition::PartitionManager;
+mod replay_cache;
+pub use replay_cache::{ReplayCache, ReplayCheck};
+mod routing_info_validator;
+pub use routing_info_validator::RoutingInfoLimits;
+use routing_info_validator::RoutingInfoValidator;
+mod routing_selector;
+pub use routing_selector::{
+    RoutingSelector, RoutingSelectors, RoutingStrategy, WorldRoutingStrategy,
+};
 
-    let res = match req.rpc_id {
-        RPC_ID_TRANSFER => Some(
-            transfer(
-                state.clone(),
-                cd.clone(),
-                TransferRequest::decode(Cursor::new(&req.param))?,
-            )
-            .await?
-            .encode_to_vec(),
-        ),
-        RPC_ID_EXCHANGE_ROUTING_INFO => Some(
-            exchange_routeing_info(
+/// Whether Transfer may cross worlds (ClientData.url).
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum CrossWorldPolicy {
+    Deny,
+    /// Allow between worlds on the same host.
+    SameHost,
+    Allow,
+}
+impl CrossWorldPolicy {
+    pub fn allows(&self, from_url: &str, to_url: &str) -> bool {
+        if from_url == to_url {
+            return true;
+        }
+        match self {
+            CrossWorldPolicy::Deny => false,
+            CrossWorldPolicy::SameHost => {
+                let host = |u: &str| {
+                    Url::parse(u)
+                        .ok()
+                        .and_then(|u| u.host_str().map(str::to_string))
+                };
+                matches!((host(from_url), host(to_url)), (Some(a), Some(b)) if a == b)
+            }
+            CrossWorldPolicy::Allow => true,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TransferConfig {
+    pub cross_world_policy: CrossWorldPolicy,
+    /// Upper limit of TransferRequest.ttl.
+    pub max_hops: u32,
+    pub replay_window: Duration,
+    pub replay_cache_size: usize,
+    /// Entries of the replay cache per sender.
+    pub replay_sender_quota: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct RoutingConfig {
+    /// How ExchangeRoutingInfo chooses the members of a world.
+    pub selectors: RoutingSelectors,
+    pub area_of_interest: AreaOfInterestConfig,
+    /// Limits of RoutingInfo reported by a client.
+    pub limits: RoutingInfoLimits,
+    pub gateway: GatewayConfig,
+    pub partition: PartitionConfig,
+}
+
+pub fn register_handlers(
+    registry: &mut RpcRegistry,
+    config: TransferConfig,
+    multicast_config: MulticastConfig,
+    routing_config: RoutingConfig,
+) {
+    registry.register(RpcKey::top(RPC_ID_SWARM), Arc::new(SwarmRouter));
+    let server = Arc::new(SwarmNodeServer {
+        multicast: MulticastHandler::new(multicast_config, &config),
+        transfer: TransferHandler::new(config),
+        exchange_routing_info: ExchangeRoutingInfoHandler {
+            validator: RoutingInfoValidator::new(routing_config.limits.clone()),
+            gateways: Arc::new(GatewayManager::new(routing_config.gateway.clone())),
+            partitions: Arc::new(PartitionManager::new(routing_config.partition.clone())),
+            config: routing_config,
+        },
+    });
+    registry.register_service(
+        Some(RPC_ID_SWARM),
+        swarm_node_service::Service(server.clone()),
+    );
+    registry.add_metrics(move || server.get_metrics());
+}
+
+/// Serves SwarmNodeService nested in RPC_ID_SWARM.
+struct SwarmNodeServer {
+    transfer: TransferHandler,
+    exchange_routing_info: ExchangeRoutingInfoHandler,
+    multicast: MulticastHandler,
+}
+impl SwarmNodeServer {
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        let mut res = MethodHandler::<swarm_node_service::Transfer>::get_metrics(&self.transfer);
+        res.append(
+            &mut MethodHandler::<swarm_node_service::ExchangeRoutingInfo>::get_metrics(
+                &self.exchange_routing_info,
+            ),
+        );
+        res.append(
+            &mut MethodHandler::<swarm_node_service::Multicast>::get_metrics(&self.multicast),
+        );
+        res
+    }
+}
+#[async_trait]
+impl swarm_node_service::Server for SwarmNodeServer {
+    type Context = ServiceContext;
+    type Error = RpcError;
+    async fn transfer(
+        &self,
+        (state, cd): ServiceContext,
+        req: TransferRequest,
+    ) -> Result<TransferResponse, RpcError> {
+        MethodHandler::<swarm_node_service::Transfer>::call(&self.transfer, state, cd, req).await
+    }
+    async fn exchange_routing_info(
+        &self,
+        (state, cd): ServiceContext,
+        req: RoutingInfo,
+    ) -> Result<RoutingInfo, RpcError> {
+        self.exchange_routing_info.call(state, cd, req).await
+    }
+    async fn multicast(
+        &self,
+        (state, cd): ServiceContext,
+        req: MulticastRequest,
+    ) -> Result<MulticastResponse, RpcError> {
+        self.multicast.call(state, cd, req).await
+    }
+    async fn subscribe(
+        &self,
+        (state, cd): ServiceContext,
+        req: SubscribeRequest,
+    ) -> Result<SubscribeResponse, RpcError> {
+        SubscribeHandler.call(state, cd, req).await
+    }
+}
+
+/// Decodes SwarmPacket and dispatches its SwarmRequest by the nested rpc id.
+struct SwarmRouter;
+#[async_trait]
+impl RpcHandler for SwarmRouter {
+    fn name(&self) -> &'static str {
+        "swarm"
+    }
+    fn is_router(&self) -> bool {
+        true
+    }
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        param: Vec<u8>,
+    ) -> Result<Vec<u8>, RpcError> {
+        let packet = SwarmPacket::decode(Cursor::new(&param))?;
+        let Some(swarm_packet::Data::Request(req)) = packet.data else {
+            return Err(RpcError::BadRequest("not a swarm request".into()));
+        };
+
+        let res = state
+            .rpc_registry
+            .dispatch(
+                RpcKey::nested(RPC_ID_SWARM, req.rpc_id),
                 state.clone(),
-                cd.clone(),
-                RoutingInfo::decode(Cursor::new(&req.param))?,
+                cd,
+                req.param,
             )
-            .await?
-            .encode_to_vec(),
-        ),
-        _ => None,
-    };
-    if let Some(res) = res {
-        Ok(Some(SwarmPacket {
+            .await
+            .map_err(|e| match e {
+                RpcError::UnknownRpcId(_) => e,
+                e => RpcError::Nested(req.rpc_id, Box::new(e)),
+            })?;
+        Ok(SwarmPacket {
             data: Some(swarm_packet::Data::Response(SwarmResponse {
                 rpc_id: req.rpc_id,
                 param: res,
             })),
-        }))
-    } else {
-        Ok(None)
+        }
+        .encode_to_vec())
     }
 }
-async fn transfer(
-    state: Arc<State>,
-    cd: Arc<ClientData>,
-    req: TransferRequest,
-) -> Result<TransferResponse> {
-    let to_session_id = req.to_session_id.clone();
-    let result = _transfer(state.clone(), cd.clone(), req).await?;
-    Ok(TransferResponse {
-        result,
-        dest_session_id: to_session_id,
-    })
-}
-async fn _transfer(state: Arc<State>, _cd: Arc<ClientData>, req: TransferRequest) -> Result<bool> {
-    let to_session_id = SessionId::try_from(&req.to_session_id)?;
-
-    let signature = req
-        .signature
-        .as_ref()
-        .ok_or_else(|| anyhow::anyhow!("bad signature"))?;
-    let ss = verse_session_id::SignatureSet::try_from(signature)?;
-    let from_session_id = SessionId::try_from(&signature.from_session_id)?;
-    from_session_id.verify(vec![&req.to_session_id, &req.payload], &ss)?;
-
-    if req.ttl < 1 {
-        return Ok(false);
-    }
-    let req = TransferRequest {
-        ttl: req.ttl - 1,
-        ..req
-    };
+
+struct TransferHandler {
+    config: TransferConfig,
+    replay_cache: ReplayCache,
+    rejected_count: AtomicU64,
+    replay_count: AtomicU64,
+    routed_count: AtomicU64,
+}
+#[async_trait]
+impl MethodHandler<swarm_node_service::Transfer> for TransferHandler {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: TransferRequest,
+    ) -> Result<TransferResponse, RpcError> {
+        Ok(self.transfer(state, cd, req).await?)
+    }
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        vec![
+            (
+                "transfer_rejected_count".to_string(),
+                self.rejected_count.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "transfer_replay_count".to_string(),
+                self.replay_count.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "transfer_routed_count".to_string(),
+                self.routed_count.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "transfer_replay_cache_size".to_string(),
+                self.replay_cache.len() as i64,
+            ),
+            (
+                "transfer_replay_cache_evicted_count".to_string(),
+                self.replay_cache.get_evicted_count() as i64,
+            ),
+        ]
+    }
+}
+
+struct SubscribeHandler;
+#[async_trait]
+impl MethodHandler<swarm_node_service::Subscribe> for SubscribeHandler {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: SubscribeRequest,
+    ) -> Result<SubscribeResponse, RpcError> {
+        let Some(ud) = state.get_url_data(&cd.url) else {
+            return Err(RpcError::Internal(anyhow::anyhow!("url data not found")));
+        };
+        let sequence = ud.set_subscribed(&cd, req.subscribe);
+        Ok(SubscribeResponse { sequence })
+    }
+}
+
+struct ExchangeRoutingInfoHandler {
+    config: RoutingConfig,
+    validator: RoutingInfoValidator,
+    gateways: Arc<GatewayManager>,
+    partitions: Arc<PartitionManager>,
+}
+#[async_trait]
+impl MethodHandler<swarm_node_service::ExchangeRoutingInfo> for ExchangeRoutingInfoHandler {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: RoutingInfo,
+    ) -> Result<RoutingInfo, RpcError> {
+        let req = self.validator.validate(req, &cd.session_id).map_err(|e| {
+            warn!(
+                "bad routing info from {}: {}",
+                cd.session_id.to_debug_string(),
+                e
+            );
+            RpcError::BadRequest(e.to_string())
+        })?;
+        let ud = state.get_url_data(&cd.url);
+        let res = exchange_routeing_info(state.clone(), cd, req, &self.config).await?;
+        if let Some(ud) = ud {
+            self.gateways.start_check(ud.clone());
+            self.partitions.start_check(ud);
+        }
+        Ok(res)
+    }
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        let mut res = self.validator.get_metrics();
+        res.push((
+            "gateway_requested_count".to_string(),
+            self.gateways.get_requested_count() as i64,
+        ));
+        res.push((
+            "gateway_request_failed_count".to_string(),
+            self.gateways.get_failed_count() as i64,
+        ));
+        res.push((
+            "merge_suggestion_count".to_string(),
+            self.partitions.get_suggested_count() as i64,
+        ));
+        res.push((
+            "merge_suggestion_failed_count".to_string(),
+            self.partitions.get_failed_count() as i64,
+        ));
+        res
+    }
+}
+
+impl TransferHandler {
+    fn new(config: TransferConfig) -> Self {
+        TransferHandler {
+            replay_cache: ReplayCache::new(
+                config.replay_window,
+                config.replay_cache_size,
+                config.replay_sender_quota,
+            ),
+            config,
+            rejected_count: AtomicU64::new(0),
+            replay_count: AtomicU64::new(0),
+            routed_count: AtomicU64::new(0),
+        }
+    }
+    async fn transfer(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: TransferRequest,
+    ) -> Result<TransferResponse> {
+        let to_session_id = req.to_session_id.clone();
+        let route = self._transfer(state.clone(), cd.clone(), req).await?;
+        Ok(TransferResponse {
+            result: route != TransferRoute::Unspecified,
+            dest_session_id: to_session_id,
+            route: route.into(),
+        })
+    }
+    async fn _transfer(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: TransferRequest,
+    ) -> Result<TransferRoute> {
+        let to_session_id = SessionId::try_from(&req.to_session_id)?;
+
+        let signature = req
+            .signature
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("bad signature"))?;
+        let ss = verse_session_id::SignatureSet::try_from(signature)?;
+        let from_session_id = SessionId::try_from(&signature.from_session_id)?;
+        from_session_id.verify(vec![&req.to_session_id, &req.payload], &ss)?;
+        let salt = signature.salt.clone();
+
+        let ttl = req.ttl.min(self.config.max_hops);
+        if ttl < 1 {
+            return Ok(TransferRoute::Unspecified);
+        }
+        // remembered only when forwarded, so the sender can retry an undelivered one
+        if self
+            .replay_cache
+            .check(from_session_id, &salt, Instant::now())
+            == ReplayCheck::Replayed
+        {
+            self.replay_count.fetch_add(1, Ordering::Relaxed);
+            debug!(
+                "transfer replayed: {} -> {}",
+                from_session_id.to_debug_string(),
+                to_session_id.to_debug_string()
+            );
+            return Ok(TransferRoute::Unspecified);
+        }
+        let req = TransferRequest {
+            ttl: ttl - 1,
+            ..req
+        };
+
+        if let Some(to_cd) = state.get_connection(&to_session_id) {
+            if !self.config.cross_world_policy.allows(&cd.url, &to_cd.url) {
+                // same result as an unknown destination, so other worlds can not be probed
+                self.rejected_count.fetch_add(1, Ordering::Relaxed);
+                debug!(
+                    "transfer rejected: {} -> {}",
+                    cd.session_id.to_debug_string(),
+                    to_session_id.to_debug_string()
+                );
+                return Ok(TransferRoute::Unspecified);
+            }
+            if !send_transfer(&to_cd, req).await? {
+                return Ok(TransferRoute::Unspecified);
+            }
+            self.remember(from_session_id, &salt);
+            return Ok(TransferRoute::Direct);
+        }
+
+        // not connected to this hub. forward to a client of the same world that can reach it.
+        let Some(ud) = state.get_url_data(&cd.url) else {
+            return Ok(TransferRoute::Unspecified);
+        };
+        let Some((relay_session_id, depth)) =
+            ud.find_relay(&to_session_id, &cd.session_id, req.ttl)
+        else {
+            return Ok(TransferRoute::Unspecified);
+        };
+        let Some(relay) = state.get_connection(&relay_session_id) else {
+            return Ok(TransferRoute::Unspecified);
+        };
+        debug!(
+            "transfer routed: {} -> {} -> {} ({})",
+            cd.session_id.to_debug_string(),
+            relay.session_id.to_debug_string(),
+            to_session_id.to_debug_string(),
+            depth
+        );
+        if !send_transfer(&relay, req).await? {
+            return Ok(TransferRoute::Unspecified);
+        }
+        self.remember(from_session_id, &salt);
+        self.routed_count.fetch_add(1, Ordering::Relaxed);
+        Ok(TransferRoute::Routed)
+    }
+    fn remember(&self, from_session_id: SessionId, salt: &[u8]) {
+        if !self
+            .replay_cache
+            .insert(from_session_id, salt, Instant::now())
+        {
+            // forwarded concurrently with the same one
+            self.replay_count.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+async fn send_transfer(to_cd: &ClientData, req: TransferRequest) -> Result<bool> {
     let req = SwarmRequest {
         rpc_id: RPC_ID_TRANSFER,
         param: req.encode_to_vec(),
@@ -87,34 +452,73 @@ async fn _transfer(state: Arc<State>, _cd: Arc<ClientData>, req: TransferRequest
     let packet = SwarmPacket {
         data: Some(swarm_packet::Data::Request(req)),
     };
-    state
-        .send_rpc_response(&to_session_id, RPC_ID_SWARM, packet.encode_to_vec())
-        .await
+    send_swarm_request(to_cd, packet.encode_to_vec()).await
+}
+/// Sends an encoded SwarmPacket of SwarmRequest to the client.
+async fn send_swarm_request(to_cd: &ClientData, packet: Vec<u8>) -> Result<bool> {
+    to_cd.send_rpc_response(RPC_ID_SWARM, packet).await
 }
 async fn exchange_routeing_info(
     state: Arc<State>,
     cd: Arc<ClientData>,
     req: RoutingInfo,
+    config: &RoutingConfig,
 ) -> Result<RoutingInfo> {
-    cd.set_routing_info(req);
+    let old_node_type = cd
+        .get_routing_info()
+        .map_or(NodeType::Normal, |v| v.node_type());
+    let node_type = req.node_type();
+    cd.set_routing_info(req.clone());
     let ud = if let Some(ud) = state.get_url_data(&cd.url) {
         ud
     } else {
         unreachable!("url data not found");
     };
+    if old_node_type != node_type {
+        ud.push_membership_event(
+            MembershipEventType::GatewayChange,
+            &cd.session_id,
+            node_type,
+        );
+    }
 
     ud.update_routing_info_if_needed();
 
     let ri = ud.get_routing_info();
 
     if let Some(relation) = ri.get_relations() {
-        if state.max_routing_results < relation.len() {
-            use rand::seq::SliceRandom;
-            let rng = &mut rand::thread_rng();
-            let relation: Vec<RoutingInfo> = relation
-                .choose_multiple(rng, state.max_routing_results)
-                .cloned()
+        let n = state.max_routing_results;
+        let selector = config.selectors.get(&cd.url);
+        let center = req.position.as_ref().and_then(position_to_point);
+        if let (Some(area), Some(center)) = (config.area_of_interest.get(&cd.url), center) {
+            let index = ud.get_spatial_index();
+            let mut ids = match area {
+                AreaOfInterest::Nearest => index.nearest(&center, n, &cd.session_id),
+                AreaOfInterest::Radius(r) => index.within(&center, r, &cd.session_id),
+            };
+            ids.truncate(n);
+            let mut nearby: Vec<RoutingInfo> = ids
+                .iter()
+                .filter_map(|id| state.get_connection(id)?.get_routing_info())
+                .map(|v| (*v).clone())
                 .collect();
+            // fills up with the others so that an isolated client still gets peers
+            if nearby.len() < n {
+                let ids: HashSet<Vec<u8>> = ids.iter().map(|v| v.to_vec()).collect();
+                let rest: Vec<RoutingInfo> = relation
+                    .iter()
+                    .filter(|v| !v.session_id.as_ref().is_some_and(|v| ids.contains(v)))
+                    .cloned()
+                    .collect();
+                nearby.extend(selector.select(&req, &rest, n - nearby.len()));
+            }
+
+            let mut ri1: RoutingInfo = (*ri).clone();
+            ri1.set_relations(nearby);
+            return Ok(ri1);
+        }
+        if n < relation.len() {
+            let relation = selector.select(&req, relation, n);
 
             let mut ri1: RoutingInfo = (*ri).clone();
             ri1.set_relations(relation);
@@ -124,3 +528,99 @@ async fn exchange_routeing_info(
 
     Ok((*ri).clone())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc_handler::RpcRegistry;
+    use tokio::sync::mpsc;
+    use webrtc::api::APIBuilder;
+
+    #[test]
+    fn test_cross_world_policy() {
+        let a = "https://example.com/a";
+        let b = "https://example.com/b";
+        let c = "https://example.net/a";
+        for p in [
+            CrossWorldPolicy::Deny,
+            CrossWorldPolicy::SameHost,
+            CrossWorldPolicy::Allow,
+        ] {
+            assert!(p.allows(a, a));
+        }
+        assert!(!CrossWorldPolicy::Deny.allows(a, b));
+        assert!(CrossWorldPolicy::SameHost.allows(a, b));
+        assert!(!CrossWorldPolicy::SameHost.allows(a, c));
+        assert!(!CrossWorldPolicy::SameHost.allows("", a));
+        assert!(CrossWorldPolicy::Allow.allows(a, c));
+    }
+
+    #[tokio::test]
+    async fn test_transfer_retry() {
+        let state = State::new(
+            APIBuilder::new().build(),
+            None,
+            None,
+            10,
+            vec![],
+            None,
+            None,
+            None,
+            RpcRegistry::new(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let handler = TransferHandler::new(TransferConfig {
+            cross_world_policy: CrossWorldPolicy::Deny,
+            max_hops: 8,
+            replay_window: Duration::from_secs(300),
+            replay_cache_size: 100,
+            replay_sender_quota: 10,
+        });
+        let url = "https://example.com/a".to_string();
+        let pair = new_session_id_pair().unwrap();
+        let (tx, _rx) = mpsc::channel(10);
+        let cd = ClientData::new_backend(pair.get_id().to_owned(), url.clone(), tx);
+        state.add_connection(cd.clone()).unwrap();
+
+        let to_session_id: SessionId = [9; 32].into();
+        let payload = b"payload".to_vec();
+        let ss = pair.sign(vec![&to_session_id.to_vec(), &payload]).unwrap();
+        let req = TransferRequest {
+            to_session_id: to_session_id.to_vec(),
+            signature: Some(verse_proto::swarm::SignatureSet {
+                from_session_id: pair.get_id().to_vec(),
+                signature: ss.signature.to_vec(),
+                salt: ss.salt.to_vec(),
+            }),
+            payload,
+            ttl: 1,
+        };
+        let route = |res: Result<TransferResponse>| res.unwrap().route();
+
+        // not connected yet, the retry is not taken as a replay
+        for _ in 0..2 {
+            let res = handler
+                .transfer(state.clone(), cd.clone(), req.clone())
+                .await;
+            assert_eq!(route(res), TransferRoute::Unspecified);
+        }
+        let (to_tx, mut to_rx) = mpsc::channel(10);
+        state
+            .add_connection(ClientData::new_backend(to_session_id, url, to_tx))
+            .unwrap();
+        let res = handler
+            .transfer(state.clone(), cd.clone(), req.clone())
+            .await;
+        assert_eq!(route(res), TransferRoute::Direct);
+        assert!(to_rx.try_recv().is_ok());
+        assert_eq!(handler.replay_count.load(Ordering::Relaxed), 0);
+
+        // delivered once
+        let res = handler.transfer(state.clone(), cd, req).await;
+        assert_eq!(route(res), TransferRoute::Unspecified);
+        assert!(to_rx.try_recv().is_err());
+        assert_eq!(handler.replay_count.load(Ordering::Relaxed), 1);
+    }
+}
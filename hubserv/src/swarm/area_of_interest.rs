@@ -0,0 +1,97 @@
+use anyhow::{anyhow, bail, Result};
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Which peers near the requester's position ExchangeRoutingInfo returns.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AreaOfInterest {
+    Nearest,
+    Radius(f64),
+}
+impl FromStr for AreaOfInterest {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        if s == "nearest" {
+            return Ok(AreaOfInterest::Nearest);
+        }
+        let radius: f64 = s.parse()?;
+        if !radius.is_finite() || radius <= 0.0 {
+            bail!("bad radius: {}", s);
+        }
+        Ok(AreaOfInterest::Radius(radius))
+    }
+}
+
+/// `<url>=<nearest|radius>`
+#[derive(Clone, Debug)]
+pub struct WorldAreaOfInterest {
+    pub url: String,
+    pub area: AreaOfInterest,
+}
+impl FromStr for WorldAreaOfInterest {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (url, area) = s
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("expected <url>=<nearest|radius>: {}", s))?;
+        Ok(WorldAreaOfInterest {
+            url: url.to_string(),
+            area: area.parse()?,
+        })
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct AreaOfInterestConfig {
+    default: Option<AreaOfInterest>,
+    by_url: HashMap<String, AreaOfInterest>,
+}
+impl AreaOfInterestConfig {
+    pub fn new(default: Option<AreaOfInterest>) -> Self {
+        AreaOfInterestConfig {
+            default,
+            by_url: HashMap::new(),
+        }
+    }
+    pub fn with_world(mut self, url: impl Into<String>, area: AreaOfInterest) -> Self {
+        self.by_url.insert(url.into(), area);
+        self
+    }
+    /// None: positions are not used in the world.
+    pub fn get(&self, url: &str) -> Option<AreaOfInterest> {
+        self.by_url.get(url).copied().or(self.default)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_area_of_interest_config() {
+        assert_eq!(
+            "nearest".parse::<AreaOfInterest>().unwrap(),
+            AreaOfInterest::Nearest
+        );
+        assert_eq!(
+            "12.5".parse::<AreaOfInterest>().unwrap(),
+            AreaOfInterest::Radius(12.5)
+        );
+        assert!("0".parse::<AreaOfInterest>().is_err());
+        assert!("inf".parse::<AreaOfInterest>().is_err());
+
+        let w: WorldAreaOfInterest = "https://example.com/a=100".parse().unwrap();
+        let config = AreaOfInterestConfig::default().with_world(w.url, w.area);
+        assert_eq!(
+            config.get("https://example.com/a"),
+            Some(AreaOfInterest::Radius(100.0))
+        );
+        assert_eq!(config.get("https://example.com/b"), None);
+
+        let config = AreaOfInterestConfig::new(Some(AreaOfInterest::Nearest));
+        assert_eq!(
+            config.get("https://example.com/b"),
+            Some(AreaOfInterest::Nearest)
+        );
+    }
+}
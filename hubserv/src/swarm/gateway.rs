@@ -0,0 +1,185 @@
+use super::send_swarm_request;
+use crate::ids::*;
+use crate::state::UrlData;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use parking_lot::Mutex;
+use prost::Message;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use verse_proto::swarm::*;
+use verse_session_id::SessionId;
+
+#[derive(Clone, Debug)]
+pub struct GatewayConfig {
+    /// Gateways each world should have. 0: the hub does not ask for gateways.
+    pub min_gateways: usize,
+    /// A gateway connected to the tracker for less than this is not counted.
+    pub min_uptime_msec: u64,
+    pub check_interval_sec: i64,
+}
+impl Default for GatewayConfig {
+    fn default() -> Self {
+        GatewayConfig {
+            min_gateways: 0,
+            min_uptime_msec: 60 * 1000,
+            check_interval_sec: 30,
+        }
+    }
+}
+
+/// Asks Normal nodes to become gateways when a world has too few.
+pub(super) struct GatewayManager {
+    config: GatewayConfig,
+    /// Sessions asked to become gateways, and when. They are not asked again, and
+    /// are counted as gateways, until they have had time to become healthy.
+    requested: Mutex<HashMap<SessionId, u64>>,
+    requested_count: AtomicU64,
+    failed_count: AtomicU64,
+}
+impl GatewayManager {
+    pub(super) fn new(config: GatewayConfig) -> Self {
+        GatewayManager {
+            config,
+            requested: Default::default(),
+            requested_count: AtomicU64::new(0),
+            failed_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks the world in another task, if not checked within the interval.
+    pub(super) fn start_check(self: &Arc<Self>, ud: Arc<UrlData>) {
+        if !ud.try_start_gateway_check(self.config.check_interval_sec) {
+            return;
+        }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            let now_msec = chrono::Local::now().timestamp_millis().max(0) as u64;
+            manager.check(&ud, now_msec).await;
+        });
+    }
+
+    async fn check(&self, ud: &UrlData, now_msec: u64) {
+        let stats = ud.update_gateway_stats(now_msec, self.config.min_uptime_msec);
+        let pending: HashSet<SessionId> = {
+            let expire_msec =
+                self.config.min_uptime_msec + self.config.check_interval_sec.max(0) as u64 * 1000;
+            let mut requested = self.requested.lock();
+            requested.retain(|_, v| now_msec < *v + expire_msec);
+            requested.keys().copied().collect()
+        };
+        let deficit = self
+            .config
+            .min_gateways
+            .saturating_sub(stats.healthy_gateway_count + pending.len());
+        if deficit == 0 {
+            return;
+        }
+
+        let packet = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest {
+                rpc_id: RPC_ID_BECOME_GATEWAY,
+                param: BecomeGatewayRequest {
+                    gateway_count: stats.healthy_gateway_count as u32,
+                    min_gateway_count: self.config.min_gateways as u32,
+                }
+                .encode_to_vec(),
+            })),
+        }
+        .encode_to_vec();
+        let candidates = ud
+            .get_gateway_candidates(deficit + pending.len())
+            .into_iter()
+            .filter(|v| !pending.contains(&v.session_id))
+            .take(deficit);
+        for cd in candidates {
+            debug!("request gateway: {}", cd.session_id.to_debug_string());
+            match send_swarm_request(&cd, packet.clone()).await {
+                Ok(true) => {
+                    self.requested.lock().insert(cd.session_id, now_msec);
+                    self.requested_count.fetch_add(1, Ordering::Relaxed);
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    self.failed_count.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "can't request gateway to {}: {:?}",
+                        cd.session_id.to_debug_string(),
+                        e
+                    );
+                }
+            }
+        }
+    }
+
+    pub(super) fn get_requested_count(&self) -> u64 {
+        self.requested_count.load(Ordering::Relaxed)
+    }
+    pub(super) fn get_failed_count(&self) -> u64 {
+        self.failed_count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::ClientData;
+    use tokio::sync::mpsc;
+
+    #[tokio::test]
+    async fn test_gateway_manager() {
+        let manager = GatewayManager::new(GatewayConfig {
+            min_gateways: 4,
+            min_uptime_msec: 1000,
+            check_interval_sec: 0,
+        });
+        let ud = UrlData::new_empty();
+        let ri = |node_type: NodeType, last_connect_time, relation_count| RoutingInfo {
+            node_type: node_type.into(),
+            gateway_state: Some(GatewayState {
+                last_connect_time,
+                last_disconnect_time: 0,
+            }),
+            relation: Some(routing_info::Relation::Count(relation_count)),
+            ..Default::default()
+        };
+        let mut receivers = HashMap::new();
+        for (n, ri) in [
+            ri(NodeType::Gateway, 1000, 0),
+            // healthy at 10900
+            ri(NodeType::Gateway, 9900, 0),
+            ri(NodeType::Normal, 0, 5),
+            ri(NodeType::Normal, 0, 3),
+            ri(NodeType::Normal, 0, 1),
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let (tx, rx) = mpsc::channel(10);
+            let cd = ClientData::new_backend([n as u8; 32].into(), "".to_string(), tx);
+            cd.set_routing_info(ri);
+            ud.add_connection(cd, None);
+            receivers.insert(n, rx);
+        }
+        // can't be reached
+        receivers.remove(&2);
+        let mut received = |n: usize| receivers.get_mut(&n).unwrap().try_recv().is_ok();
+
+        // 3 healthy gateways short
+        manager.check(&ud, 10000).await;
+        assert_eq!(manager.get_requested_count(), 2);
+        assert!(received(3) && received(4));
+
+        // the pending ones are not asked again
+        manager.check(&ud, 10500).await;
+        assert_eq!(manager.get_requested_count(), 2);
+        assert!(!received(3) && !received(4));
+
+        // asked again after expired
+        manager.check(&ud, 20000).await;
+        assert_eq!(manager.get_requested_count(), 3);
+        assert!(received(3) && !received(4));
+        assert_eq!(manager.get_failed_count(), 0);
+    }
+}
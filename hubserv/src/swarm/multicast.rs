@@ -0,0 +1,296 @@
+use super::replay_cache::{ReplayCache, ReplayCheck};
+use super::{send_swarm_request, CrossWorldPolicy, TransferConfig};
+use crate::ids::*;
+use crate::rpc_handler::{MethodHandler, RateLimiter, RpcError};
+use crate::state::{ClientData, State};
+use anyhow::Result;
+use async_trait::async_trait;
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Instant;
+use verse_proto::swarm::*;
+use verse_session_id::*;
+
+#[derive(Clone, Debug)]
+pub struct MulticastConfig {
+    /// Multicasts per second per sender.
+    pub rate_per_sec: f64,
+    pub burst: f64,
+    pub max_payload_size: usize,
+    pub max_recipients: usize,
+}
+
+pub(super) struct MulticastHandler {
+    config: MulticastConfig,
+    cross_world_policy: CrossWorldPolicy,
+    limiter: RateLimiter,
+    replay_cache: ReplayCache,
+    delivered_count: AtomicU64,
+    failed_count: AtomicU64,
+    rate_limited_count: AtomicU64,
+}
+impl MulticastHandler {
+    pub(super) fn new(config: MulticastConfig, transfer_config: &TransferConfig) -> Self {
+        MulticastHandler {
+            limiter: RateLimiter::new(config.rate_per_sec, config.burst),
+            config,
+            cross_world_policy: transfer_config.cross_world_policy,
+            replay_cache: ReplayCache::new(
+                transfer_config.replay_window,
+                transfer_config.replay_cache_size,
+                transfer_config.replay_sender_quota,
+            ),
+            delivered_count: AtomicU64::new(0),
+            failed_count: AtomicU64::new(0),
+            rate_limited_count: AtomicU64::new(0),
+        }
+    }
+
+    fn get_recipients(
+        &self,
+        state: &State,
+        cd: &ClientData,
+        req: &MulticastRequest,
+    ) -> Result<Vec<Arc<ClientData>>, RpcError> {
+        if req.to_session_ids.is_empty() {
+            let Some(ud) = state.get_url_data(&cd.url) else {
+                return Ok(Vec::new());
+            };
+            let recipients: Vec<_> = ud
+                .get_clients()
+                .into_iter()
+                .filter(|v| !v.session_id.eq(&cd.session_id))
+                .collect();
+            if recipients.len() > self.config.max_recipients {
+                return Err(RpcError::BadRequest("too many recipients".into()));
+            }
+            return Ok(recipients);
+        }
+        Ok(req
+            .to_session_ids
+            .iter()
+            .filter_map(|v| SessionId::try_from(v).ok())
+            .filter(|v| !v.eq(&cd.session_id))
+            .filter_map(|v| state.get_connection(&v))
+            .filter(|v| self.cross_world_policy.allows(&cd.url, &v.url))
+            .collect())
+    }
+}
+#[async_trait]
+impl MethodHandler<swarm_node_service::Multicast> for MulticastHandler {
+    async fn call(
+        &self,
+        state: Arc<State>,
+        cd: Arc<ClientData>,
+        req: MulticastRequest,
+    ) -> Result<MulticastResponse, RpcError> {
+        if req.payload.len() > self.config.max_payload_size {
+            return Err(RpcError::BadRequest("payload too large".into()));
+        }
+        if req.to_session_ids.len() > self.config.max_recipients {
+            return Err(RpcError::BadRequest("too many recipients".into()));
+        }
+        if !self.limiter.check(cd.session_id) {
+            self.rate_limited_count.fetch_add(1, Ordering::Relaxed);
+            return Err(RpcError::RateLimited);
+        }
+
+        let (from_session_id, salt) =
+            verify(&req, &cd.session_id).map_err(|e| RpcError::BadRequest(e.to_string()))?;
+
+        match self
+            .replay_cache
+            .check(from_session_id, &salt, Instant::now())
+        {
+            ReplayCheck::New => {}
+            ReplayCheck::Replayed => {
+                debug!("multicast replayed: {}", from_session_id.to_debug_string());
+                return Ok(MulticastResponse { recipient_count: 0 });
+            }
+            ReplayCheck::OverQuota => {
+                self.rate_limited_count.fetch_add(1, Ordering::Relaxed);
+                return Err(RpcError::RateLimited);
+            }
+        }
+        let recipients = self.get_recipients(&state, &cd, &req)?;
+        // remembered after the request turned out to be valid
+        if !self
+            .replay_cache
+            .insert(from_session_id, &salt, Instant::now())
+        {
+            debug!("multicast replayed: {}", from_session_id.to_debug_string());
+            return Ok(MulticastResponse { recipient_count: 0 });
+        }
+
+        let packet = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest {
+                rpc_id: RPC_ID_MULTICAST,
+                param: req.encode_to_vec(),
+            })),
+        }
+        .encode_to_vec();
+        let mut recipient_count = 0;
+        for to_cd in recipients.iter() {
+            match send_swarm_request(to_cd, packet.clone()).await {
+                Ok(true) => recipient_count += 1,
+                Ok(false) => {}
+                Err(e) => {
+                    self.failed_count.fetch_add(1, Ordering::Relaxed);
+                    warn!(
+                        "multicast to {} failed: {:?}",
+                        to_cd.session_id.to_debug_string(),
+                        e
+                    );
+                }
+            }
+        }
+        self.delivered_count
+            .fetch_add(recipient_count as u64, Ordering::Relaxed);
+
+        Ok(MulticastResponse { recipient_count })
+    }
+    fn get_metrics(&self) -> Vec<(String, i64)> {
+        vec![
+            (
+                "multicast_delivered_count".to_string(),
+                self.delivered_count.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "multicast_failed_count".to_string(),
+                self.failed_count.load(Ordering::Relaxed) as i64,
+            ),
+            (
+                "multicast_rate_limited_count".to_string(),
+                self.rate_limited_count.load(Ordering::Relaxed) as i64,
+            ),
+        ]
+    }
+}
+
+/// Returns the sender and the salt of the signature.
+/// The world is not signed, so a world broadcast is accepted only from the signer itself.
+fn verify(req: &MulticastRequest, relayer: &SessionId) -> Result<(SessionId, Vec<u8>)> {
+    let signature = req
+        .signature
+        .as_ref()
+        .ok_or_else(|| anyhow::anyhow!("bad signature"))?;
+    let ss = verse_session_id::SignatureSet::try_from(signature)?;
+    let from_session_id = SessionId::try_from(&signature.from_session_id)?;
+    if req.to_session_ids.is_empty() && !from_session_id.eq(relayer) {
+        anyhow::bail!("world broadcast relayed by another session");
+    }
+    let to_session_ids = req.to_session_ids.concat();
+    from_session_id.verify(vec![&to_session_ids, &req.payload], &ss)?;
+    Ok((from_session_id, signature.salt.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc_handler::RpcRegistry;
+    use std::time::Duration;
+    use tokio::sync::mpsc;
+    use webrtc::api::APIBuilder;
+
+    fn new_request(pair: &SessionIdPair, to_session_ids: Vec<Vec<u8>>) -> MulticastRequest {
+        let payload = b"payload".to_vec();
+        let ss = pair.sign(vec![&to_session_ids.concat(), &payload]).unwrap();
+        MulticastRequest {
+            to_session_ids,
+            signature: Some(verse_proto::swarm::SignatureSet {
+                from_session_id: pair.get_id().to_vec(),
+                signature: ss.signature.to_vec(),
+                salt: ss.salt.to_vec(),
+            }),
+            payload,
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let pair = new_session_id_pair().unwrap();
+        let other = new_session_id_pair().unwrap();
+        let sender = pair.get_id().to_owned();
+        let relayer = other.get_id().to_owned();
+
+        let req = new_request(&pair, vec![relayer.to_vec()]);
+        assert_eq!(verify(&req, &sender).unwrap().0, sender);
+        assert_eq!(verify(&req, &relayer).unwrap().0, sender);
+
+        // world broadcast
+        let req = new_request(&pair, vec![]);
+        assert!(verify(&req, &sender).is_ok());
+        assert!(verify(&req, &relayer).is_err());
+
+        let mut req = new_request(&pair, vec![relayer.to_vec()]);
+        req.payload = b"modified".to_vec();
+        assert!(verify(&req, &sender).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_multicast_replay() {
+        let state = State::new(
+            APIBuilder::new().build(),
+            None,
+            None,
+            10,
+            vec![],
+            None,
+            None,
+            None,
+            RpcRegistry::new(),
+            Default::default(),
+            Default::default(),
+            Default::default(),
+        );
+        let handler = MulticastHandler::new(
+            MulticastConfig {
+                rate_per_sec: 100.0,
+                burst: 100.0,
+                max_payload_size: 100,
+                max_recipients: 1,
+            },
+            &TransferConfig {
+                cross_world_policy: CrossWorldPolicy::Deny,
+                max_hops: 8,
+                replay_window: Duration::from_secs(300),
+                replay_cache_size: 100,
+                replay_sender_quota: 2,
+            },
+        );
+        let url = "https://example.com/a".to_string();
+        let pair = new_session_id_pair().unwrap();
+        let mut receivers = Vec::new();
+        let mut connect = |session_id: SessionId| {
+            let (tx, rx) = mpsc::channel(10);
+            let cd = ClientData::new_backend(session_id, url.clone(), tx);
+            state.add_connection(cd.clone()).unwrap();
+            receivers.push(rx);
+            cd
+        };
+        let cd = connect(pair.get_id().to_owned());
+        connect([1; 32].into());
+        connect([2; 32].into());
+
+        // rejected without remembering the salt
+        let req = new_request(&pair, vec![]);
+        let res = handler.call(state.clone(), cd.clone(), req.clone()).await;
+        assert!(matches!(res, Err(RpcError::BadRequest(_))));
+        state.remove_connection(&[2; 32].into());
+        let res = handler.call(state.clone(), cd.clone(), req.clone()).await;
+        assert_eq!(res.unwrap().recipient_count, 1);
+        let res = handler.call(state.clone(), cd.clone(), req).await;
+        assert_eq!(res.unwrap().recipient_count, 0);
+
+        // over the quota of the sender
+        let req = new_request(&pair, vec![]);
+        let res = handler.call(state.clone(), cd.clone(), req).await;
+        assert_eq!(res.unwrap().recipient_count, 1);
+        let req = new_request(&pair, vec![]);
+        let res = handler.call(state.clone(), cd, req).await;
+        assert!(matches!(res, Err(RpcError::RateLimited)));
+    }
+}
@@ -0,0 +1,214 @@
+use super::send_swarm_request;
+use crate::ids::*;
+use crate::state::{ClientData, UrlData, WorldGraph};
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use verse_proto::swarm::*;
+use verse_session_id::SessionId;
+
+#[derive(Clone, Debug)]
+pub struct PartitionConfig {
+    /// Send MergeSuggestion to the members of detached components.
+    pub merge: bool,
+    pub check_interval_sec: i64,
+    /// Peers suggested, and members notified in each detached component.
+    pub max_peers: usize,
+}
+impl Default for PartitionConfig {
+    fn default() -> Self {
+        PartitionConfig {
+            merge: false,
+            check_interval_sec: 30,
+            max_peers: 3,
+        }
+    }
+}
+
+/// Keeps the health of each world, and tells the members of the components
+/// other than the largest which peers to connect to.
+pub(super) struct PartitionManager {
+    config: PartitionConfig,
+    suggested_count: AtomicU64,
+    failed_count: AtomicU64,
+}
+impl PartitionManager {
+    pub(super) fn new(config: PartitionConfig) -> Self {
+        PartitionManager {
+            config,
+            suggested_count: AtomicU64::new(0),
+            failed_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks the world in another task, if not checked within the interval.
+    pub(super) fn start_check(self: &Arc<Self>, ud: Arc<UrlData>) {
+        if !ud.try_start_partition_check(self.config.check_interval_sec) {
+            return;
+        }
+        let manager = self.clone();
+        tokio::spawn(async move {
+            manager.check(&ud).await;
+        });
+    }
+
+    async fn check(&self, ud: &UrlData) {
+        let graph = WorldGraph::build(ud);
+        ud.set_world_health(graph.get_health());
+        if !self.config.merge {
+            return;
+        }
+        let components = graph.get_components();
+        if components.len() < 2 {
+            return;
+        }
+
+        // only the peers connected to this hub can be reached for signaling
+        let connected = |component: &[usize]| -> Vec<usize> {
+            let mut v: Vec<usize> = component
+                .iter()
+                .copied()
+                .filter(|i| graph.nodes[*i].connected)
+                .collect();
+            v.sort_by_key(|i| graph.nodes[*i].routing_info.node_type() != NodeType::Gateway);
+            v.truncate(self.config.max_peers);
+            v
+        };
+        let peers = connected(&components[0]);
+        if peers.is_empty() {
+            return;
+        }
+        let packet = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest {
+                rpc_id: RPC_ID_MERGE_SUGGESTION,
+                param: MergeSuggestion {
+                    session_ids: peers
+                        .iter()
+                        .map(|i| graph.nodes[*i].session_id.clone())
+                        .collect(),
+                }
+                .encode_to_vec(),
+            })),
+        }
+        .encode_to_vec();
+
+        let clients: HashMap<SessionId, Arc<ClientData>> = ud
+            .get_clients()
+            .into_iter()
+            .map(|v| (v.session_id, v))
+            .collect();
+        for component in components[1..].iter() {
+            for i in connected(component) {
+                let Ok(session_id) = SessionId::try_from(&graph.nodes[i].session_id) else {
+                    continue;
+                };
+                let Some(cd) = clients.get(&session_id) else {
+                    continue;
+                };
+                match send_swarm_request(cd, packet.clone()).await {
+                    Ok(true) => {
+                        self.suggested_count.fetch_add(1, Ordering::Relaxed);
+                    }
+                    Ok(false) => {}
+                    Err(e) => {
+                        self.failed_count.fetch_add(1, Ordering::Relaxed);
+                        warn!(
+                            "can't send merge suggestion to {}: {:?}",
+                            session_id.to_debug_string(),
+                            e
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    pub(super) fn get_suggested_count(&self) -> u64 {
+        self.suggested_count.load(Ordering::Relaxed)
+    }
+    pub(super) fn get_failed_count(&self) -> u64 {
+        self.failed_count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::mpsc;
+
+    fn ri(n: u8, node_type: NodeType, relations: &[u8]) -> RoutingInfo {
+        let mut ri = RoutingInfo {
+            session_id: Some(vec![n; 32]),
+            node_type: node_type.into(),
+            ..Default::default()
+        };
+        ri.set_relations(
+            relations
+                .iter()
+                .map(|v| RoutingInfo {
+                    session_id: Some(vec![*v; 32]),
+                    node_type: NodeType::Normal.into(),
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        ri
+    }
+
+    #[tokio::test]
+    async fn test_partition_manager() {
+        let ud = UrlData::new_empty();
+        let mut receivers = HashMap::new();
+        // 0(gw) - 1 - 2, 3 - 4, 5
+        for ri in [
+            ri(0, NodeType::Gateway, &[1]),
+            ri(1, NodeType::Normal, &[0, 2]),
+            ri(2, NodeType::Normal, &[1]),
+            ri(3, NodeType::Normal, &[4]),
+            ri(4, NodeType::Normal, &[3]),
+            ri(5, NodeType::Normal, &[]),
+        ] {
+            let n = ri.session_id.as_ref().unwrap()[0];
+            let (tx, rx) = mpsc::channel(10);
+            let cd = ClientData::new_backend([n; 32].into(), "".to_string(), tx);
+            cd.set_routing_info(ri);
+            ud.add_connection(cd, None);
+            receivers.insert(n, rx);
+        }
+        // can't be reached
+        receivers.remove(&4);
+
+        // only the health without merge
+        let manager = PartitionManager::new(PartitionConfig::default());
+        assert!(ud.get_world_health().is_none());
+        manager.check(&ud).await;
+        assert_eq!(ud.get_world_health().unwrap().component_count, 3);
+        assert_eq!(manager.get_suggested_count(), 0);
+
+        let manager = PartitionManager::new(PartitionConfig {
+            merge: true,
+            check_interval_sec: 0,
+            max_peers: 2,
+        });
+        manager.check(&ud).await;
+        assert_eq!(manager.get_suggested_count(), 2);
+        assert_eq!(manager.get_failed_count(), 0);
+        for n in [0, 1, 2] {
+            assert!(receivers.get_mut(&n).unwrap().try_recv().is_err());
+        }
+        for n in [3, 5] {
+            let packet = receivers.get_mut(&n).unwrap().try_recv().unwrap();
+            let Some(swarm_packet::Data::Request(req)) = packet.data else {
+                panic!("not a request");
+            };
+            assert_eq!(req.rpc_id, RPC_ID_MERGE_SUGGESTION);
+            let suggestion = MergeSuggestion::decode(req.param.as_slice()).unwrap();
+            // the gateway first
+            assert_eq!(suggestion.session_ids[0], vec![0; 32]);
+            assert_eq!(suggestion.session_ids.len(), 2);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use parking_lot::Mutex;
+use std::collections::{hash_map, BTreeMap, HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use verse_session_id::SessionId;
+
+/// Result of ReplayCache::check.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplayCheck {
+    New,
+    Replayed,
+    /// New, but the sender already has `sender_quota` entries.
+    OverQuota,
+}
+
+#[derive(Default)]
+struct Sender {
+    salts: HashMap<Vec<u8>, u64>,
+    // sequences of `salts`, oldest first
+    order: VecDeque<u64>,
+}
+
+#[derive(Default)]
+struct Inner {
+    // sequence -> (from_session_id, salt, inserted at), oldest first
+    entries: BTreeMap<u64, (SessionId, Vec<u8>, Instant)>,
+    senders: HashMap<SessionId, Sender>,
+    next_seq: u64,
+}
+impl Inner {
+    /// `seq` is always the oldest entry of its sender.
+    fn remove(&mut self, seq: u64) {
+        let Some((from, salt, _)) = self.entries.remove(&seq) else {
+            return;
+        };
+        if let hash_map::Entry::Occupied(mut sender) = self.senders.entry(from) {
+            let v = sender.get_mut();
+            v.salts.remove(&salt);
+            let front = v.order.pop_front();
+            debug_assert_eq!(front, Some(seq));
+            if v.order.is_empty() {
+                sender.remove();
+            }
+        }
+    }
+    fn expire(&mut self, now: Instant, window: Duration) {
+        while let Some((&seq, (_, _, at))) = self.entries.first_key_value() {
+            if now.duration_since(*at) < window {
+                break;
+            }
+            self.remove(seq);
+        }
+    }
+}
+
+/// Remembers (from_session_id, salt) of the signatures accepted, to detect replays.
+/// Signatures do not cover time, so a replay older than `window` is not detected.
+/// Each sender keeps at most `sender_quota` entries and pushes out its own oldest ones
+/// beyond that, so flooding with distinct salts only weakens the detection for the flooder.
+/// The oldest entries of all are pushed out while the cache holds `capacity` entries.
+pub struct ReplayCache {
+    window: Duration,
+    capacity: usize,
+    sender_quota: usize,
+    inner: Mutex<Inner>,
+    evicted_count: AtomicU64,
+}
+impl ReplayCache {
+    pub fn new(window: Duration, capacity: usize, sender_quota: usize) -> Self {
+        ReplayCache {
+            window,
+            capacity,
+            sender_quota,
+            inner: Default::default(),
+            evicted_count: AtomicU64::new(0),
+        }
+    }
+
+    /// Checks the pair without remembering it.
+    pub fn check(&self, from_session_id: SessionId, salt: &[u8], now: Instant) -> ReplayCheck {
+        let mut inner = self.inner.lock();
+        inner.expire(now, self.window);
+        match inner.senders.get(&from_session_id) {
+            Some(v) if v.salts.contains_key(salt) => ReplayCheck::Replayed,
+            Some(v) if v.order.len() >= self.sender_quota => ReplayCheck::OverQuota,
+            _ => ReplayCheck::New,
+        }
+    }
+    /// Remembers the pair, returns false if it has been seen within the window.
+    pub fn insert(&self, from_session_id: SessionId, salt: &[u8], now: Instant) -> bool {
+        let mut inner = self.inner.lock();
+        inner.expire(now, self.window);
+        let oldest_of_sender = match inner.senders.get(&from_session_id) {
+            Some(v) if v.salts.contains_key(salt) => return false,
+            Some(v) if v.order.len() >= self.sender_quota => v.order.front().copied(),
+            _ => None,
+        };
+        let evicted = match oldest_of_sender {
+            Some(seq) => Some(seq),
+            None if inner.entries.len() >= self.capacity => {
+                inner.entries.first_key_value().map(|(seq, _)| *seq)
+            }
+            None => None,
+        };
+        if let Some(seq) = evicted {
+            inner.remove(seq);
+            self.evicted_count.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let seq = inner.next_seq;
+        inner.next_seq += 1;
+        inner
+            .entries
+            .insert(seq, (from_session_id, salt.to_vec(), now));
+        let sender = inner.senders.entry(from_session_id).or_default();
+        sender.salts.insert(salt.to_vec(), seq);
+        sender.order.push_back(seq);
+        true
+    }
+
+    pub fn len(&self) -> usize {
+        self.inner.lock().entries.len()
+    }
+    /// Number of entries pushed out before expiring.
+    pub fn get_evicted_count(&self) -> u64 {
+        self.evicted_count.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replay_cache() {
+        let c = ReplayCache::new(Duration::from_secs(10), 3, 3);
+        let a: SessionId = [1; 32].into();
+        let b: SessionId = [2; 32].into();
+        let now = Instant::now();
+
+        assert_eq!(c.check(a, b"1", now), ReplayCheck::New);
+        assert!(c.insert(a, b"1", now));
+        assert_eq!(c.check(a, b"1", now), ReplayCheck::Replayed);
+        assert!(!c.insert(a, b"1", now));
+        assert!(c.insert(b, b"1", now));
+        assert!(c.insert(a, b"2", now));
+        assert_eq!(c.len(), 3);
+
+        // the oldest one is pushed out when full
+        assert!(c.insert(b, b"2", now));
+        assert_eq!(c.len(), 3);
+        assert_eq!(c.get_evicted_count(), 1);
+        assert_eq!(c.check(a, b"1", now), ReplayCheck::New);
+        assert_eq!(c.check(b, b"1", now), ReplayCheck::Replayed);
+
+        // expired
+        let later = now + Duration::from_secs(10);
+        assert!(c.insert(a, b"3", later));
+        assert!(!c.insert(a, b"3", later));
+        assert_eq!(c.check(b, b"2", later), ReplayCheck::New);
+        assert_eq!(c.len(), 1);
+    }
+
+    #[test]
+    fn test_replay_cache_flood() {
+        let c = ReplayCache::new(Duration::from_secs(10), 100, 10);
+        let victim: SessionId = [1; 32].into();
+        let attacker: SessionId = [2; 32].into();
+        let now = Instant::now();
+
+        assert!(c.insert(victim, b"victim", now));
+        for i in 0..1000u32 {
+            let at = now + Duration::from_millis(i as u64);
+            assert_ne!(
+                c.check(attacker, &i.to_le_bytes(), at),
+                ReplayCheck::Replayed
+            );
+            assert!(c.insert(attacker, &i.to_le_bytes(), at));
+        }
+        // the attacker only pushes out its own entries
+        assert_eq!(c.len(), 11);
+        assert_eq!(c.get_evicted_count(), 990);
+        let at = now + Duration::from_secs(9);
+        assert_eq!(c.check(attacker, b"new", at), ReplayCheck::OverQuota);
+        assert_eq!(
+            c.check(attacker, &999u32.to_le_bytes(), at),
+            ReplayCheck::Replayed
+        );
+        assert_eq!(c.check(victim, b"victim", at), ReplayCheck::Replayed);
+        assert!(c.insert(victim, b"victim2", at));
+    }
+}
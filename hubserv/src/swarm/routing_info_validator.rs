@@ -0,0 +1,271 @@
+#[allow(unused_imports)]
+use log::{debug, error, info, warn};
+use prost::Message;
+use std::sync::atomic::{AtomicU64, Ordering};
+use verse_proto::primitive::Position3D;
+use verse_proto::swarm::*;
+use verse_session_id::SessionId;
+
+#[derive(Clone, Debug)]
+pub struct RoutingInfoLimits {
+    /// Relations deeper than this are replaced with their count.
+    pub max_depth: u32,
+    /// Relations of a node beyond this are dropped.
+    pub max_breadth: usize,
+    /// Upper limit of the absolute value of each coordinate.
+    pub max_position: f64,
+    /// Upper limit of the encoded size.
+    pub max_size: usize,
+}
+impl Default for RoutingInfoLimits {
+    fn default() -> Self {
+        RoutingInfoLimits {
+            max_depth: 4,
+            max_breadth: 100,
+            max_position: 1e9,
+            max_size: 64 * 1024,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, thiserror::Error)]
+pub enum Violation {
+    #[error("session_id does not match the sender")]
+    SessionId,
+    #[error("bad node_type")]
+    NodeType,
+    #[error("bad position")]
+    Position,
+    #[error("too large")]
+    Size,
+    #[error("too deep")]
+    Depth,
+    #[error("too many relations")]
+    Breadth,
+}
+impl Violation {
+    const ALL: [Violation; 6] = [
+        Violation::SessionId,
+        Violation::NodeType,
+        Violation::Position,
+        Violation::Size,
+        Violation::Depth,
+        Violation::Breadth,
+    ];
+    pub fn name(&self) -> &'static str {
+        match self {
+            Violation::SessionId => "session_id",
+            Violation::NodeType => "node_type",
+            Violation::Position => "position",
+            Violation::Size => "size",
+            Violation::Depth => "depth",
+            Violation::Breadth => "breadth",
+        }
+    }
+}
+
+/// Checks RoutingInfo reported by a client, and counts the violations.
+pub struct RoutingInfoValidator {
+    limits: RoutingInfoLimits,
+    counts: [AtomicU64; Violation::ALL.len()],
+}
+impl RoutingInfoValidator {
+    pub fn new(limits: RoutingInfoLimits) -> Self {
+        RoutingInfoValidator {
+            limits,
+            counts: Default::default(),
+        }
+    }
+
+    /// Rejects RoutingInfo whose own fields are bad, and drops bad parts of its relations.
+    pub fn validate(
+        &self,
+        mut ri: RoutingInfo,
+        session_id: &SessionId,
+    ) -> Result<RoutingInfo, Violation> {
+        self.check(&ri, session_id)
+            .inspect_err(|e| self.report(*e))?;
+        let mut violations = Vec::new();
+        self.sanitize(&mut ri, 0, &mut violations);
+        if !violations.is_empty() {
+            debug!(
+                "routing info sanitized {}: {:?}",
+                session_id.to_debug_string(),
+                violations
+            );
+        }
+        for v in violations {
+            self.report(v);
+        }
+        Ok(ri)
+    }
+
+    pub fn get_metrics(&self) -> Vec<(String, i64)> {
+        Violation::ALL
+            .iter()
+            .map(|v| {
+                (
+                    format!("routing_info_violation_count{{reason=\"{}\"}}", v.name()),
+                    self.counts[*v as usize].load(Ordering::Relaxed) as i64,
+                )
+            })
+            .collect()
+    }
+
+    fn check(&self, ri: &RoutingInfo, session_id: &SessionId) -> Result<(), Violation> {
+        if ri.encoded_len() > self.limits.max_size {
+            return Err(Violation::Size);
+        }
+        if !ri
+            .session_id
+            .as_ref()
+            .is_some_and(|v| session_id.eq_slice(v))
+        {
+            return Err(Violation::SessionId);
+        }
+        if !is_valid_node_type(ri) {
+            return Err(Violation::NodeType);
+        }
+        if !self.is_valid_position(ri.position.as_ref()) {
+            return Err(Violation::Position);
+        }
+        Ok(())
+    }
+
+    fn sanitize(&self, ri: &mut RoutingInfo, depth: u32, violations: &mut Vec<Violation>) {
+        let Some(routing_info::Relation::RoutingInfos(ris)) = ri.relation.as_mut() else {
+            return;
+        };
+        if self.limits.max_depth <= depth {
+            if !ris.infos.is_empty() {
+                violations.push(Violation::Depth);
+            }
+            let count = ris.infos.len() as u32;
+            ri.set_count(count);
+            return;
+        }
+        if self.limits.max_breadth < ris.infos.len() {
+            violations.push(Violation::Breadth);
+            ris.infos.truncate(self.limits.max_breadth);
+        }
+        ris.infos.retain(|v| {
+            let valid = is_valid_node_type(v);
+            if !valid {
+                violations.push(Violation::NodeType);
+            }
+            valid
+        });
+        for v in ris.infos.iter_mut() {
+            if !self.is_valid_position(v.position.as_ref()) {
+                violations.push(Violation::Position);
+                v.position = None;
+            }
+            self.sanitize(v, depth + 1, violations);
+        }
+    }
+
+    fn is_valid_position(&self, position: Option<&Position3D>) -> bool {
+        let Some(p) = position else {
+            return true;
+        };
+        [p.x, p.y as f64, p.z]
+            .iter()
+            .all(|v| v.is_finite() && v.abs() <= self.limits.max_position)
+    }
+
+    fn report(&self, violation: Violation) {
+        self.counts[violation as usize].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+fn is_valid_node_type(ri: &RoutingInfo) -> bool {
+    matches!(ri.node_type(), NodeType::Normal | NodeType::Gateway)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ri(n: u8, relations: Vec<RoutingInfo>) -> RoutingInfo {
+        let mut ri = RoutingInfo {
+            node_type: NodeType::Normal.into(),
+            session_id: Some([n; 32].to_vec()),
+            ..Default::default()
+        };
+        ri.set_relations(relations);
+        ri
+    }
+    fn pos(x: f64) -> Option<Position3D> {
+        Some(Position3D { x, y: 0.0, z: 0.0 })
+    }
+    fn count(validator: &RoutingInfoValidator, violation: Violation) -> u64 {
+        validator.counts[violation as usize].load(Ordering::Relaxed)
+    }
+
+    #[test]
+    fn test_validate() {
+        let validator = RoutingInfoValidator::new(RoutingInfoLimits {
+            max_depth: 2,
+            max_breadth: 3,
+            max_position: 100.0,
+            max_size: 1024,
+        });
+        let sid: SessionId = [1; 32].into();
+
+        assert!(validator.validate(ri(1, vec![]), &sid).is_ok());
+        assert_eq!(
+            validator.validate(ri(2, vec![]), &sid).unwrap_err(),
+            Violation::SessionId
+        );
+        let mut v = ri(1, vec![]);
+        v.session_id = None;
+        assert_eq!(
+            validator.validate(v, &sid).unwrap_err(),
+            Violation::SessionId
+        );
+        let mut v = ri(1, vec![]);
+        v.node_type = NodeType::Tracker.into();
+        assert_eq!(
+            validator.validate(v, &sid).unwrap_err(),
+            Violation::NodeType
+        );
+        let mut v = ri(1, vec![]);
+        v.position = pos(f64::NAN);
+        assert_eq!(
+            validator.validate(v, &sid).unwrap_err(),
+            Violation::Position
+        );
+        let mut v = ri(1, vec![]);
+        v.position = pos(101.0);
+        assert_eq!(
+            validator.validate(v, &sid).unwrap_err(),
+            Violation::Position
+        );
+        let v = ri(1, (0..100).map(|n| ri(n, vec![])).collect());
+        assert_eq!(validator.validate(v, &sid).unwrap_err(), Violation::Size);
+        assert_eq!(count(&validator, Violation::SessionId), 2);
+
+        // sanitized
+        let mut tracker = ri(3, vec![]);
+        tracker.node_type = NodeType::Tracker.into();
+        let mut far = ri(4, vec![ri(5, vec![ri(6, vec![])])]);
+        far.position = pos(1000.0);
+        let v = ri(
+            1,
+            vec![tracker, far, ri(7, vec![]), ri(8, vec![]), ri(9, vec![])],
+        );
+        let v = validator.validate(v, &sid).unwrap();
+        let relations = v.get_relations().unwrap();
+        assert_eq!(relations.len(), 2);
+        assert!(relations[0].position.is_none());
+        let nested = relations[0].get_relations().unwrap();
+        assert_eq!(nested.len(), 1);
+        assert!(nested[0].get_relations().is_none());
+        assert_eq!(nested[0].get_relation_count(), 1);
+        assert_eq!(count(&validator, Violation::NodeType), 2);
+        assert_eq!(count(&validator, Violation::Position), 3);
+        assert_eq!(count(&validator, Violation::Depth), 1);
+        assert_eq!(count(&validator, Violation::Breadth), 1);
+        assert_eq!(validator.get_metrics().len(), Violation::ALL.len());
+    }
+}
@@ -0,0 +1,232 @@
+use anyhow::{anyhow, Result};
+use rand::seq::SliceRandom;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+use std::sync::Arc;
+use verse_proto::swarm::*;
+
+/// Chooses which members of a world are returned by ExchangeRoutingInfo.
+pub trait RoutingSelector: Send + Sync {
+    /// Returns at most `n` of `candidates` for `requester`.
+    fn select(
+        &self,
+        requester: &RoutingInfo,
+        candidates: &[RoutingInfo],
+        n: usize,
+    ) -> Vec<RoutingInfo>;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum RoutingStrategy {
+    Random,
+    /// Prefer peers with fewer relations.
+    LowRelationCount,
+    /// Prefer gateways that have been connected to the tracker the longest.
+    LongLivedGateway,
+    /// Prefer peers not in the requester's relations.
+    NotRelated,
+}
+impl RoutingStrategy {
+    pub fn to_selector(self) -> Arc<dyn RoutingSelector> {
+        match self {
+            RoutingStrategy::Random => Arc::new(RandomSelector),
+            RoutingStrategy::LowRelationCount => Arc::new(LowRelationCountSelector),
+            RoutingStrategy::LongLivedGateway => Arc::new(LongLivedGatewaySelector),
+            RoutingStrategy::NotRelated => Arc::new(NotRelatedSelector),
+        }
+    }
+}
+
+/// `<url>=<strategy>`
+#[derive(Clone, Debug)]
+pub struct WorldRoutingStrategy {
+    pub url: String,
+    pub strategy: RoutingStrategy,
+}
+impl FromStr for WorldRoutingStrategy {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (url, strategy) = s
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("expected <url>=<strategy>: {}", s))?;
+        let strategy = <RoutingStrategy as clap::ValueEnum>::from_str(strategy, true)
+            .map_err(|e| anyhow!(e))?;
+        Ok(WorldRoutingStrategy {
+            url: url.to_string(),
+            strategy,
+        })
+    }
+}
+
+/// The selector of each world.
+#[derive(Clone)]
+pub struct RoutingSelectors {
+    default: Arc<dyn RoutingSelector>,
+    by_url: HashMap<String, Arc<dyn RoutingSelector>>,
+}
+impl RoutingSelectors {
+    pub fn new(default: Arc<dyn RoutingSelector>) -> Self {
+        RoutingSelectors {
+            default,
+            by_url: HashMap::new(),
+        }
+    }
+    pub fn with_world(
+        mut self,
+        url: impl Into<String>,
+        selector: Arc<dyn RoutingSelector>,
+    ) -> Self {
+        self.by_url.insert(url.into(), selector);
+        self
+    }
+    pub fn get(&self, url: &str) -> &dyn RoutingSelector {
+        self.by_url.get(url).unwrap_or(&self.default).as_ref()
+    }
+}
+impl Default for RoutingSelectors {
+    fn default() -> Self {
+        Self::new(RoutingStrategy::Random.to_selector())
+    }
+}
+
+pub struct RandomSelector;
+impl RoutingSelector for RandomSelector {
+    fn select(&self, _: &RoutingInfo, candidates: &[RoutingInfo], n: usize) -> Vec<RoutingInfo> {
+        candidates
+            .choose_multiple(&mut rand::thread_rng(), n)
+            .cloned()
+            .collect()
+    }
+}
+
+pub struct LowRelationCountSelector;
+impl RoutingSelector for LowRelationCountSelector {
+    fn select(&self, _: &RoutingInfo, candidates: &[RoutingInfo], n: usize) -> Vec<RoutingInfo> {
+        select_by_key(candidates, n, |v| v.get_relation_count())
+    }
+}
+
+pub struct LongLivedGatewaySelector;
+impl RoutingSelector for LongLivedGatewaySelector {
+    fn select(&self, _: &RoutingInfo, candidates: &[RoutingInfo], n: usize) -> Vec<RoutingInfo> {
+        select_by_key(candidates, n, |v| {
+            match (v.node_type(), v.gateway_state.as_ref()) {
+                // still connected, the earliest connection first
+                (NodeType::Gateway, Some(gs)) if gs.last_disconnect_time < gs.last_connect_time => {
+                    (0, gs.last_connect_time)
+                }
+                (NodeType::Gateway, _) => (1, 0),
+                _ => (2, 0),
+            }
+        })
+    }
+}
+
+pub struct NotRelatedSelector;
+impl RoutingSelector for NotRelatedSelector {
+    fn select(
+        &self,
+        requester: &RoutingInfo,
+        candidates: &[RoutingInfo],
+        n: usize,
+    ) -> Vec<RoutingInfo> {
+        let related: HashSet<&[u8]> = requester
+            .get_relations()
+            .unwrap_or_default()
+            .iter()
+            .chain(std::iter::once(requester))
+            .filter_map(|v| v.session_id.as_deref())
+            .collect();
+        select_by_key(candidates, n, |v| {
+            v.session_id
+                .as_deref()
+                .is_some_and(|id| related.contains(id))
+        })
+    }
+}
+
+/// The `n` smallest by `key`. Ties are broken randomly.
+fn select_by_key<K: Ord>(
+    candidates: &[RoutingInfo],
+    n: usize,
+    key: impl Fn(&RoutingInfo) -> K,
+) -> Vec<RoutingInfo> {
+    let mut v: Vec<&RoutingInfo> = candidates.iter().collect();
+    v.shuffle(&mut rand::thread_rng());
+    v.sort_by_cached_key(|v| key(v));
+    v.into_iter().take(n).cloned().collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ri(n: u8, relation_count: u32) -> RoutingInfo {
+        RoutingInfo {
+            node_type: NodeType::Normal.into(),
+            session_id: Some([n; 32].to_vec()),
+            relation: Some(routing_info::Relation::Count(relation_count)),
+            ..Default::default()
+        }
+    }
+    fn gateway(n: u8, last_connect_time: u64, last_disconnect_time: u64) -> RoutingInfo {
+        RoutingInfo {
+            node_type: NodeType::Gateway.into(),
+            gateway_state: Some(GatewayState {
+                last_connect_time,
+                last_disconnect_time,
+            }),
+            ..ri(n, 0)
+        }
+    }
+    fn ids(v: &[RoutingInfo]) -> Vec<u8> {
+        let mut ids: Vec<u8> = v
+            .iter()
+            .map(|v| v.session_id.as_ref().unwrap()[0])
+            .collect();
+        ids.sort();
+        ids
+    }
+
+    #[test]
+    fn test_selectors() {
+        let requester = ri(0, 0);
+        let candidates = vec![ri(1, 5), ri(2, 1), ri(3, 3), ri(4, 0)];
+
+        assert_eq!(RandomSelector.select(&requester, &candidates, 2).len(), 2);
+        assert_eq!(RandomSelector.select(&requester, &candidates, 5).len(), 4);
+
+        let res = LowRelationCountSelector.select(&requester, &candidates, 2);
+        assert_eq!(ids(&res), vec![2, 4]);
+
+        let candidates = vec![
+            ri(1, 0),
+            gateway(2, 30, 10),
+            gateway(3, 20, 10),
+            gateway(4, 10, 20),
+        ];
+        let res = LongLivedGatewaySelector.select(&requester, &candidates, 1);
+        assert_eq!(ids(&res), vec![3]);
+        let res = LongLivedGatewaySelector.select(&requester, &candidates, 3);
+        assert_eq!(ids(&res), vec![2, 3, 4]);
+
+        let mut requester = ri(0, 0);
+        requester.set_relations(vec![ri(1, 0), ri(2, 0)]);
+        let candidates = vec![ri(0, 0), ri(1, 0), ri(2, 0), ri(3, 0), ri(4, 0)];
+        let res = NotRelatedSelector.select(&requester, &candidates, 2);
+        assert_eq!(ids(&res), vec![3, 4]);
+    }
+
+    #[test]
+    fn test_world_routing_strategy() {
+        let v: WorldRoutingStrategy = "https://example.com/a?b=c=not-related".parse().unwrap();
+        assert_eq!(v.url, "https://example.com/a?b=c");
+        assert_eq!(v.strategy, RoutingStrategy::NotRelated);
+        assert!("https://example.com/a"
+            .parse::<WorldRoutingStrategy>()
+            .is_err());
+        assert!("https://example.com/a=foo"
+            .parse::<WorldRoutingStrategy>()
+            .is_err());
+    }
+}
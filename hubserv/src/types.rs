@@ -101,6 +101,30 @@ impl RequestPayload for CandidateRequestPayload {
     }
 }
 
+/// Signed by a backend connecting with SwarmBackendService.
+#[derive(Default, Serialize, Deserialize)]
+pub struct BackendRequestPayload {
+    pub url: String,
+    /// Unix time in milliseconds, limits the replay of the signature.
+    pub timestamp: i64,
+
+    #[serde(skip)]
+    pub raw_url: String,
+}
+impl RequestPayload for BackendRequestPayload {
+    fn normalize(&mut self) {
+        self.raw_url = self.url.clone();
+        if self.raw_url.len() > MAX_URL_LEN
+            || self.raw_url.contains('\r')
+            || self.raw_url.contains('\n')
+        {
+            self.url = "".into();
+            return;
+        }
+        self.url = normalize_url(&self.url);
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct EnterResponse {
     pub sdp: RTCSessionDescription,
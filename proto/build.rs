@@ -1,6 +1,260 @@
+use prost_build::{Method, Service, ServiceGenerator};
+use std::fmt::{self, Write};
 use std::io::Result;
+
+const RPC_ID_TAG: &str = "@rpc_id";
+
+/// Generates the rpc ids, typed methods, `Server` trait and dispatchers of each service
+/// (see src/service.rs).
+/// Streaming services are served with gRPC instead.
+struct RpcServiceGenerator {
+    #[cfg(feature = "grpc")]
+    grpc: Box<dyn ServiceGenerator>,
+}
+impl ServiceGenerator for RpcServiceGenerator {
+    fn generate(&mut self, service: Service, buf: &mut String) {
+        if service
+            .methods
+            .iter()
+            .any(|m| m.client_streaming || m.server_streaming)
+        {
+            #[cfg(feature = "grpc")]
+            self.grpc.generate(service, buf);
+            return;
+        }
+        generate_service(&service, buf).unwrap();
+    }
+    #[cfg(feature = "grpc")]
+    fn finalize(&mut self, buf: &mut String) {
+        self.grpc.finalize(buf);
+    }
+}
+
+fn get_rpc_ids(service: &Service) -> Vec<u32> {
+    let mut next = 1;
+    service
+        .methods
+        .iter()
+        .map(|m| {
+            let tagged = m
+                .comments
+                .leading
+                .iter()
+                .find_map(|v| v.trim().strip_prefix(RPC_ID_TAG));
+            let id = match tagged {
+                Some(v) => v.trim().parse().unwrap_or_else(|_| {
+                    panic!("bad {}: {}.{}", RPC_ID_TAG, service.name, m.proto_name)
+                }),
+                None => next,
+            };
+            next = id + 1;
+            id
+        })
+        .collect()
+}
+
+fn to_snake_case(v: &str) -> String {
+    let mut res = String::new();
+    for (i, c) in v.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                res.push('_');
+            }
+            res.push(c.to_ascii_lowercase());
+        } else {
+            res.push(c);
+        }
+    }
+    res
+}
+
+fn write_doc(buf: &mut String, method: &Method, indent: &str) -> fmt::Result {
+    for line in method.comments.leading.iter() {
+        if !line.trim().starts_with(RPC_ID_TAG) {
+            writeln!(buf, "{}///{}", indent, line)?;
+        }
+    }
+    Ok(())
+}
+
+fn generate_service(service: &Service, buf: &mut String) -> fmt::Result {
+    let ids = get_rpc_ids(service);
+    for (i, id) in ids.iter().enumerate() {
+        if ids[..i].contains(id) {
+            panic!("duplicated rpc id: {}.{}", service.name, id);
+        }
+    }
+
+    writeln!(buf, "/// Generated from `service {}`.", service.proto_name)?;
+    writeln!(buf, "pub mod {} {{", to_snake_case(&service.name))?;
+    for (m, id) in service.methods.iter().zip(ids.iter()) {
+        writeln!(
+            buf,
+            "    pub const RPC_ID_{}: u32 = {};",
+            m.name.to_uppercase(),
+            id
+        )?;
+    }
+    writeln!(buf, "    /// Rpc ids and method names.")?;
+    writeln!(buf, "    pub const METHODS: &[(u32, &str)] = &[")?;
+    for (m, id) in service.methods.iter().zip(ids.iter()) {
+        writeln!(buf, "        ({}, \"{}\"),", id, m.name)?;
+    }
+    writeln!(buf, "    ];")?;
+
+    for (m, id) in service.methods.iter().zip(ids.iter()) {
+        writeln!(buf)?;
+        write_doc(buf, m, "    ")?;
+        writeln!(buf, "    pub struct {};", m.proto_name)?;
+        writeln!(
+            buf,
+            "    impl crate::service::RpcMethod for {} {{",
+            m.proto_name
+        )?;
+        writeln!(buf, "        const RPC_ID: u32 = {};", id)?;
+        writeln!(buf, "        const NAME: &'static str = \"{}\";", m.name)?;
+        writeln!(buf, "        type Request = super::{};", m.input_type)?;
+        writeln!(buf, "        type Response = super::{};", m.output_type)?;
+        writeln!(buf, "    }}")?;
+    }
+
+    writeln!(buf)?;
+    writeln!(buf, "    #[async_trait::async_trait]")?;
+    writeln!(buf, "    pub trait Server: Send + Sync {{")?;
+    writeln!(buf, "        type Context: Send;")?;
+    writeln!(buf, "        type Error: From<prost::DecodeError> + Send;")?;
+    for m in service.methods.iter() {
+        write_doc(buf, m, "        ")?;
+        writeln!(
+            buf,
+            "        async fn {}(&self, ctx: Self::Context, req: super::{}) -> Result<super::{}, Self::Error>;",
+            m.name, m.input_type, m.output_type
+        )?;
+    }
+    writeln!(buf, "    }}")?;
+
+    writeln!(buf)?;
+    writeln!(
+        buf,
+        "    /// Decodes `param` and calls the method of `rpc_id`. None if the id is unknown."
+    )?;
+    writeln!(
+        buf,
+        "    pub async fn dispatch<S: Server + ?Sized>(server: &S, ctx: S::Context, rpc_id: u32, param: &[u8]) -> Option<Result<Vec<u8>, S::Error>> {{"
+    )?;
+    writeln!(buf, "        use prost::Message;")?;
+    writeln!(buf, "        Some(match rpc_id {{")?;
+    for (m, id) in service.methods.iter().zip(ids.iter()) {
+        writeln!(
+            buf,
+            "            {} => match super::{}::decode(param) {{",
+            id, m.input_type
+        )?;
+        writeln!(
+            buf,
+            "                Ok(req) => server.{}(ctx, req).await.map(|res| res.encode_to_vec()),",
+            m.name
+        )?;
+        writeln!(buf, "                Err(e) => Err(e.into()),")?;
+        writeln!(buf, "            }},")?;
+    }
+    writeln!(buf, "            _ => return None,")?;
+    writeln!(buf, "        }})")?;
+    writeln!(buf, "    }}")?;
+
+    writeln!(
+        buf,
+        "    /// The response has the request_id of `req`. None if the id is unknown."
+    )?;
+    writeln!(
+        buf,
+        "    pub async fn dispatch_rpc_request<S: Server + ?Sized>(server: &S, ctx: S::Context, req: crate::rpc::RpcRequest) -> Option<Result<crate::rpc::RpcResponse, S::Error>> {{"
+    )?;
+    writeln!(
+        buf,
+        "        let res = dispatch(server, ctx, req.rpc_id, &req.param).await?;"
+    )?;
+    writeln!(
+        buf,
+        "        Some(res.map(|param| crate::rpc::RpcResponse {{ rpc_id: req.rpc_id, param, request_id: req.request_id }}))"
+    )?;
+    writeln!(buf, "    }}")?;
+
+    writeln!(
+        buf,
+        "    /// Answers the SwarmRequest in `packet`. None if it is not a request or the id is unknown."
+    )?;
+    writeln!(
+        buf,
+        "    pub async fn dispatch_swarm_packet<S: Server + ?Sized>(server: &S, ctx: S::Context, packet: crate::swarm::SwarmPacket) -> Option<Result<crate::swarm::SwarmPacket, S::Error>> {{"
+    )?;
+    writeln!(buf, "        let req = match packet.data {{")?;
+    writeln!(
+        buf,
+        "            Some(crate::swarm::swarm_packet::Data::Request(req)) => req,"
+    )?;
+    writeln!(buf, "            _ => return None,")?;
+    writeln!(buf, "        }};")?;
+    writeln!(
+        buf,
+        "        let res = dispatch(server, ctx, req.rpc_id, &req.param).await?;"
+    )?;
+    writeln!(
+        buf,
+        "        Some(res.map(|param| crate::swarm::SwarmPacket {{"
+    )?;
+    writeln!(
+        buf,
+        "            data: Some(crate::swarm::swarm_packet::Data::Response(crate::swarm::SwarmResponse {{ rpc_id: req.rpc_id, param }})),"
+    )?;
+    writeln!(buf, "        }}))")?;
+    writeln!(buf, "    }}")?;
+
+    writeln!(buf)?;
+    writeln!(
+        buf,
+        "    /// `Server` as a [`crate::service::RpcService`], to be dispatched without knowing the service."
+    )?;
+    writeln!(
+        buf,
+        "    pub struct Service<S: ?Sized>(pub std::sync::Arc<S>);"
+    )?;
+    writeln!(buf, "    #[async_trait::async_trait]")?;
+    writeln!(
+        buf,
+        "    impl<S: Server + ?Sized> crate::service::RpcService for Service<S> {{"
+    )?;
+    writeln!(buf, "        type Context = S::Context;")?;
+    writeln!(buf, "        type Error = S::Error;")?;
+    writeln!(
+        buf,
+        "        fn methods(&self) -> &'static [(u32, &'static str)] {{"
+    )?;
+    writeln!(buf, "            METHODS")?;
+    writeln!(buf, "        }}")?;
+    writeln!(
+        buf,
+        "        async fn dispatch(&self, ctx: S::Context, rpc_id: u32, param: &[u8]) -> Option<Result<Vec<u8>, S::Error>> {{"
+    )?;
+    writeln!(
+        buf,
+        "            dispatch(&*self.0, ctx, rpc_id, param).await"
+    )?;
+    writeln!(buf, "        }}")?;
+    writeln!(buf, "    }}")?;
+    writeln!(buf, "}}")?;
+    Ok(())
+}
+
 fn main() -> Result<()> {
-    prost_build::compile_protos(
+    let mut config = prost_build::Config::new();
+    config.service_generator(Box::new(RpcServiceGenerator {
+        #[cfg(feature = "grpc")]
+        grpc: tonic_build::configure()
+            .build_client(false)
+            .service_generator(),
+    }));
+    config.compile_protos(
         &[
             "rpc.proto",
             "swarm.proto",
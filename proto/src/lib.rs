@@ -22,24 +22,42 @@ pub mod person {
     #![allow(clippy::all)]
     include!(concat!(env!("OUT_DIR"), "/person.rs"));
 }
+mod rpc_fragment;
+
 pub mod rpc {
     #![allow(clippy::all)]
     include!(concat!(env!("OUT_DIR"), "/rpc.rs"));
 
+    pub use super::rpc_fragment::*;
+
     use anyhow::Result;
     #[allow(unused_imports)]
     use log::{debug, error, info, trace, warn};
     use prost::Message;
     use std::io::Cursor;
-    use verse_common::compress::{compress_if_needed, decompress};
+    use verse_common::compress::{compress_with, decompress_with_limits};
+    pub use verse_common::compress::{Codec, DecompressError, DecompressLimits};
 
     pub trait IRpcPacket {
         fn set_request(&mut self, v: RpcRequest);
         fn set_response(&mut self, v: RpcResponse);
+        fn set_error(&mut self, v: RpcError);
+        fn get_error(&self) -> Option<&RpcError>;
+        /// rpc_id of the request, response or error.
+        fn get_rpc_id(&self) -> Option<u32>;
+        /// Compresses the param with zlib, which every client can decode.
         fn encode_packet(&mut self) -> Vec<u8>;
+        fn encode_packet_with(&mut self, codec: Codec) -> Vec<u8>;
+        /// Same as encode_packet_with, but leaves the param uncompressed for RPC_CODEC_NONE.
+        fn encode_packet_as(&mut self, codec: RpcCodec) -> Vec<u8>;
+        /// Decompresses the param with the default DecompressLimits.
         fn decode_packet(data: &[u8]) -> Result<Self>
         where
             Self: Default;
+        /// Fails with DecompressError if the param can not be decompressed within `limits`.
+        fn decode_packet_with_limits(data: &[u8], limits: &DecompressLimits) -> Result<Self>
+        where
+            Self: Default;
     }
     impl IRpcPacket for RpcPacket {
         fn set_request(&mut self, v: RpcRequest) {
@@ -48,51 +66,142 @@ pub mod rpc {
         fn set_response(&mut self, v: RpcResponse) {
             self.data = Some(rpc_packet::Data::Response(v));
         }
+        fn set_error(&mut self, v: RpcError) {
+            self.data = Some(rpc_packet::Data::Error(v));
+        }
+        fn get_error(&self) -> Option<&RpcError> {
+            match self.data {
+                Some(rpc_packet::Data::Error(ref v)) => Some(v),
+                _ => None,
+            }
+        }
+        fn get_rpc_id(&self) -> Option<u32> {
+            match self.data {
+                Some(rpc_packet::Data::Request(ref v)) => Some(v.rpc_id),
+                Some(rpc_packet::Data::Response(ref v)) => Some(v.rpc_id),
+                Some(rpc_packet::Data::Error(ref v)) => Some(v.rpc_id),
+                Some(rpc_packet::Data::Fragment(_)) | None => None,
+            }
+        }
         fn encode_packet(&mut self) -> Vec<u8> {
+            self.encode_packet_with(Codec::Zlib)
+        }
+        fn encode_packet_with(&mut self, codec: Codec) -> Vec<u8> {
             if !self.is_compressed {
-                match self.data {
-                    Some(rpc_packet::Data::Request(ref mut r)) => {
-                        if let Ok(Some(compressed)) = compress_if_needed(&mut r.param) {
-                            r.param = compressed;
-                            self.is_compressed = true;
+                let param = match self.data {
+                    Some(rpc_packet::Data::Request(ref mut r)) => Some(&mut r.param),
+                    Some(rpc_packet::Data::Response(ref mut r)) => Some(&mut r.param),
+                    _ => None,
+                };
+                if let Some(param) = param {
+                    if let Ok(Some(compressed)) = compress_with(codec, param) {
+                        *param = compressed;
+                        self.is_compressed = true;
+                        if codec != Codec::Zlib {
+                            self.set_codec(RpcCodec::from_codec(codec));
                         }
                     }
-                    Some(rpc_packet::Data::Response(ref mut r)) => {
-                        if let Ok(Some(compressed)) = compress_if_needed(&mut r.param) {
-                            r.param = compressed;
-                            self.is_compressed = true;
-                        }
-                    }
-                    _ => {}
                 }
             }
             self.encode_to_vec()
         }
+        fn encode_packet_as(&mut self, codec: RpcCodec) -> Vec<u8> {
+            match codec.to_codec() {
+                Some(codec) => self.encode_packet_with(codec),
+                None => self.encode_to_vec(),
+            }
+        }
         fn decode_packet(data: &[u8]) -> Result<Self>
+        where
+            Self: Default,
+        {
+            Self::decode_packet_with_limits(data, &Default::default())
+        }
+        fn decode_packet_with_limits(data: &[u8], limits: &DecompressLimits) -> Result<Self>
         where
             Self: Default,
         {
             let mut p = RpcPacket::decode(Cursor::new(data))?;
             if p.is_compressed {
-                match p.data {
-                    Some(rpc_packet::Data::Request(ref mut r)) => {
-                        if let Ok(decompressed) = decompress(&mut r.param) {
-                            r.param = decompressed;
-                            p.is_compressed = false;
-                        }
-                    }
-                    Some(rpc_packet::Data::Response(ref mut r)) => {
-                        if let Ok(decompressed) = decompress(&mut r.param) {
-                            r.param = decompressed;
-                            p.is_compressed = false;
-                        }
-                    }
-                    _ => {}
+                let codec = p.codec().to_codec().unwrap_or(Codec::Zlib);
+                let param = match p.data {
+                    Some(rpc_packet::Data::Request(ref mut r)) => Some(&mut r.param),
+                    Some(rpc_packet::Data::Response(ref mut r)) => Some(&mut r.param),
+                    _ => None,
+                };
+                if let Some(param) = param {
+                    *param = decompress_with_limits(codec, param, limits)?;
+                    p.is_compressed = false;
+                    p.set_codec(RpcCodec::None);
                 }
             }
             Ok(p)
         }
     }
+
+    pub trait IRpcCodec {
+        /// None for RPC_CODEC_NONE.
+        fn to_codec(&self) -> Option<Codec>;
+        fn from_codec(codec: Codec) -> Self;
+        /// Whether packets compressed with this codec can be decoded here.
+        fn is_available(&self) -> bool;
+    }
+    impl IRpcCodec for RpcCodec {
+        fn to_codec(&self) -> Option<Codec> {
+            match self {
+                RpcCodec::None => None,
+                RpcCodec::Zlib => Some(Codec::Zlib),
+                RpcCodec::Zstd => Some(Codec::Zstd),
+                RpcCodec::Brotli => Some(Codec::Brotli),
+                RpcCodec::Lz4 => Some(Codec::Lz4),
+            }
+        }
+        fn from_codec(codec: Codec) -> Self {
+            match codec {
+                Codec::Zlib => RpcCodec::Zlib,
+                Codec::Zstd => RpcCodec::Zstd,
+                Codec::Brotli => RpcCodec::Brotli,
+                Codec::Lz4 => RpcCodec::Lz4,
+            }
+        }
+        fn is_available(&self) -> bool {
+            match self.to_codec() {
+                Some(codec) => codec.is_available(),
+                None => true,
+            }
+        }
+    }
+
+    pub trait IRpcError {
+        fn new(rpc_id: u32, code: RpcErrorCode, message: impl Into<String>) -> Self;
+        fn with_nested_rpc_id(self, nested_rpc_id: u32) -> Self;
+        fn with_request_id(self, request_id: u32) -> Self;
+        /// Whether this is the answer to a request sent with `rpc_id` (and `nested_rpc_id`).
+        fn is_for(&self, rpc_id: u32, nested_rpc_id: Option<u32>) -> bool;
+    }
+    impl IRpcError for RpcError {
+        fn new(rpc_id: u32, code: RpcErrorCode, message: impl Into<String>) -> Self {
+            RpcError {
+                rpc_id,
+                nested_rpc_id: None,
+                code: code.into(),
+                message: message.into(),
+                request_id: 0,
+            }
+        }
+        fn with_nested_rpc_id(self, nested_rpc_id: u32) -> Self {
+            RpcError {
+                nested_rpc_id: Some(nested_rpc_id),
+                ..self
+            }
+        }
+        fn with_request_id(self, request_id: u32) -> Self {
+            RpcError { request_id, ..self }
+        }
+        fn is_for(&self, rpc_id: u32, nested_rpc_id: Option<u32>) -> bool {
+            self.rpc_id == rpc_id && self.nested_rpc_id == nested_rpc_id
+        }
+    }
 }
 pub mod signaling {
     #![allow(clippy::all)]
@@ -109,6 +218,7 @@ pub mod signaling {
 }
 
 mod routing_info_ex;
+pub mod service;
 
 pub mod swarm {
     #![allow(clippy::all)]
@@ -191,6 +301,8 @@ pub mod swarm {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use prost::Message;
+    use std::io::Cursor;
 
     #[test]
     fn test_position3d() {
@@ -210,7 +322,7 @@ mod tests {
         let mut v0: RpcPacket = Default::default();
         v0.set_request(RpcRequest {
             rpc_id: 1,
-            param: Default::default(),
+            ..Default::default()
         });
         let Some(rpc_packet::Data::Request(ref req)) = v0.data else {
             unreachable!();
@@ -219,7 +331,7 @@ mod tests {
 
         v0.set_response(RpcResponse {
             rpc_id: 2,
-            param: Default::default(),
+            ..Default::default()
         });
         let Some(rpc_packet::Data::Response(ref req)) = v0.data else {
             unreachable!();
@@ -234,6 +346,7 @@ mod tests {
             let r = RpcRequest {
                 rpc_id: 1,
                 param: [1u8; 1024].to_vec(),
+                ..Default::default()
             };
             p.set_request(r.clone());
             let bin = p.encode_packet();
@@ -248,6 +361,7 @@ mod tests {
             let r = RpcResponse {
                 rpc_id: 1,
                 param: [1u8; 1024].to_vec(),
+                ..Default::default()
             };
             p.set_response(r.clone());
             let bin = p.encode_packet();
@@ -259,6 +373,98 @@ mod tests {
         }
     }
     #[test]
+    fn test_rpcpacket_codec() {
+        use rpc::*;
+        let r = RpcResponse {
+            rpc_id: 1,
+            param: [1u8; 1024].to_vec(),
+            ..Default::default()
+        };
+        for codec in Codec::ALL {
+            let mut p: RpcPacket = Default::default();
+            p.set_response(r.clone());
+            let bin = p.encode_packet_with(codec);
+            assert!(bin.len() < 1024);
+            let p0 = RpcPacket::decode(Cursor::new(&bin)).unwrap();
+            assert!(p0.is_compressed);
+            // zlib packets stay readable by clients that do not know RpcPacket.codec
+            assert_eq!(p0.codec().to_codec().is_none(), codec == Codec::Zlib);
+
+            let p = RpcPacket::decode_packet(&bin).unwrap();
+            assert!(!p.is_compressed);
+            assert!(matches!(p.data.unwrap(), rpc_packet::Data::Response(r0) if r0 == r));
+        }
+
+        let mut p: RpcPacket = Default::default();
+        p.set_response(r.clone());
+        let bin = p.encode_packet_as(RpcCodec::None);
+        let p = RpcPacket::decode_packet(&bin).unwrap();
+        assert!(!p.is_compressed);
+        assert!(matches!(p.data.unwrap(), rpc_packet::Data::Response(r0) if r0 == r));
+    }
+    #[test]
+    fn test_rpcpacket_decompress_limits() {
+        use rpc::*;
+        let mut p: RpcPacket = Default::default();
+        p.set_request(RpcRequest {
+            rpc_id: 1,
+            param: vec![0u8; 1024 * 1024],
+            ..Default::default()
+        });
+        let bin = p.encode_packet();
+        let e = RpcPacket::decode_packet(&bin).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<DecompressError>(),
+            Some(DecompressError::RatioExceeded(_))
+        ));
+
+        let limits = DecompressLimits {
+            max_size: 1024,
+            max_ratio: usize::MAX,
+        };
+        let e = RpcPacket::decode_packet_with_limits(&bin, &limits).unwrap_err();
+        assert!(matches!(
+            e.downcast_ref::<DecompressError>(),
+            Some(DecompressError::TooLarge(1024))
+        ));
+
+        let limits = DecompressLimits {
+            max_size: 1024 * 1024,
+            max_ratio: usize::MAX,
+        };
+        assert!(RpcPacket::decode_packet_with_limits(&bin, &limits).is_ok());
+    }
+    #[test]
+    fn test_rpcpacket_error() {
+        use rpc::*;
+        let mut p: RpcPacket = Default::default();
+        assert!(p.get_error().is_none());
+        assert_eq!(p.get_rpc_id(), None);
+
+        p.set_request(RpcRequest {
+            rpc_id: 1,
+            ..Default::default()
+        });
+        assert!(p.get_error().is_none());
+        assert_eq!(p.get_rpc_id(), Some(1));
+
+        p.set_error(
+            RpcError::new(1, RpcErrorCode::UnknownRpcId, "unknown")
+                .with_nested_rpc_id(3)
+                .with_request_id(7),
+        );
+        let bin = p.encode_packet();
+        let p = RpcPacket::decode_packet(&bin).unwrap();
+        assert_eq!(p.get_rpc_id(), Some(1));
+        let e = p.get_error().unwrap();
+        assert_eq!(e.code(), RpcErrorCode::UnknownRpcId);
+        assert_eq!(e.message, "unknown");
+        assert_eq!(e.request_id, 7);
+        assert!(e.is_for(1, Some(3)));
+        assert!(!e.is_for(1, None));
+        assert!(!e.is_for(2, Some(3)));
+    }
+    #[test]
     fn test_signaling() {
         use signaling::*;
         let mut v: TransferPayload = Default::default();
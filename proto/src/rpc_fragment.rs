@@ -0,0 +1,329 @@
+use crate::rpc::*;
+use anyhow::{anyhow, Result};
+#[allow(unused_imports)]
+use log::{debug, error, info, trace, warn};
+use prost::Message;
+use std::collections::HashMap;
+
+/// Safe size for a single SCTP message on every browser.
+pub const DEFAULT_MAX_FRAGMENT_SIZE: usize = 16 * 1024;
+pub const MIN_FRAGMENT_SIZE: usize = 1024;
+// message_id, index, total and the tags of RpcPacket/RpcFragment
+const FRAGMENT_OVERHEAD: usize = 32;
+
+pub trait IRpcCapabilities {
+    /// Fragment size to use when sending to the peer that sent these capabilities.
+    fn get_fragment_size(&self) -> Option<usize>;
+    /// The first codec in `codecs` that is available here. RPC_CODEC_NONE is always available.
+    fn select_codec(&self) -> Option<RpcCodec>;
+}
+impl IRpcCapabilities for RpcCapabilities {
+    fn get_fragment_size(&self) -> Option<usize> {
+        if !self.fragmentation {
+            return None;
+        }
+        if self.max_fragment_size == 0 {
+            return Some(DEFAULT_MAX_FRAGMENT_SIZE);
+        }
+        Some((self.max_fragment_size as usize).clamp(MIN_FRAGMENT_SIZE, u16::MAX as usize))
+    }
+    fn select_codec(&self) -> Option<RpcCodec> {
+        self.codecs().find(|v| v.is_available())
+    }
+}
+
+/// Splits an encoded RpcPacket into encoded RpcPackets of RpcFragment.
+/// Returns None if `data` fits in one fragment.
+pub fn split_packet(
+    data: &[u8],
+    message_id: u32,
+    max_fragment_size: usize,
+) -> Option<Vec<Vec<u8>>> {
+    if data.len() <= max_fragment_size {
+        return None;
+    }
+    let chunk_size = max_fragment_size.saturating_sub(FRAGMENT_OVERHEAD).max(1);
+    let chunks: Vec<&[u8]> = data.chunks(chunk_size).collect();
+    let total = chunks.len() as u32;
+    Some(
+        chunks
+            .into_iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                RpcPacket {
+                    data: Some(rpc_packet::Data::Fragment(RpcFragment {
+                        message_id,
+                        index: index as u32,
+                        total,
+                        data: chunk.to_vec(),
+                    })),
+                    ..Default::default()
+                }
+                .encode_to_vec()
+            })
+            .collect(),
+    )
+}
+
+#[derive(Clone, Debug)]
+pub struct FragmentLimits {
+    /// Maximum size of a reassembled packet.
+    pub max_message_size: usize,
+    /// Maximum size of RpcFragment.data, the max_fragment_size sent in RpcCapabilities.
+    pub max_fragment_size: usize,
+    pub max_fragments: u32,
+    /// Maximum number of messages being reassembled at once.
+    pub max_pending_messages: usize,
+    pub timeout_msec: u64,
+}
+impl Default for FragmentLimits {
+    fn default() -> Self {
+        FragmentLimits {
+            max_message_size: 4 * 1024 * 1024,
+            max_fragment_size: DEFAULT_MAX_FRAGMENT_SIZE,
+            max_fragments: 1024,
+            max_pending_messages: 2,
+            timeout_msec: 30_000,
+        }
+    }
+}
+
+struct PendingMessage {
+    started: u64,
+    total: u32,
+    received: u32,
+    size: usize,
+    chunks: Vec<Option<Vec<u8>>>,
+}
+
+pub struct FragmentAssembler {
+    limits: FragmentLimits,
+    pending: HashMap<u32, PendingMessage>,
+}
+impl FragmentAssembler {
+    pub fn new(limits: FragmentLimits) -> Self {
+        FragmentAssembler {
+            limits,
+            pending: HashMap::new(),
+        }
+    }
+
+    /// Returns the encoded RpcPacket when all fragments of the message have arrived.
+    pub fn push(&mut self, fragment: RpcFragment, now_msec: u64) -> Result<Option<Vec<u8>>> {
+        self.remove_expired(now_msec);
+
+        let RpcFragment {
+            message_id,
+            index,
+            total,
+            data,
+        } = fragment;
+        if total == 0 || total > self.limits.max_fragments || index >= total {
+            self.pending.remove(&message_id);
+            return Err(anyhow!("bad fragment: {}/{}", index, total));
+        }
+        if data.len() > self.limits.max_fragment_size {
+            self.pending.remove(&message_id);
+            return Err(anyhow!("fragment too large: {}", data.len()));
+        }
+        if !self.pending.contains_key(&message_id)
+            && self.pending.len() >= self.limits.max_pending_messages
+        {
+            return Err(anyhow!("too many pending fragmented messages"));
+        }
+        let msg = self
+            .pending
+            .entry(message_id)
+            .or_insert_with(|| PendingMessage {
+                started: now_msec,
+                total,
+                received: 0,
+                size: 0,
+                chunks: vec![None; total as usize],
+            });
+        if msg.total != total {
+            self.pending.remove(&message_id);
+            return Err(anyhow!("fragment total mismatch"));
+        }
+        let chunk = &mut msg.chunks[index as usize];
+        if chunk.is_some() {
+            // duplicated
+            return Ok(None);
+        }
+        msg.size += data.len();
+        if msg.size > self.limits.max_message_size {
+            self.pending.remove(&message_id);
+            return Err(anyhow!("fragmented message too large"));
+        }
+        *chunk = Some(data);
+        msg.received += 1;
+        if msg.received < msg.total {
+            return Ok(None);
+        }
+
+        let Some(msg) = self.pending.remove(&message_id) else {
+            unreachable!();
+        };
+        let mut res = Vec::with_capacity(msg.size);
+        for chunk in msg.chunks.into_iter().flatten() {
+            res.extend_from_slice(&chunk);
+        }
+        Ok(Some(res))
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.len()
+    }
+
+    fn remove_expired(&mut self, now_msec: u64) {
+        let timeout_msec = self.limits.timeout_msec;
+        self.pending.retain(|message_id, v| {
+            let alive = now_msec < v.started + timeout_msec;
+            if !alive {
+                debug!("fragmented message timeout: {}", message_id);
+            }
+            alive
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_fragments(packets: Vec<Vec<u8>>) -> Vec<RpcFragment> {
+        packets
+            .into_iter()
+            .map(|v| {
+                let p = RpcPacket::decode_packet(&v).unwrap();
+                let Some(rpc_packet::Data::Fragment(f)) = p.data else {
+                    unreachable!();
+                };
+                f
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_split_and_assemble() {
+        let src: Vec<u8> = (0..10_000u32).map(|v| (v % 251) as u8).collect();
+        assert!(split_packet(&src, 1, src.len()).is_none());
+
+        let packets = split_packet(&src, 1, 1024).unwrap();
+        assert!(packets.len() > 1);
+        assert!(packets.iter().all(|v| v.len() <= 1024));
+
+        let mut fragments = to_fragments(packets);
+        fragments.reverse();
+        let mut a = FragmentAssembler::new(Default::default());
+        let last = fragments.pop().unwrap();
+        for f in fragments {
+            assert!(a.push(f.clone(), 0).unwrap().is_none());
+            // duplicated
+            assert!(a.push(f, 0).unwrap().is_none());
+        }
+        assert_eq!(a.pending_count(), 1);
+        assert_eq!(a.push(last, 0).unwrap().unwrap(), src);
+        assert_eq!(a.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_limits() {
+        let src = [1u8; 5000];
+        let fragments = to_fragments(split_packet(&src, 1, 1024).unwrap());
+
+        let mut a = FragmentAssembler::new(FragmentLimits {
+            max_message_size: 4000,
+            ..Default::default()
+        });
+        let res: Result<Vec<_>> = fragments.iter().map(|f| a.push(f.clone(), 0)).collect();
+        assert!(res.is_err());
+        assert_eq!(a.pending_count(), 0);
+
+        let mut a = FragmentAssembler::new(FragmentLimits {
+            max_fragments: 2,
+            ..Default::default()
+        });
+        assert!(a.push(fragments[0].clone(), 0).is_err());
+
+        let mut a = FragmentAssembler::new(FragmentLimits {
+            max_pending_messages: 1,
+            ..Default::default()
+        });
+        assert!(a.push(fragments[0].clone(), 0).unwrap().is_none());
+        let mut other = fragments[0].clone();
+        other.message_id = 2;
+        assert!(a.push(other, 0).is_err());
+
+        let mut bad = fragments[0].clone();
+        bad.index = bad.total;
+        assert!(a.push(bad, 0).is_err());
+
+        // larger than the negotiated size
+        let mut a = FragmentAssembler::new(FragmentLimits {
+            max_fragment_size: 1024,
+            ..Default::default()
+        });
+        assert!(a.push(fragments[0].clone(), 0).unwrap().is_none());
+        let mut large = fragments[1].clone();
+        large.data = vec![1; 1025];
+        assert!(a.push(large, 0).is_err());
+        assert_eq!(a.pending_count(), 0);
+    }
+
+    #[test]
+    fn test_timeout() {
+        let src = [1u8; 5000];
+        let fragments = to_fragments(split_packet(&src, 1, 1024).unwrap());
+        let mut a = FragmentAssembler::new(FragmentLimits {
+            timeout_msec: 100,
+            ..Default::default()
+        });
+        assert!(a.push(fragments[0].clone(), 1000).unwrap().is_none());
+        for f in fragments.iter().skip(1).take(fragments.len() - 2) {
+            assert!(a.push(f.clone(), 1050).unwrap().is_none());
+        }
+        // the first fragment has expired
+        assert!(a
+            .push(fragments.last().unwrap().clone(), 1100)
+            .unwrap()
+            .is_none());
+        assert_eq!(a.pending_count(), 1);
+    }
+
+    #[test]
+    fn test_capabilities() {
+        let c = RpcCapabilities::default();
+        assert_eq!(c.get_fragment_size(), None);
+        let c = RpcCapabilities {
+            fragmentation: true,
+            max_fragment_size: 0,
+            ..Default::default()
+        };
+        assert_eq!(c.get_fragment_size(), Some(DEFAULT_MAX_FRAGMENT_SIZE));
+        let c = RpcCapabilities {
+            fragmentation: true,
+            max_fragment_size: 10,
+            ..Default::default()
+        };
+        assert_eq!(c.get_fragment_size(), Some(MIN_FRAGMENT_SIZE));
+        assert_eq!(c.select_codec(), None);
+
+        let c = RpcCapabilities {
+            codecs: vec![
+                RpcCodec::None as i32,
+                100,
+                RpcCodec::Lz4 as i32,
+                RpcCodec::Zlib as i32,
+            ],
+            ..Default::default()
+        };
+        assert_eq!(c.select_codec(), Some(RpcCodec::None));
+
+        let c = RpcCapabilities {
+            codecs: vec![100, RpcCodec::Lz4 as i32, RpcCodec::Zlib as i32],
+            ..Default::default()
+        };
+        assert_eq!(c.select_codec(), Some(RpcCodec::Lz4));
+    }
+}
@@ -0,0 +1,174 @@
+//! Shared by the code generated from the proto services (see build.rs).
+//!
+//! For each service without streaming methods, a module named after the service
+//! (ex: `swarm::swarm_node_service`) has:
+//! - `RPC_ID_*` and `METHODS`, the rpc ids in the order of the service.
+//!   `// @rpc_id N` in the comment of a method sets its id, and the following methods count from it.
+//! - a type implementing [`RpcMethod`] for each method.
+//! - `Server`, the async trait with the typed request and response.
+//! - `dispatch`, `dispatch_rpc_request` and `dispatch_swarm_packet`.
+//! - `Service`, which wraps a `Server` as an [`RpcService`].
+use prost::Message;
+
+pub trait RpcMethod {
+    const RPC_ID: u32;
+    /// snake_case name of the method.
+    const NAME: &'static str;
+    type Request: Message + Default + Send + 'static;
+    type Response: Message + Default + Send + 'static;
+}
+
+/// A generated `Server` with its service erased, to be registered by its `METHODS`.
+#[async_trait::async_trait]
+pub trait RpcService: Send + Sync {
+    type Context: Send;
+    type Error: Send;
+    /// Rpc ids and method names of the service.
+    fn methods(&self) -> &'static [(u32, &'static str)];
+    /// None if the id is unknown.
+    async fn dispatch(
+        &self,
+        ctx: Self::Context,
+        rpc_id: u32,
+        param: &[u8],
+    ) -> Option<Result<Vec<u8>, Self::Error>>;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swarm::*;
+
+    struct Hub;
+    #[async_trait::async_trait]
+    impl swarm_node_service::Server for Hub {
+        type Context = u32;
+        type Error = prost::DecodeError;
+        async fn transfer(
+            &self,
+            ctx: u32,
+            req: TransferRequest,
+        ) -> Result<TransferResponse, Self::Error> {
+            Ok(TransferResponse {
+                result: ctx == req.ttl,
+                dest_session_id: req.to_session_id,
+                ..Default::default()
+            })
+        }
+        async fn exchange_routing_info(
+            &self,
+            _ctx: u32,
+            req: RoutingInfo,
+        ) -> Result<RoutingInfo, Self::Error> {
+            Ok(req)
+        }
+        async fn multicast(
+            &self,
+            _ctx: u32,
+            _req: MulticastRequest,
+        ) -> Result<MulticastResponse, Self::Error> {
+            Ok(Default::default())
+        }
+        async fn subscribe(
+            &self,
+            _ctx: u32,
+            _req: SubscribeRequest,
+        ) -> Result<SubscribeResponse, Self::Error> {
+            Ok(Default::default())
+        }
+    }
+
+    #[test]
+    fn test_rpc_ids() {
+        use crate::rpc::rpc_service;
+        assert_eq!(
+            rpc_service::METHODS,
+            &[(0, "keep_alive"), (1, "swarm"), (2, "negotiate")]
+        );
+        assert_eq!(
+            swarm_node_service::METHODS,
+            &[
+                (1, "transfer"),
+                (2, "exchange_routing_info"),
+                (3, "multicast"),
+                (4, "subscribe")
+            ]
+        );
+        assert_eq!(swarm_hub_service::RPC_ID_MEMBERSHIP_EVENTS, 5);
+        assert_eq!(swarm_hub_service::RPC_ID_MERGE_SUGGESTION, 7);
+        assert_eq!(
+            <swarm_node_service::Transfer as RpcMethod>::RPC_ID,
+            swarm_node_service::RPC_ID_TRANSFER
+        );
+        assert_eq!(
+            <swarm_node_service::Transfer as RpcMethod>::NAME,
+            "transfer"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_dispatch() {
+        let req = TransferRequest {
+            to_session_id: vec![1; 32],
+            ttl: 3,
+            ..Default::default()
+        };
+        let packet = SwarmPacket {
+            data: Some(swarm_packet::Data::Request(SwarmRequest {
+                rpc_id: swarm_node_service::RPC_ID_TRANSFER,
+                param: req.encode_to_vec(),
+            })),
+        };
+        let res = swarm_node_service::dispatch_swarm_packet(&Hub, 3, packet)
+            .await
+            .unwrap()
+            .unwrap();
+        let Some(swarm_packet::Data::Response(res)) = res.data else {
+            panic!("not a response");
+        };
+        assert_eq!(res.rpc_id, swarm_node_service::RPC_ID_TRANSFER);
+        let res = TransferResponse::decode(res.param.as_slice()).unwrap();
+        assert!(res.result);
+        assert_eq!(res.dest_session_id, vec![1; 32]);
+
+        // unknown id
+        assert!(swarm_node_service::dispatch(&Hub, 0, 100, &[])
+            .await
+            .is_none());
+        // bad param
+        assert!(swarm_node_service::dispatch(
+            &Hub,
+            0,
+            swarm_node_service::RPC_ID_TRANSFER,
+            &[0xff]
+        )
+        .await
+        .unwrap()
+        .is_err());
+        let res = swarm_node_service::dispatch_rpc_request(
+            &Hub,
+            0,
+            crate::rpc::RpcRequest {
+                rpc_id: swarm_node_service::RPC_ID_SUBSCRIBE,
+                param: Vec::new(),
+                request_id: 42,
+            },
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(res.rpc_id, swarm_node_service::RPC_ID_SUBSCRIBE);
+        assert_eq!(res.request_id, 42);
+
+        // through the erased service
+        let service = swarm_node_service::Service(std::sync::Arc::new(Hub));
+        assert_eq!(service.methods(), swarm_node_service::METHODS);
+        assert!(service.dispatch(0, 100, &[]).await.is_none());
+        let res = service
+            .dispatch(3, swarm_node_service::RPC_ID_TRANSFER, &req.encode_to_vec())
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(TransferResponse::decode(res.as_slice()).unwrap().result);
+    }
+}